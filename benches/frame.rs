@@ -0,0 +1,71 @@
+// how much CPU time a frame and a sprite take, run with `cargo bench --bench frame`. Frames run one after the other
+// on this thread with the platform's end of the channels emptied in between, so only the emulator is timed.
+#![allow(clippy::needless_return)]
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use chip8_rust::{create_contexts, load_rom, Cpu, Framebuffer, Keypad, Quirks, Random, Rom, Runnable, Screen};
+
+struct Fixed;
+
+impl Random for Fixed {
+    fn random(&mut self) -> u8 {
+        return 0x5a;
+    }
+}
+
+struct NoKeys;
+
+impl Keypad for NoKeys {
+    fn keys(&mut self) -> Option<[u8; 16]> {
+        return None;
+    }
+
+    fn released(&mut self) -> Option<u8> {
+        return None;
+    }
+}
+
+// takes the rows that changed like a platform would, without drawing them anywhere
+struct Discard;
+
+impl Screen for Discard {
+    fn draw(&mut self, screen: &Framebuffer, rows: u64) {
+        black_box((screen, rows));
+    }
+}
+
+// runs f a tenth of the iterations to warm up, then prints the average time of the rest
+fn time(name: &str, iterations: u32, mut f: impl FnMut()) {
+    for _ in 0..iterations / 10 {
+        f();
+    }
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let nanos = start.elapsed().as_nanos() / u128::from(iterations);
+    println!("{:<24} {:>8} ns", name, nanos);
+}
+
+fn main() {
+    // a headless frame at the default speed, with the screen and status sent to a platform that drops them
+    let tetris = std::fs::read("test/tetris.ch8").expect("run from the crate root");
+    let mut program = load_rom(Rom::from_bytes(&tetris).unwrap()).unwrap();
+    program.realtime = false;
+    let (platform_context, cpu_context) = create_contexts();
+    time("tetris.ch8 frame", 100_000, || {
+        black_box(program.run_frame(&cpu_context));
+        while platform_context.events.try_recv().is_ok() {}
+    });
+
+    // D01F draws the font's 0, 1 and 2 as one 15 row sprite at 0,0 and 1202 goes back to it. The sprite XORs
+    // itself away every other time, so every draw changes the screen.
+    let mut cpu = Cpu::new(Quirks::default());
+    cpu.load(&[0xa0, 0x50, 0xd0, 0x1f, 0x12, 0x02]).unwrap();
+    time("15 row DXYN and a jump", 1_000_000, || {
+        black_box(cpu.step(&mut Fixed, &mut NoKeys, &mut Discard));
+        black_box(cpu.step(&mut Fixed, &mut NoKeys, &mut Discard));
+    });
+}
@@ -0,0 +1,18 @@
+// cargo +nightly fuzz run instructions
+//
+// runs whatever libFuzzer comes up with through the interpreter and the reference model in
+// tests/reference, a difference is minimised to the smallest failing program before it is reported
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../tests/reference/mod.rs"]
+mod reference;
+
+fuzz_target!(|data: &[u8]| {
+    let case = reference::Case::from_bytes(data);
+    if reference::run(&case).is_err() {
+        let mismatch = reference::run(&reference::minimise(case)).unwrap_err();
+        panic!("\n{}", mismatch);
+    }
+});
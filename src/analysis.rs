@@ -0,0 +1,252 @@
+use crate::cpu::{IOpCode, OpCode};
+use crate::disassembler::{self, Line, LineKind};
+use crate::quirks::QuirkProfile;
+use crate::rom::Rom;
+
+// how far past FX55/FX65 we look for an instruction that depends on where I was left
+const MEMORY_LOOKAHEAD: usize = 8;
+
+// the platform a ROM was most likely written for, worked out from the instructions it can reach
+#[derive(Clone, Debug)]
+pub struct Suggestion {
+    // CHIP-8 database platform id
+    pub platform: &'static str,
+    pub profile: QuirkProfile,
+    // 0 to 1, grows with every instruction that points at the platform
+    pub confidence: f32,
+    // one line per instruction that was taken into account
+    pub evidence: Vec<String>,
+}
+
+struct Evidence {
+    lines: Vec<String>,
+    // probability that none of the evidence so far is a coincidence
+    doubt: f32,
+}
+
+impl Evidence {
+    fn new() -> Self {
+        return Evidence {
+            lines: Vec::new(),
+            doubt: 1.0,
+        };
+    }
+
+    fn add(&mut self, line: &Line, weight: f32, reason: &str) {
+        self.lines.push(format!("{:04x} {:<24} {}", line.address, line.text, reason));
+        self.doubt *= 1.0 - weight;
+    }
+
+    // takes in the evidence for a platform this one builds on
+    fn merge(&mut self, other: Evidence) {
+        self.lines.extend(other.lines);
+        self.doubt *= other.doubt;
+    }
+
+    fn confidence(&self) -> f32 {
+        return 1.0 - self.doubt;
+    }
+}
+
+fn opcode(line: &Line) -> OpCode {
+    return OpCode {
+        high: line.bytes[0],
+        low: line.bytes[1],
+    };
+}
+
+// instructions that only exist on XO-CHIP and how strongly they point at it
+fn xochip_weight(code: &OpCode) -> Option<f32> {
+    match (code.nib1(), code.nib4(), code.low) {
+        (0x0, _, _) if code.combine() & 0xfff0 == 0x00d0 => Some(0.6),
+        (0x5, 0x2, _) | (0x5, 0x3, _) => Some(0.6),
+        (0xf, _, 0x00) if code.combine() == 0xf000 => Some(0.8),
+        (0xf, _, 0x01) | (0xf, _, 0x3a) => Some(0.6),
+        (0xf, _, 0x02) if code.nib2() == 0 => Some(0.6),
+        _ => None,
+    }
+}
+
+// instructions that were added by SUPER-CHIP, XO-CHIP supports these too
+fn schip_weight(code: &OpCode) -> Option<f32> {
+    match (code.nib1(), code.low) {
+        (0x0, 0xfe) | (0x0, 0xff) => Some(0.7),
+        (0x0, 0xfb) | (0x0, 0xfc) | (0x0, 0xfd) => Some(0.5),
+        (0x0, _) if code.combine() & 0xfff0 == 0x00c0 => Some(0.5),
+        (0xf, 0x30) | (0xf, 0x75) | (0xf, 0x85) => Some(0.5),
+        // a 16x16 sprite, on the VIP this would draw nothing which is unlikely to be intended
+        (0xd, _) if code.nib4() == 0 => Some(0.3),
+        _ => None,
+    }
+}
+
+// true if the instruction reads or writes memory through I
+fn uses_i(code: &OpCode) -> bool {
+    return matches!(
+        (code.nib1(), code.low),
+        (0xd, _) | (0xf, 0x1e) | (0xf, 0x33) | (0xf, 0x55) | (0xf, 0x65)
+    );
+}
+
+// true if using I after `previous` only makes sense when I was advanced. Drawing or stepping I
+// continues from where the registers ended, as does another store or load in the same direction.
+// Reading back what was just stored (or the other way around) expects I to be unchanged
+fn continues_after(previous: &OpCode, code: &OpCode) -> bool {
+    match (code.nib1(), code.low) {
+        (0xd, _) | (0xf, 0x1e) => true,
+        (0xf, 0x55) | (0xf, 0x65) => code.low == previous.low,
+        _ => false,
+    }
+}
+
+// true if the instruction points I somewhere new
+fn sets_i(code: &OpCode) -> bool {
+    return matches!((code.nib1(), code.low), (0xa, _) | (0xf, 0x00) | (0xf, 0x29) | (0xf, 0x30));
+}
+
+// true if execution doesn't simply carry on with the next line
+fn leaves_block(code: &OpCode) -> bool {
+    match code.nib1() {
+        0x0 => code.low == 0xee || code.low == 0xfd,
+        0x1 | 0x2 | 0xb => true,
+        _ => false,
+    }
+}
+
+// FX55/FX65 followed by a use of I without I being set again only works if I was advanced past the
+// registers, which is what the VIP interpreter did
+fn relies_on_memory_increment(lines: &[Line], index: usize) -> bool {
+    let previous = opcode(&lines[index]);
+    for line in lines.iter().skip(index + 1).take(MEMORY_LOOKAHEAD) {
+        if line.kind != LineKind::Code || line.label.is_some() {
+            return false;
+        }
+        let code = opcode(line);
+        if uses_i(&code) {
+            return continues_after(&previous, &code);
+        }
+        if sets_i(&code) || leaves_block(&code) {
+            return false;
+        }
+    }
+    return false;
+}
+
+// looks for instructions that only make sense on particular platforms, None means the ROM looks
+// like it runs anywhere
+pub fn analyse(rom: &Rom) -> Option<Suggestion> {
+    let lines = disassembler::disassemble(rom);
+    let mut xochip = Evidence::new();
+    let mut schip = Evidence::new();
+    let mut vip = Evidence::new();
+
+    for (index, line) in lines.iter().enumerate() {
+        if line.kind != LineKind::Code {
+            continue;
+        }
+        let code = opcode(line);
+
+        if let Some(weight) = xochip_weight(&code) {
+            xochip.add(line, weight, "XO-CHIP instruction");
+        } else if let Some(weight) = schip_weight(&code) {
+            schip.add(line, weight, "SUPER-CHIP instruction");
+        } else if code.nib1() == 0xf
+            && (code.low == 0x55 || code.low == 0x65)
+            && relies_on_memory_increment(&lines, index)
+        {
+            vip.add(line, 0.4, "uses I after it was advanced");
+        }
+    }
+
+    // XO-CHIP is a superset of SUPER-CHIP so its own instructions decide it, and the SUPER-CHIP ones
+    // back it up
+    let (platform, profile, evidence) = if !xochip.lines.is_empty() {
+        xochip.merge(schip);
+        ("xochip", QuirkProfile::XoChip, xochip)
+    } else if !schip.lines.is_empty() {
+        ("superchip", QuirkProfile::Schip, schip)
+    } else if !vip.lines.is_empty() {
+        ("originalChip8", QuirkProfile::Vip, vip)
+    } else {
+        return None;
+    };
+
+    return Some(Suggestion {
+        platform,
+        profile,
+        confidence: evidence.confidence(),
+        evidence: evidence.lines,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analyse_program(opcodes: &[u16]) -> Option<Suggestion> {
+        let bytes: Vec<u8> = opcodes.iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+        return analyse(&Rom::from_bytes(&bytes).unwrap());
+    }
+
+    #[test]
+    fn plain_chip8_runs_anywhere() {
+        assert!(analyse_program(&[0x00e0, 0xa20a, 0xd015, 0x7001, 0x1202]).is_none());
+    }
+
+    #[test]
+    fn superchip_instructions() {
+        let suggestion = analyse_program(&[0x00ff, 0xf030, 0x00fd]).unwrap();
+        assert_eq!(suggestion.platform, "superchip");
+        assert_eq!(suggestion.profile, QuirkProfile::Schip);
+        assert_eq!(suggestion.evidence.len(), 3);
+        assert!((suggestion.confidence - (1.0 - 0.3 * 0.5 * 0.5)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn xochip_counts_the_superchip_evidence_too() {
+        let suggestion = analyse_program(&[0x00ff, 0xf000, 0x0300, 0x00fd]).unwrap();
+        assert_eq!(suggestion.platform, "xochip");
+        assert_eq!(suggestion.profile, QuirkProfile::XoChip);
+        assert_eq!(suggestion.evidence.len(), 3);
+        // every line that is listed counts towards the confidence
+        assert!((suggestion.confidence - (1.0 - 0.2 * 0.3 * 0.5)).abs() < 1e-6);
+        assert!(suggestion.evidence[0].contains("XO-CHIP"));
+    }
+
+    #[test]
+    fn using_i_after_a_store_means_the_vip() {
+        // save v3 then draw from where the registers ended
+        let suggestion = analyse_program(&[0xa300, 0xf355, 0xd015, 0x1206]).unwrap();
+        assert_eq!(suggestion.platform, "originalChip8");
+        assert!((suggestion.confidence - 0.4).abs() < 1e-6);
+        // a SUPER-CHIP instruction outweighs it
+        let suggestion = analyse_program(&[0xa300, 0xf355, 0xd015, 0x00fd]).unwrap();
+        assert_eq!(suggestion.platform, "superchip");
+    }
+
+    #[test]
+    fn setting_i_again_or_reading_back_means_nothing() {
+        assert!(analyse_program(&[0xa300, 0xf355, 0xa300, 0xd015, 0x1208]).is_none());
+        assert!(analyse_program(&[0xa300, 0xf355, 0xf365, 0x1206]).is_none());
+    }
+
+    #[test]
+    fn load_rom_runs_a_rom_the_way_it_was_written() {
+        use crate::cpu::{load_rom, Runnable};
+        // hires, a 16x16 sprite of the program itself, then loop
+        let rom = Rom::from_bytes(&[0x00, 0xff, 0xa2, 0x00, 0xd0, 0x10, 0x12, 0x06]).unwrap();
+        let mut program = load_rom(rom).unwrap();
+        assert_eq!(program.cpu.quirks, QuirkProfile::Schip.quirks());
+        program.realtime = false;
+        let (_platform, cpu) = crate::platform::create_contexts();
+        assert!(program.run_frame(&cpu));
+        assert_eq!(program.cpu.screen().width(), 128);
+        assert_eq!(program.cpu.screen().pixel(15, 0), 1);
+    }
+
+    #[test]
+    fn only_reachable_instructions_count() {
+        // 00FF after the loop is data
+        assert!(analyse_program(&[0x1200, 0x00ff]).is_none());
+    }
+}
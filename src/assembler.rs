@@ -0,0 +1,1035 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+
+// programs are loaded into RAM at this address
+const PROGRAM_START: usize = 0x200;
+const RAM_SIZE: usize = 4096;
+// how many macro expansions may nest inside one another, so a macro that expands itself stops
+const MACRO_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}:{}: {}", self.line, self.column, self.message);
+    }
+}
+
+impl Error for AsmError {}
+
+pub struct Assembly {
+    pub bytes: Vec<u8>,
+    pub symbols: BTreeMap<String, u16>,
+}
+
+impl Assembly {
+    // one `name 0xaddr` line per label, sorted by address
+    pub fn symbol_file(&self) -> String {
+        let mut symbols: Vec<(&String, &u16)> = self.symbols.iter().collect();
+        symbols.sort_by_key(|(name, address)| (**address, (*name).clone()));
+        let mut out = String::new();
+        for (name, address) in symbols {
+            out.push_str(&format!("{} 0x{:04x}\n", name, address));
+        }
+        return out;
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+    // how many macro expansions this token came out of
+    depth: usize,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+    calls: usize,
+}
+
+enum Operand {
+    Register(u8),
+    Value(u8),
+}
+
+// the comparisons allowed in `if`/`while`
+#[derive(Clone, Copy)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Key,
+    NoKey,
+}
+
+impl Comparison {
+    fn negate(self) -> Comparison {
+        match self {
+            Comparison::Eq => Comparison::Ne,
+            Comparison::Ne => Comparison::Eq,
+            Comparison::Lt => Comparison::Ge,
+            Comparison::Gt => Comparison::Le,
+            Comparison::Le => Comparison::Gt,
+            Comparison::Ge => Comparison::Lt,
+            Comparison::Key => Comparison::NoKey,
+            Comparison::NoKey => Comparison::Key,
+        }
+    }
+}
+
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (line_idx, line) in source.lines().enumerate() {
+        let mut start: Option<usize> = None;
+        let chars: Vec<char> = line.chars().collect();
+        for (col, &c) in chars.iter().enumerate() {
+            if start.is_none() && c == '#' {
+                // a comment runs to the end of the line
+                break;
+            }
+            if c.is_whitespace() {
+                if let Some(s) = start.take() {
+                    tokens.push_back(Token {
+                        text: chars[s..col].iter().collect(),
+                        line: line_idx + 1,
+                        column: s + 1,
+                        depth: 0,
+                    });
+                }
+            } else if start.is_none() {
+                start = Some(col);
+            }
+        }
+        if let Some(s) = start {
+            tokens.push_back(Token {
+                text: chars[s..].iter().collect(),
+                line: line_idx + 1,
+                column: s + 1,
+                depth: 0,
+            });
+        }
+    }
+    return tokens;
+}
+
+fn parse_number(text: &str) -> Option<f64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    } else if let Some(bin) = digits.strip_prefix("0b").or(digits.strip_prefix("0B")) {
+        i64::from_str_radix(bin, 2).ok()? as f64
+    } else if digits.chars().next().is_some_and(|c| c.is_ascii_digit()) {
+        digits.parse::<f64>().ok()?
+    } else {
+        return None;
+    };
+    return Some(if negative { -value } else { value });
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let lower = text.to_ascii_lowercase();
+    let digit = lower.strip_prefix('v')?;
+    if digit.len() != 1 {
+        return None;
+    }
+    return u8::from_str_radix(digit, 16).ok();
+}
+
+const RESERVED: [&str; 56] = [
+    ":", ":alias", ":const", ":calc", ":macro", ":byte", ":next", ":org", ":call", ":breakpoint",
+    "clear", "return", ";", "jump", "jump0", "bcd", "save", "load", "sprite", "delay", "buzzer",
+    "i", "loop", "again", "while", "if", "then", "begin", "else", "end", ":=", "+=", "-=", "=-",
+    "|=", "&=", "^=", ">>=", "<<=", "random", "hires", "lores", "exit", "scroll-down", "scroll-up",
+    "scroll-left", "scroll-right", "saveflags", "loadflags", "audio", "plane", "pitch", "hex",
+    "bighex", "long", "key",
+];
+
+struct Assembler {
+    tokens: VecDeque<Token>,
+    last: Token,
+    rom: Vec<u8>,
+    here: usize,
+    end: usize,
+    emitted: bool,
+    main_at_start: bool,
+    labels: BTreeMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    // address fields that refer to labels which weren't defined yet, true for the 16 bit field of `i := long`
+    forward: Vec<(usize, String, Token, bool)>,
+    // labels waiting to point at the second byte of the next instruction
+    next: Vec<String>,
+    // start address and pending `while` exits of each open loop
+    loops: Vec<(usize, Vec<usize>, Token)>,
+    // jumps of each open `if ... begin` waiting for their `else`/`end`
+    branches: Vec<(usize, Token)>,
+}
+
+impl Assembler {
+    fn new(source: &str) -> Self {
+        return Assembler {
+            tokens: tokenize(source),
+            last: Token {
+                text: String::new(),
+                line: 1,
+                column: 1,
+                depth: 0,
+            },
+            rom: vec![0u8; RAM_SIZE],
+            // 0x200 is reserved for a jump to main
+            here: PROGRAM_START + 2,
+            end: PROGRAM_START + 2,
+            emitted: false,
+            main_at_start: false,
+            labels: BTreeMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            forward: Vec::new(),
+            next: Vec::new(),
+            loops: Vec::new(),
+            branches: Vec::new(),
+        };
+    }
+
+    fn error_at<T>(&self, token: &Token, message: String) -> Result<T, AsmError> {
+        return Err(AsmError {
+            line: token.line,
+            column: token.column,
+            message,
+        });
+    }
+
+    fn next_token(&mut self) -> Result<Token, AsmError> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.last = token.clone();
+                return Ok(token);
+            }
+            None => {
+                let last = self.last.clone();
+                return self.error_at(&last, String::from("unexpected end of file"));
+            }
+        }
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        return self.tokens.front().is_some_and(|t| t.text == text);
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), AsmError> {
+        let token = self.next_token()?;
+        if token.text != text {
+            return self.error_at(&token, format!("expected '{}', found '{}'", text, token.text));
+        }
+        return Ok(());
+    }
+
+    fn byte(&mut self, value: u8) -> Result<(), AsmError> {
+        if self.here >= RAM_SIZE {
+            let last = self.last.clone();
+            return self.error_at(&last, String::from("program does not fit in 4096 bytes of RAM"));
+        }
+        self.rom[self.here] = value;
+        self.here += 1;
+        self.end = self.end.max(self.here);
+        self.emitted = true;
+        return Ok(());
+    }
+
+    fn inst(&mut self, high: u8, low: u8) -> Result<(), AsmError> {
+        for name in std::mem::take(&mut self.next) {
+            self.labels.insert(name, (self.here + 1) as u16);
+        }
+        self.byte(high)?;
+        return self.byte(low);
+    }
+
+    fn patch_address(&mut self, at: usize, address: usize) {
+        self.rom[at] = (self.rom[at] & 0xf0) | ((address >> 8) as u8 & 0x0f);
+        self.rom[at + 1] = (address & 0xff) as u8;
+    }
+
+    fn identifier(&mut self) -> Result<Token, AsmError> {
+        let token = self.next_token()?;
+        if parse_number(&token.text).is_some()
+            || parse_register(&token.text).is_some()
+            || RESERVED.contains(&token.text.as_str())
+        {
+            return self.error_at(&token, format!("'{}' is not a valid name", token.text));
+        }
+        return Ok(token);
+    }
+
+    fn register(&mut self) -> Result<u8, AsmError> {
+        let token = self.next_token()?;
+        if let Some(reg) = parse_register(&token.text) {
+            return Ok(reg);
+        }
+        if let Some(reg) = self.aliases.get(&token.text) {
+            return Ok(*reg);
+        }
+        return self.error_at(&token, format!("expected a register, found '{}'", token.text));
+    }
+
+    fn is_register(&self) -> bool {
+        return self.tokens.front().is_some_and(|t| {
+            parse_register(&t.text).is_some() || self.aliases.contains_key(&t.text)
+        });
+    }
+
+    fn lookup(&self, name: &str) -> Option<f64> {
+        if let Some(value) = self.constants.get(name) {
+            return Some(*value);
+        }
+        return self.labels.get(name).map(|address| f64::from(*address));
+    }
+
+    // a number, constant, defined label or `{ calc }` expression
+    fn value(&mut self) -> Result<(f64, Token), AsmError> {
+        let token = self.next_token()?;
+        if token.text == "{" {
+            let value = self.calc_block()?;
+            return Ok((value, token));
+        }
+        if let Some(value) = parse_number(&token.text) {
+            return Ok((value, token));
+        }
+        if let Some(value) = self.lookup(&token.text) {
+            return Ok((value, token));
+        }
+        return self.error_at(&token, format!("undefined name '{}'", token.text));
+    }
+
+    fn ranged(&self, value: f64, token: &Token, min: f64, max: f64) -> Result<u16, AsmError> {
+        if value.fract() != 0.0 || value < min || value > max {
+            return self.error_at(token, format!("value {} is out of range {}..{}", value, min, max));
+        }
+        return Ok((value as i64 & 0xffff) as u16);
+    }
+
+    fn byte_value(&mut self) -> Result<u8, AsmError> {
+        let (value, token) = self.value()?;
+        return Ok((self.ranged(value, &token, -128.0, 255.0)? & 0xff) as u8);
+    }
+
+    fn nibble_value(&mut self) -> Result<u8, AsmError> {
+        let (value, token) = self.value()?;
+        return Ok(self.ranged(value, &token, 0.0, 15.0)? as u8);
+    }
+
+    // records the next operand as a forward reference to be patched at `at` if it names an unknown label
+    fn forward_reference(&mut self, at: usize, long: bool) -> Result<bool, AsmError> {
+        let token = self.tokens.front().cloned();
+        if let Some(token) = token {
+            let is_forward = token.text != "{"
+                && parse_number(&token.text).is_none()
+                && self.lookup(&token.text).is_none();
+            if is_forward {
+                let name = self.identifier()?;
+                self.forward.push((at, name.text.clone(), name, long));
+                return Ok(true);
+            }
+        }
+        return Ok(false);
+    }
+
+    // emits an instruction with a 12 bit address operand which may refer to a label defined later on
+    fn address_inst(&mut self, high_nibble: u8) -> Result<(), AsmError> {
+        if self.forward_reference(self.here, false)? {
+            return self.inst(high_nibble << 4, 0);
+        }
+        let (value, token) = self.value()?;
+        let address = self.ranged(value, &token, 0.0, 4095.0)?;
+        return self.inst((high_nibble << 4) | (address >> 8) as u8, (address & 0xff) as u8);
+    }
+
+    // emits the XO-CHIP `i := long NNNN` with its 16 bit address in the 2 bytes after F000
+    fn long_address_inst(&mut self) -> Result<(), AsmError> {
+        if self.forward_reference(self.here + 2, true)? {
+            self.inst(0xf0, 0x00)?;
+            return self.inst(0x00, 0x00);
+        }
+        let (value, token) = self.value()?;
+        let address = self.ranged(value, &token, 0.0, 65535.0)?;
+        self.inst(0xf0, 0x00)?;
+        return self.inst((address >> 8) as u8, (address & 0xff) as u8);
+    }
+
+    fn calc_block(&mut self) -> Result<f64, AsmError> {
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.next_token()?;
+            if token.text == "}" {
+                if depth == 0 {
+                    break;
+                }
+                depth -= 1;
+            } else if token.text == "{" {
+                depth += 1;
+            }
+            body.push(token);
+        }
+        let mut pos = 0;
+        let value = self.calc_expr(&body, &mut pos)?;
+        if pos < body.len() {
+            return self.error_at(&body[pos], format!("unexpected '{}' in expression", body[pos].text));
+        }
+        return Ok(value);
+    }
+
+    // binary operators have no precedence and are evaluated right to left, like Octo
+    fn calc_expr(&self, body: &[Token], pos: &mut usize) -> Result<f64, AsmError> {
+        let lhs = self.calc_term(body, pos)?;
+        if *pos >= body.len() || body[*pos].text == ")" {
+            return Ok(lhs);
+        }
+        let op = body[*pos].clone();
+        *pos += 1;
+        let rhs = self.calc_expr(body, pos)?;
+        let value = match op.text.as_str() {
+            "+" => lhs + rhs,
+            "-" => lhs - rhs,
+            "*" => lhs * rhs,
+            "/" => lhs / rhs,
+            "%" => lhs % rhs,
+            "&" => ((lhs as i64) & (rhs as i64)) as f64,
+            "|" => ((lhs as i64) | (rhs as i64)) as f64,
+            "^" => ((lhs as i64) ^ (rhs as i64)) as f64,
+            "<<" => ((lhs as i64) << (rhs as i64)) as f64,
+            ">>" => ((lhs as i64) >> (rhs as i64)) as f64,
+            "pow" => lhs.powf(rhs),
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            "<" => f64::from(u8::from(lhs < rhs)),
+            ">" => f64::from(u8::from(lhs > rhs)),
+            "<=" => f64::from(u8::from(lhs <= rhs)),
+            ">=" => f64::from(u8::from(lhs >= rhs)),
+            "==" => f64::from(u8::from(lhs == rhs)),
+            "!=" => f64::from(u8::from(lhs != rhs)),
+            _ => return self.error_at(&op, format!("unknown operator '{}'", op.text)),
+        };
+        return Ok(value);
+    }
+
+    fn calc_term(&self, body: &[Token], pos: &mut usize) -> Result<f64, AsmError> {
+        if *pos >= body.len() {
+            let last = body.last().unwrap_or(&self.last).clone();
+            return self.error_at(&last, String::from("incomplete expression"));
+        }
+        let token = body[*pos].clone();
+        *pos += 1;
+        let unary: Option<fn(f64) -> f64> = match token.text.as_str() {
+            "-" => Some(|v| -v),
+            "~" => Some(|v| !(v as i64) as f64),
+            "!" => Some(|v| f64::from(u8::from(v == 0.0))),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            "tan" => Some(f64::tan),
+            "exp" => Some(f64::exp),
+            "log" => Some(f64::ln),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "sign" => Some(f64::signum),
+            "ceil" => Some(f64::ceil),
+            "floor" => Some(f64::floor),
+            _ => None,
+        };
+        if let Some(f) = unary {
+            return Ok(f(self.calc_term(body, pos)?));
+        }
+        match token.text.as_str() {
+            "(" => {
+                let value = self.calc_expr(body, pos)?;
+                if *pos >= body.len() || body[*pos].text != ")" {
+                    return self.error_at(&token, String::from("unbalanced '('"));
+                }
+                *pos += 1;
+                return Ok(value);
+            }
+            "@" => {
+                let address = self.calc_term(body, pos)? as usize;
+                return Ok(f64::from(self.rom[address.min(RAM_SIZE - 1)]));
+            }
+            "HERE" => return Ok(self.here as f64),
+            "PI" => return Ok(std::f64::consts::PI),
+            "E" => return Ok(std::f64::consts::E),
+            _ => (),
+        }
+        if let Some(value) = parse_number(&token.text) {
+            return Ok(value);
+        }
+        if let Some(value) = self.lookup(&token.text) {
+            return Ok(value);
+        }
+        return self.error_at(&token, format!("undefined name '{}'", token.text));
+    }
+
+    fn comparison(&mut self) -> Result<(u8, Comparison, Option<Operand>), AsmError> {
+        let reg = self.register()?;
+        let token = self.next_token()?;
+        let comparison = match token.text.as_str() {
+            "==" => Comparison::Eq,
+            "!=" => Comparison::Ne,
+            "<" => Comparison::Lt,
+            ">" => Comparison::Gt,
+            "<=" => Comparison::Le,
+            ">=" => Comparison::Ge,
+            "key" => return Ok((reg, Comparison::Key, None)),
+            "-key" => return Ok((reg, Comparison::NoKey, None)),
+            _ => return self.error_at(&token, format!("expected a comparison, found '{}'", token.text)),
+        };
+        let operand = if self.is_register() {
+            Operand::Register(self.register()?)
+        } else {
+            Operand::Value(self.byte_value()?)
+        };
+        return Ok((reg, comparison, Some(operand)));
+    }
+
+    // emits code which skips the next instruction unless the comparison holds
+    fn skip_unless(&mut self, reg: u8, comparison: Comparison, operand: Option<Operand>) -> Result<(), AsmError> {
+        let x = reg;
+        let (load_high, load_low) = match operand {
+            Some(Operand::Register(y)) => (0x8f, y << 4),
+            Some(Operand::Value(n)) => (0x6f, n),
+            None => (0, 0),
+        };
+        match (comparison, operand) {
+            (Comparison::Key, _) => self.inst(0xe0 | x, 0xa1),
+            (Comparison::NoKey, _) => self.inst(0xe0 | x, 0x9e),
+            (Comparison::Eq, Some(Operand::Register(y))) => self.inst(0x90 | x, y << 4),
+            (Comparison::Eq, Some(Operand::Value(n))) => self.inst(0x40 | x, n),
+            (Comparison::Ne, Some(Operand::Register(y))) => self.inst(0x50 | x, y << 4),
+            (Comparison::Ne, Some(Operand::Value(n))) => self.inst(0x30 | x, n),
+            // the remaining comparisons are computed in vF
+            (Comparison::Gt, _) | (Comparison::Le, _) => {
+                self.inst(load_high, load_low)?;
+                self.inst(0x8f, (x << 4) | 0x05)?;
+                let flag = if let Comparison::Gt = comparison { 1 } else { 0 };
+                self.inst(0x3f, flag)
+            }
+            (Comparison::Lt, _) | (Comparison::Ge, _) => {
+                self.inst(load_high, load_low)?;
+                self.inst(0x8f, (x << 4) | 0x07)?;
+                let flag = if let Comparison::Lt = comparison { 1 } else { 0 };
+                self.inst(0x3f, flag)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn define_label(&mut self, name: Token) -> Result<(), AsmError> {
+        if self.labels.contains_key(&name.text) || self.constants.contains_key(&name.text) {
+            return self.error_at(&name, format!("'{}' is already defined", name.text));
+        }
+        if name.text == "main" && self.here == PROGRAM_START + 2 && !self.emitted {
+            // main comes first so it doesn't need the jump
+            self.here = PROGRAM_START;
+            self.end = PROGRAM_START;
+            self.main_at_start = true;
+            // nothing was emitted yet so any label declared before main is at main too
+            for address in self.labels.values_mut() {
+                *address = PROGRAM_START as u16;
+            }
+        }
+        self.labels.insert(name.text, self.here as u16);
+        return Ok(());
+    }
+
+    fn define_macro(&mut self) -> Result<(), AsmError> {
+        let name = self.identifier()?;
+        let mut params = Vec::new();
+        while !self.peek_is("{") {
+            params.push(self.identifier()?.text);
+        }
+        self.expect("{")?;
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.next_token()?;
+            if token.text == "}" {
+                if depth == 0 {
+                    break;
+                }
+                depth -= 1;
+            } else if token.text == "{" {
+                depth += 1;
+            }
+            body.push(token);
+        }
+        self.macros.insert(
+            name.text,
+            Macro {
+                params,
+                body,
+                calls: 0,
+            },
+        );
+        return Ok(());
+    }
+
+    fn expand_macro(&mut self, name: &Token) -> Result<(), AsmError> {
+        if name.depth >= MACRO_DEPTH {
+            return self.error_at(name, format!("macro '{}' nests more than {} deep", name.text, MACRO_DEPTH));
+        }
+        let param_count = self.macros[&name.text].params.len();
+        let mut args = HashMap::new();
+        for i in 0..param_count {
+            let arg = self.next_token()?;
+            args.insert(self.macros[&name.text].params[i].clone(), arg);
+        }
+        let mac = self.macros.get_mut(&name.text).unwrap();
+        let calls = mac.calls;
+        mac.calls += 1;
+        for token in mac.body.iter().rev() {
+            let mut expanded = match args.get(&token.text) {
+                Some(arg) => arg.clone(),
+                None => token.clone(),
+            };
+            if expanded.text == "CALLS" {
+                expanded.text = calls.to_string();
+            }
+            expanded.depth = name.depth + 1;
+            self.tokens.push_front(expanded);
+        }
+        return Ok(());
+    }
+
+    fn register_statement(&mut self, x: u8) -> Result<(), AsmError> {
+        let op = self.next_token()?;
+        let alu_op = match op.text.as_str() {
+            "|=" => Some(0x1),
+            "&=" => Some(0x2),
+            "^=" => Some(0x3),
+            "=-" => Some(0x7),
+            ">>=" => Some(0x6),
+            "<<=" => Some(0xe),
+            _ => None,
+        };
+        if let Some(n) = alu_op {
+            let y = self.register()?;
+            return self.inst(0x80 | x, (y << 4) | n);
+        }
+        match op.text.as_str() {
+            ":=" => {
+                if self.is_register() {
+                    let y = self.register()?;
+                    return self.inst(0x80 | x, y << 4);
+                }
+                if self.peek_is("random") {
+                    self.next_token()?;
+                    let mask = self.byte_value()?;
+                    return self.inst(0xc0 | x, mask);
+                }
+                if self.peek_is("key") {
+                    self.next_token()?;
+                    return self.inst(0xf0 | x, 0x0a);
+                }
+                if self.peek_is("delay") {
+                    self.next_token()?;
+                    return self.inst(0xf0 | x, 0x07);
+                }
+                let n = self.byte_value()?;
+                return self.inst(0x60 | x, n);
+            }
+            "+=" => {
+                if self.is_register() {
+                    let y = self.register()?;
+                    return self.inst(0x80 | x, (y << 4) | 0x4);
+                }
+                let n = self.byte_value()?;
+                return self.inst(0x70 | x, n);
+            }
+            "-=" => {
+                if self.is_register() {
+                    let y = self.register()?;
+                    return self.inst(0x80 | x, (y << 4) | 0x5);
+                }
+                let n = self.byte_value()?;
+                return self.inst(0x70 | x, n.wrapping_neg());
+            }
+            _ => return self.error_at(&op, format!("unknown register operation '{}'", op.text)),
+        }
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), AsmError> {
+        match token.text.as_str() {
+            ":" => {
+                let name = self.identifier()?;
+                return self.define_label(name);
+            }
+            ":alias" => {
+                let name = self.identifier()?;
+                let reg = self.register()?;
+                self.aliases.insert(name.text, reg);
+            }
+            ":const" => {
+                let name = self.identifier()?;
+                let (value, _) = self.value()?;
+                self.constants.insert(name.text, value);
+            }
+            ":calc" => {
+                let name = self.identifier()?;
+                self.expect("{")?;
+                let value = self.calc_block()?;
+                self.constants.insert(name.text, value);
+            }
+            ":macro" => return self.define_macro(),
+            ":byte" => {
+                let value = self.byte_value()?;
+                return self.byte(value);
+            }
+            ":next" => {
+                let name = self.identifier()?;
+                self.next.push(name.text);
+            }
+            ":org" => {
+                let (value, token) = self.value()?;
+                // the ROM starts at 0x200 so anything below would be lost
+                self.here = usize::from(self.ranged(value, &token, PROGRAM_START as f64, (RAM_SIZE - 1) as f64)?);
+                self.emitted = true;
+            }
+            ":call" => return self.address_inst(0x2),
+            ":breakpoint" => {
+                self.identifier()?;
+            }
+            "clear" => return self.inst(0x00, 0xe0),
+            "hires" => return self.inst(0x00, 0xff),
+            "lores" => return self.inst(0x00, 0xfe),
+            "exit" => return self.inst(0x00, 0xfd),
+            "scroll-down" => {
+                let n = self.nibble_value()?;
+                return self.inst(0x00, 0xc0 | n);
+            }
+            "scroll-up" => {
+                let n = self.nibble_value()?;
+                return self.inst(0x00, 0xd0 | n);
+            }
+            "scroll-right" => return self.inst(0x00, 0xfb),
+            "scroll-left" => return self.inst(0x00, 0xfc),
+            "audio" => return self.inst(0xf0, 0x02),
+            "plane" => {
+                let n = self.nibble_value()?;
+                return self.inst(0xf0 | n, 0x01);
+            }
+            "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                return self.inst(0xf0 | x, 0x3a);
+            }
+            "saveflags" | "loadflags" => {
+                let x = self.register()?;
+                let low = if token.text == "saveflags" { 0x75 } else { 0x85 };
+                return self.inst(0xf0 | x, low);
+            }
+            "return" | ";" => return self.inst(0x00, 0xee),
+            "jump" => return self.address_inst(0x1),
+            "jump0" => return self.address_inst(0xb),
+            "bcd" => {
+                let x = self.register()?;
+                return self.inst(0xf0 | x, 0x33);
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                // `save vx - vy` and `load vx - vy` are the XO-CHIP register range versions
+                if self.peek_is("-") {
+                    self.next_token()?;
+                    let y = self.register()?;
+                    let low = if token.text == "save" { 0x02 } else { 0x03 };
+                    return self.inst(0x50 | x, (y << 4) | low);
+                }
+                let low = if token.text == "save" { 0x55 } else { 0x65 };
+                return self.inst(0xf0 | x, low);
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble_value()?;
+                return self.inst(0xd0 | x, (y << 4) | n);
+            }
+            "delay" | "buzzer" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let low = if token.text == "delay" { 0x15 } else { 0x18 };
+                return self.inst(0xf0 | x, low);
+            }
+            "i" => {
+                let op = self.next_token()?;
+                match op.text.as_str() {
+                    ":=" if self.peek_is("hex") => {
+                        self.next_token()?;
+                        let x = self.register()?;
+                        return self.inst(0xf0 | x, 0x29);
+                    }
+                    ":=" if self.peek_is("bighex") => {
+                        self.next_token()?;
+                        let x = self.register()?;
+                        return self.inst(0xf0 | x, 0x30);
+                    }
+                    ":=" if self.peek_is("long") => {
+                        self.next_token()?;
+                        return self.long_address_inst();
+                    }
+                    ":=" => return self.address_inst(0xa),
+                    "+=" => {
+                        let x = self.register()?;
+                        return self.inst(0xf0 | x, 0x1e);
+                    }
+                    _ => return self.error_at(&op, format!("unknown operation on i '{}'", op.text)),
+                }
+            }
+            "loop" => self.loops.push((self.here, Vec::new(), token)),
+            "while" => {
+                if self.loops.is_empty() {
+                    return self.error_at(&token, String::from("'while' outside of a loop"));
+                }
+                let (reg, comparison, operand) = self.comparison()?;
+                self.skip_unless(reg, comparison.negate(), operand)?;
+                let exit = self.here;
+                self.loops.last_mut().unwrap().1.push(exit);
+                return self.inst(0x10, 0x00);
+            }
+            "again" => {
+                let (start, exits, _) = match self.loops.pop() {
+                    Some(open) => open,
+                    None => return self.error_at(&token, String::from("'again' without 'loop'")),
+                };
+                self.inst(0x10 | (start >> 8) as u8, (start & 0xff) as u8)?;
+                for exit in exits {
+                    self.patch_address(exit, self.here);
+                }
+            }
+            "if" => {
+                let (reg, comparison, operand) = self.comparison()?;
+                let form = self.next_token()?;
+                match form.text.as_str() {
+                    "then" => return self.skip_unless(reg, comparison, operand),
+                    "begin" => {
+                        self.skip_unless(reg, comparison.negate(), operand)?;
+                        self.branches.push((self.here, token));
+                        return self.inst(0x10, 0x00);
+                    }
+                    _ => return self.error_at(&form, format!("expected 'then' or 'begin', found '{}'", form.text)),
+                }
+            }
+            "else" => {
+                let (jump, open) = match self.branches.pop() {
+                    Some(branch) => branch,
+                    None => return self.error_at(&token, String::from("'else' without 'if ... begin'")),
+                };
+                let skip_else = self.here;
+                self.inst(0x10, 0x00)?;
+                self.patch_address(jump, self.here);
+                self.branches.push((skip_else, open));
+            }
+            "end" => {
+                let (jump, _) = match self.branches.pop() {
+                    Some(branch) => branch,
+                    None => return self.error_at(&token, String::from("'end' without 'if ... begin'")),
+                };
+                self.patch_address(jump, self.here);
+            }
+            _ => {
+                if let Some(x) = parse_register(&token.text).or(self.aliases.get(&token.text).copied()) {
+                    return self.register_statement(x);
+                }
+                if let Some(value) = parse_number(&token.text) {
+                    let byte = self.ranged(value, &token, -128.0, 255.0)?;
+                    return self.byte((byte & 0xff) as u8);
+                }
+                if self.macros.contains_key(&token.text) {
+                    return self.expand_macro(&token);
+                }
+                if RESERVED.contains(&token.text.as_str()) || self.constants.contains_key(&token.text) {
+                    return self.error_at(&token, format!("unexpected '{}'", token.text));
+                }
+                // anything else is a call to a subroutine which may be defined later on
+                self.tokens.push_front(token);
+                return self.address_inst(0x2);
+            }
+        }
+        return Ok(());
+    }
+
+    fn run(mut self) -> Result<Assembly, AsmError> {
+        while !self.tokens.is_empty() {
+            let token = self.next_token()?;
+            self.statement(token)?;
+        }
+
+        if let Some((_, _, token)) = self.loops.last() {
+            return self.error_at(token, String::from("'loop' without 'again'"));
+        }
+        if let Some((_, token)) = self.branches.last() {
+            return self.error_at(token, String::from("'if ... begin' without 'end'"));
+        }
+        for (at, name, token, long) in std::mem::take(&mut self.forward) {
+            match self.labels.get(&name) {
+                Some(address) if long => {
+                    self.rom[at] = (address >> 8) as u8;
+                    self.rom[at + 1] = (address & 0xff) as u8;
+                }
+                Some(address) => self.patch_address(at, usize::from(*address)),
+                None => return self.error_at(&token, format!("undefined label '{}'", name)),
+            }
+        }
+        let main = match self.labels.get("main") {
+            Some(address) => usize::from(*address),
+            None => {
+                let last = self.last.clone();
+                return self.error_at(&last, String::from("this program is missing a 'main' label"));
+            }
+        };
+        if !self.main_at_start {
+            self.rom[PROGRAM_START] = 0x10;
+            self.patch_address(PROGRAM_START, main);
+        }
+
+        return Ok(Assembly {
+            bytes: self.rom[PROGRAM_START..self.end.max(PROGRAM_START)].to_vec(),
+            symbols: self.labels,
+        });
+    }
+}
+
+// compiles Octo source into a ROM image that starts at 0x200
+pub fn assemble(source: &str) -> Result<Assembly, AsmError> {
+    return Assembler::new(source).run();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(source: &str) -> Vec<u8> {
+        match assemble(source) {
+            Ok(assembly) => return assembly.bytes,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    // the line, column and message of the error a program fails with
+    fn error(source: &str) -> (usize, usize, String) {
+        match assemble(source) {
+            Ok(_) => panic!("assembled: {}", source),
+            Err(e) => return (e.line, e.column, e.message),
+        }
+    }
+
+    #[test]
+    fn main_comes_first_or_is_jumped_to() {
+        assert_eq!(bytes(": main clear"), [0x00, 0xe0]);
+        assert_eq!(bytes(": sub return : main sub"), [0x12, 0x04, 0x00, 0xee, 0x22, 0x02]);
+    }
+
+    #[test]
+    fn a_label_before_main_is_at_main() {
+        let assembly = assemble(": start : main clear jump start").unwrap();
+        assert_eq!(assembly.bytes, [0x00, 0xe0, 0x12, 0x00]);
+        assert_eq!(assembly.symbols["start"], 0x200);
+    }
+
+    #[test]
+    fn labels_can_be_used_before_they_are_defined() {
+        let assembled = bytes(": main jump done i := long done : done exit");
+        assert_eq!(assembled, [0x12, 0x06, 0xf0, 0x00, 0x02, 0x06, 0x00, 0xfd]);
+    }
+
+    #[test]
+    fn alias_const_and_calc() {
+        assert_eq!(bytes(":alias x v3 : main x := 5 x += v1"), [0x63, 0x05, 0x83, 0x14]);
+        assert_eq!(bytes(":const five 5 : main v0 := five"), [0x60, 0x05]);
+        assert_eq!(bytes(":const five 5 :calc ten { five * 2 } : main v0 := ten"), [0x60, 0x0a]);
+        // no precedence, right to left like Octo
+        assert_eq!(bytes(":calc n { 2 * 3 + 1 } : main v0 := n"), [0x60, 0x08]);
+        assert_eq!(bytes(": main clear :calc at { HERE } i := at"), [0x00, 0xe0, 0xa2, 0x02]);
+    }
+
+    #[test]
+    fn macros_expand_their_arguments_and_count_calls() {
+        assert_eq!(bytes(":macro twice reg { reg += 1 reg += 1 } : main twice v2"), [0x72, 0x01, 0x72, 0x01]);
+        assert_eq!(bytes(":macro count { v0 := CALLS } : main count count"), [0x60, 0x00, 0x60, 0x01]);
+    }
+
+    #[test]
+    fn a_macro_that_expands_itself_stops() {
+        let (line, column, message) = error(":macro forever { forever }\n: main forever");
+        assert_eq!((line, column), (1, 18));
+        assert!(message.contains("nests more than"), "{}", message);
+    }
+
+    #[test]
+    fn loops() {
+        let source = ": main loop v0 += 1 while v0 != 3 again";
+        assert_eq!(bytes(source), [0x70, 0x01, 0x40, 0x03, 0x12, 0x08, 0x12, 0x00]);
+        assert_eq!(error(": main again"), (1, 8, String::from("'again' without 'loop'")));
+        assert_eq!(error(": main loop clear").0, 1);
+    }
+
+    #[test]
+    fn conditionals() {
+        assert_eq!(bytes(": main if v0 == 1 then v1 := 2"), [0x40, 0x01, 0x61, 0x02]);
+        assert_eq!(bytes(": main if v0 key then clear"), [0xe0, 0xa1, 0x00, 0xe0]);
+        let source = ": main if v0 == 1 begin v1 := 2 else v1 := 3 end";
+        assert_eq!(bytes(source), [0x30, 0x01, 0x12, 0x08, 0x61, 0x02, 0x12, 0x0a, 0x61, 0x03]);
+        // comparisons without an instruction of their own go through vF
+        assert_eq!(bytes(": main if v0 > 4 then clear"), [0x6f, 0x04, 0x8f, 0x05, 0x3f, 0x01, 0x00, 0xe0]);
+        assert_eq!(error(": main else").2, "'else' without 'if ... begin'");
+    }
+
+    #[test]
+    fn byte_next_and_org() {
+        assert_eq!(bytes(": main :byte 0xab :byte -1 0x12"), [0xab, 0xff, 0x12]);
+
+        let assembly = assemble(": main :next target v0 := 7 i := target").unwrap();
+        assert_eq!(assembly.bytes, [0x60, 0x07, 0xa2, 0x01]);
+        assert_eq!(assembly.symbols["target"], 0x201);
+
+        let assembled = bytes(": main jump over :org 0x300 : over clear");
+        assert_eq!(assembled.len(), 0x102);
+        assert_eq!(assembled[..2], [0x13, 0x00]);
+        assert_eq!(assembled[0x100..], [0x00, 0xe0]);
+    }
+
+    #[test]
+    fn org_below_the_program_is_an_error() {
+        let (line, column, message) = error(": main\n:org 0x100");
+        assert_eq!((line, column), (2, 6));
+        assert!(message.contains("out of range"), "{}", message);
+    }
+
+    #[test]
+    fn errors_point_at_the_token() {
+        let (line, column, _) = error("\n: main\n  v0 := 300");
+        assert_eq!((line, column), (3, 9));
+        assert_eq!(error(": main jump nowhere"), (1, 13, String::from("undefined label 'nowhere'")));
+        assert_eq!(error(": main : main"), (1, 10, String::from("'main' is already defined")));
+        assert_eq!(error(": main v0 := key v1").0, 1);
+        assert_eq!(error("clear").2, "this program is missing a 'main' label");
+        assert_eq!(error(": main sprite v0 v1").2, "unexpected end of file");
+    }
+
+    #[test]
+    fn symbol_file_lists_labels_by_address() {
+        let assembly = assemble(": sub return : main sub : also : data 0x01").unwrap();
+        assert_eq!(assembly.symbol_file(), "sub 0x0202\nmain 0x0204\nalso 0x0206\ndata 0x0206\n");
+    }
+}
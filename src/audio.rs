@@ -0,0 +1,316 @@
+use byteorder::{LittleEndian, WriteBytesExt};
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+// the tone fades in and out over this long so starting and stopping doesn't click
+const RAMP_SECONDS: f32 = 0.005;
+
+// a whole number of samples per 60Hz frame
+const CAPTURE_SAMPLE_RATE: u32 = 44100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "square" => Ok(Waveform::Square),
+            "sine" => Ok(Waveform::Sine),
+            "triangle" => Ok(Waveform::Triangle),
+            _ => Err(format!("unknown waveform '{}' (expected square, sine or triangle)", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tone {
+    // in Hz
+    pub frequency: f32,
+    pub waveform: Waveform,
+    // 0 to 1
+    pub volume: f32,
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        return Tone {
+            frequency: 440.0,
+            waveform: Waveform::Square,
+            volume: 0.25,
+        };
+    }
+}
+
+// synthesises the buzzer one sample at a time, independent of where the samples end up
+pub struct Buzzer {
+    tone: Tone,
+    sample_rate: f32,
+    // position within the current period, 0 to 1
+    phase: f32,
+    // current volume, moves towards the tone volume while on and towards 0 while off
+    gain: f32,
+    pub on: bool,
+}
+
+impl Buzzer {
+    pub fn new(tone: Tone, sample_rate: u32) -> Self {
+        return Buzzer {
+            tone,
+            sample_rate: sample_rate as f32,
+            phase: 0.0,
+            gain: 0.0,
+            on: false,
+        };
+    }
+
+    fn wave(&self) -> f32 {
+        match self.tone.waveform {
+            Waveform::Square => {
+                if self.phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Sine => (2.0 * PI * self.phase).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+        }
+    }
+
+    pub fn fill(&mut self, out: &mut [f32]) {
+        let target = if self.on { self.tone.volume.clamp(0.0, 1.0) } else { 0.0 };
+        let step = 1.0 / (RAMP_SECONDS * self.sample_rate);
+
+        for sample in out.iter_mut() {
+            if self.gain < target {
+                self.gain = (self.gain + step).min(target);
+            } else if self.gain > target {
+                self.gain = (self.gain - step).max(target);
+            }
+
+            *sample = if self.gain > 0.0 { self.wave() * self.gain } else { 0.0 };
+
+            self.phase += self.tone.frequency / self.sample_rate;
+            if self.phase >= 1.0 {
+                self.phase -= 1.0;
+            }
+        }
+    }
+}
+
+// 16 bit mono PCM. The sizes in the header are written when the writer is dropped, and once a
+// second of samples along the way so a file cut short by the process dying still plays up to there.
+pub struct WavWriter {
+    file: BufWriter<File>,
+    sample_rate: u32,
+    samples: u32,
+    // the sample count the header on disk has
+    header_samples: u32,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path, sample_rate: u32) -> io::Result<WavWriter> {
+        let mut wav = WavWriter {
+            file: BufWriter::new(File::create(path)?),
+            sample_rate,
+            samples: 0,
+            header_samples: 0,
+        };
+        wav.write_header()?;
+        return Ok(wav);
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let data_size = self.samples * 2;
+        let file = &mut self.file;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(b"RIFF")?;
+        file.write_u32::<LittleEndian>(36 + data_size)?;
+        file.write_all(b"WAVEfmt ")?;
+        file.write_u32::<LittleEndian>(16)?;
+        file.write_u16::<LittleEndian>(1)?; // PCM
+        file.write_u16::<LittleEndian>(1)?; // mono
+        file.write_u32::<LittleEndian>(self.sample_rate)?;
+        file.write_u32::<LittleEndian>(self.sample_rate * 2)?;
+        file.write_u16::<LittleEndian>(2)?;
+        file.write_u16::<LittleEndian>(16)?;
+        file.write_all(b"data")?;
+        file.write_u32::<LittleEndian>(data_size)?;
+        file.seek(SeekFrom::End(0))?;
+        self.header_samples = self.samples;
+        return file.flush();
+    }
+
+    pub fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        for sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.file.write_i16::<LittleEndian>(value)?;
+        }
+        self.samples += samples.len() as u32;
+        if self.samples - self.header_samples >= self.sample_rate {
+            self.write_header()?;
+        }
+        return Ok(());
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        if self.header_samples != self.samples {
+            match self.write_header() {
+                Ok(_) => (),
+                Err(_) => (),
+            }
+        }
+    }
+}
+
+// records what the buzzer would play, one frame at a time straight from the sound timer
+pub struct SoundCapture {
+    buzzer: Buzzer,
+    wav: WavWriter,
+    frame: Vec<f32>,
+}
+
+impl SoundCapture {
+    pub fn create(path: &Path, tone: Tone) -> io::Result<SoundCapture> {
+        return Ok(SoundCapture {
+            buzzer: Buzzer::new(tone, CAPTURE_SAMPLE_RATE),
+            wav: WavWriter::create(path, CAPTURE_SAMPLE_RATE)?,
+            frame: vec![0.0; (CAPTURE_SAMPLE_RATE / 60) as usize],
+        });
+    }
+
+    // adds the samples of one 60Hz frame during which the sound timer was running or not
+    pub fn frame(&mut self, on: bool) -> io::Result<()> {
+        self.buzzer.on = on;
+        self.buzzer.fill(&mut self.frame);
+        return self.wav.write(&self.frame);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 441Hz is a period of exactly 100 samples
+    const PERIOD: usize = 100;
+
+    // one period of a tone at half volume, once the buzzer has faded in
+    fn period(waveform: Waveform) -> Vec<f32> {
+        let tone = Tone {
+            frequency: 441.0,
+            waveform,
+            volume: 0.5,
+        };
+        let mut buzzer = Buzzer::new(tone, 44100);
+        buzzer.on = true;
+        let mut samples = vec![0.0; PERIOD * 3];
+        buzzer.fill(&mut samples);
+        let mut samples = vec![0.0; PERIOD];
+        buzzer.fill(&mut samples);
+        return samples;
+    }
+
+    fn assert_close(samples: &[f32], expected: impl Fn(f32) -> f32) {
+        for (i, sample) in samples.iter().enumerate() {
+            let wanted = expected(i as f32 / PERIOD as f32);
+            assert!((sample - wanted).abs() < 0.01, "sample {} is {} not {}", i, sample, wanted);
+        }
+    }
+
+    #[test]
+    fn square() {
+        let samples = period(Waveform::Square);
+        assert!(samples.iter().all(|sample| sample.abs() == 0.5));
+        let high = samples.iter().filter(|sample| **sample > 0.0).count();
+        assert!((49..=51).contains(&high), "{} high", high);
+        assert_eq!(samples[10], 0.5);
+        assert_eq!(samples[60], -0.5);
+    }
+
+    #[test]
+    fn sine() {
+        assert_close(&period(Waveform::Sine), |phase| 0.5 * (2.0 * PI * phase).sin());
+    }
+
+    #[test]
+    fn triangle() {
+        assert_close(&period(Waveform::Triangle), |phase| 0.5 * (1.0 - 4.0 * (phase - 0.5).abs()));
+    }
+
+    #[test]
+    fn silent_while_off_or_without_volume() {
+        let mut off = Buzzer::new(Tone::default(), 44100);
+        let mut samples = vec![1.0; PERIOD];
+        off.fill(&mut samples);
+        assert!(samples.iter().all(|sample| *sample == 0.0));
+
+        let tone = Tone {
+            volume: 0.0,
+            ..Tone::default()
+        };
+        let mut quiet = Buzzer::new(tone, 44100);
+        quiet.on = true;
+        quiet.fill(&mut samples);
+        assert!(samples.iter().all(|sample| *sample == 0.0));
+    }
+
+    fn header(bytes: &[u8], at: usize) -> u32 {
+        return u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+    }
+
+    #[test]
+    fn the_header_has_the_sizes_once_finished() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("buzzer.wav");
+        let mut wav = WavWriter::create(&path, 44100).unwrap();
+        let frame = vec![0.5; 735];
+        for _ in 0..10 {
+            wav.write(&frame).unwrap();
+        }
+        // less than a second in, the header still says there is nothing
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(header(&bytes, 40), 0);
+
+        drop(wav);
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(&bytes[..4], b"RIFF");
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(header(&bytes, 24), 44100);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(header(&bytes, 40), 7350 * 2);
+        assert_eq!(header(&bytes, 4) as usize, bytes.len() - 8);
+        assert_eq!(bytes.len(), 44 + 7350 * 2);
+        assert_eq!(i16::from_le_bytes([bytes[44], bytes[45]]), i16::MAX / 2);
+    }
+
+    #[test]
+    fn dropping_the_writer_finishes_it_and_long_recordings_keep_the_header_current() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("buzzer.wav");
+        let mut wav = WavWriter::create(&path, 8000).unwrap();
+        wav.write(&[0.0; 8000]).unwrap();
+        wav.write(&[0.0; 100]).unwrap();
+        assert_eq!(header(&std::fs::read(&path).unwrap(), 40), 8000 * 2);
+        drop(wav);
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(header(&bytes, 40), 8100 * 2);
+        assert_eq!(bytes.len(), 44 + 8100 * 2);
+    }
+
+    #[test]
+    fn waveforms_parse() {
+        assert_eq!("Sine".parse::<Waveform>(), Ok(Waveform::Sine));
+        assert!("saw".parse::<Waveform>().is_err());
+    }
+}
@@ -0,0 +1,337 @@
+use crate::cpu::{IOpCode, Program};
+use crate::headless_platform::{HeadlessPlatform, KeyScript};
+use crate::machine::Framebuffer;
+use crate::screenshot;
+use crate::settings::Settings;
+use crate::upscale::Filter;
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::Once;
+
+// runs a whole directory of ROMs headlessly and reports how each one ended up, so a change to the
+// emulator can be checked against every ROM at once. The report is a text file with a line per ROM,
+// the columns separated by tabs:
+//
+//   # rom	screen	instructions	halt	errors
+//   2-ibm-logo.ch8	5c1f8b0e2d77a913	20	jumps to itself at 0228
+//   tetris.ch8	0e3a61f4c28b9d05	19972	running
+//
+// Two reports of the same ROMs can be compared with diff, or with the batch command's --baseline.
+
+// files a batch runs, Octo cartridges included
+const ROM_EXTENSIONS: [&str; 5] = ["ch8", "c8", "sc8", "xo8", "gif"];
+
+// the size of a CHIP-8 pixel in a thumbnail
+const THUMBNAIL_SCALE: u32 = 2;
+
+thread_local! {
+    // set while this thread runs a ROM, its panics are reported in the batch rather than on stderr
+    static QUIET: Cell<bool> = const { Cell::new(false) };
+}
+
+// keeps panics on this thread quiet until it is dropped, which happens while unwinding too. The panic
+// hook is only replaced once and passes on the panics of every other thread.
+struct QuietPanics;
+
+impl QuietPanics {
+    fn new() -> Self {
+        static HOOK: Once = Once::new();
+        HOOK.call_once(|| {
+            let hook = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                if !QUIET.with(|quiet| quiet.get()) {
+                    hook(info);
+                }
+            }));
+        });
+        QUIET.with(|quiet| quiet.set(true));
+        return QuietPanics;
+    }
+}
+
+impl Drop for QuietPanics {
+    fn drop(&mut self) {
+        QUIET.with(|quiet| quiet.set(false));
+    }
+}
+
+// where a program was when the batch stopped running it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Halt {
+    // still going when it ran out of frames
+    Running,
+    // in FX0A waiting for a key
+    WaitingForKey,
+    // jumping to itself forever, the usual way for a CHIP-8 program to stop
+    Loop(u16),
+    // ran off the end of the program
+    End(u16),
+    // the emulator panicked
+    Crash,
+    // the ROM couldn't be loaded
+    Error,
+}
+
+impl fmt::Display for Halt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Halt::Running => write!(f, "running"),
+            Halt::WaitingForKey => write!(f, "waiting for a key"),
+            Halt::Loop(address) => write!(f, "jumps to itself at {:04x}", address),
+            Halt::End(address) => write!(f, "ran off the end at {:04x}", address),
+            Halt::Crash => write!(f, "crashed"),
+            Halt::Error => write!(f, "didn't load"),
+        }
+    }
+}
+
+// how a single ROM ended up
+#[derive(Clone, Debug)]
+pub struct BatchResult {
+    // the ROM's file name
+    pub name: String,
+    // FNV-1a of the final screen, None when the ROM never ran
+    pub screen: Option<u64>,
+    pub instructions: u64,
+    pub halt: Halt,
+    pub errors: Vec<String>,
+}
+
+impl BatchResult {
+    // a ROM that couldn't be loaded
+    pub fn failed(name: &str, error: String) -> Self {
+        return BatchResult {
+            name: name.to_string(),
+            screen: None,
+            instructions: 0,
+            halt: Halt::Error,
+            errors: vec![error],
+        };
+    }
+
+    // the report line for this ROM
+    pub fn line(&self) -> String {
+        let screen = match self.screen {
+            Some(hash) => format!("{:016x}", hash),
+            None => String::from("-"),
+        };
+        // tabs and newlines in an error would break the line up
+        let errors: Vec<String> = self
+            .errors
+            .iter()
+            .map(|e| e.split_whitespace().collect::<Vec<&str>>().join(" "))
+            .collect();
+        let mut line = format!("{}\t{}\t{}\t{}", self.name, screen, self.instructions, self.halt);
+        if !errors.is_empty() {
+            line.push('\t');
+            line.push_str(&errors.join("; "));
+        }
+        return line;
+    }
+}
+
+pub fn is_rom(path: &Path) -> bool {
+    return path.is_file()
+        && path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| ROM_EXTENSIONS.iter().any(|e| extension.eq_ignore_ascii_case(e)));
+}
+
+// runs a program for a number of frames and saves its final screen as a PNG thumbnail
+pub fn run(
+    name: &str,
+    program: &mut Program,
+    settings: &Settings,
+    script: Option<KeyScript>,
+    frames: u64,
+    thumbnail: &Path,
+) -> BatchResult {
+    let mut platform = HeadlessPlatform::new(settings);
+    if let Some(script) = script {
+        platform.set_script(script);
+    }
+    program.realtime = false;
+    program.frame_limit = Some(frames);
+
+    // a ROM that crashes the emulator is reported like any other, without the panic message on stderr
+    let quiet = QuietPanics::new();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| platform.run(program)));
+    drop(quiet);
+
+    let mut errors = platform.errors.clone();
+    let halt = match result {
+        Err(payload) => {
+            let message = match payload.downcast_ref::<&str>() {
+                Some(message) => message.to_string(),
+                None => payload.downcast_ref::<String>().cloned().unwrap_or_default(),
+            };
+            errors.push(format!("panicked at {:04x} in frame {}: {}", program.cpu.pc(), program.frames, message));
+            Halt::Crash
+        }
+        Ok(_) => halt(program),
+    };
+
+    let screen = program.cpu.screen();
+    let image =
+        screenshot::screen_image(screen, &settings.palette, settings.rotation, Filter::Nearest, THUMBNAIL_SCALE);
+    if let Err(e) = screenshot::save_png(thumbnail, &image) {
+        errors.push(e);
+    }
+
+    return BatchResult {
+        name: name.to_string(),
+        screen: Some(screen_hash(screen)),
+        instructions: program.instructions,
+        halt,
+        errors,
+    };
+}
+
+fn halt(program: &Program) -> Halt {
+    let cpu = &program.cpu;
+    if cpu.halted() {
+        return Halt::End(cpu.pc());
+    }
+    if cpu.waiting_for_key() {
+        return Halt::WaitingForKey;
+    }
+    match cpu.opcode() {
+        Some(opcode) if opcode.nib1() == 1 && opcode.nib_l3() == cpu.pc() => return Halt::Loop(cpu.pc()),
+        _ => (),
+    }
+    return Halt::Running;
+}
+
+// FNV-1a over the pixels, column by column
+pub fn screen_hash(screen: &Framebuffer) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for x in 0..screen.width() {
+        for y in 0..screen.height() {
+            hash ^= u64::from(screen.pixel(x, y));
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    return hash;
+}
+
+// the whole report, ROMs in the order they ran
+pub fn report(frames: u64, results: &[BatchResult]) -> String {
+    let mut out = format!("# chip8-rust batch, {} frames\n# rom\tscreen\tinstructions\thalt\terrors\n", frames);
+    for result in results {
+        out.push_str(&result.line());
+        out.push('\n');
+    }
+    return out;
+}
+
+// the lines of a report by ROM name
+fn report_lines(text: &str) -> BTreeMap<&str, &str> {
+    return text
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.trim().is_empty())
+        .map(|line| (line.split('\t').next().unwrap(), line))
+        .collect();
+}
+
+// the ROMs that ended up differently from a baseline report, or are missing from this one. ROMs the
+// baseline doesn't have are listed but don't count as differences.
+pub fn compare(baseline: &str, report: &str) -> (Vec<String>, usize) {
+    let before = report_lines(baseline);
+    let after = report_lines(report);
+    let mut out = Vec::new();
+    let mut differences = 0;
+    for (name, line) in before.iter() {
+        match after.get(name) {
+            Some(now) if now == line => (),
+            Some(now) => {
+                out.push(format!("{}: changed\n  was {}\n  now {}", name, line, now));
+                differences += 1;
+            }
+            None => {
+                out.push(format!("{}: missing", name));
+                differences += 1;
+            }
+        }
+    }
+    for name in after.keys().filter(|name| !before.contains_key(*name)) {
+        out.push(format!("{}: new", name));
+    }
+    return (out, differences);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::load_rom;
+    use crate::rom::Rom;
+
+    const BASELINE: &str = "# chip8-rust batch, 100 frames\n# rom\tscreen\tinstructions\thalt\terrors\n\
+        a.ch8\t0000000000000001\t10\trunning\n\
+        b.ch8\t0000000000000002\t20\trunning\n\
+        c.ch8\t0000000000000003\t30\trunning\n";
+
+    #[test]
+    fn report_lines_are_by_rom() {
+        let lines = report_lines(BASELINE);
+        assert_eq!(lines.keys().copied().collect::<Vec<&str>>(), ["a.ch8", "b.ch8", "c.ch8"]);
+        assert_eq!(lines["b.ch8"], "b.ch8\t0000000000000002\t20\trunning");
+    }
+
+    #[test]
+    fn changed_missing_and_new_roms() {
+        let report = "# chip8-rust batch, 100 frames\n\
+            a.ch8\t0000000000000001\t10\trunning\n\
+            c.ch8\t0000000000000003\t31\trunning\n\
+            d.ch8\t0000000000000004\t40\trunning\n\n";
+        let (lines, differences) = compare(BASELINE, report);
+        assert_eq!(
+            lines,
+            [
+                "b.ch8: missing",
+                "c.ch8: changed\n  \
+                 was c.ch8\t0000000000000003\t30\trunning\n  \
+                 now c.ch8\t0000000000000003\t31\trunning",
+                "d.ch8: new",
+            ]
+        );
+        // a ROM the baseline didn't have isn't a difference
+        assert_eq!(differences, 2);
+        assert_eq!(compare(BASELINE, BASELINE), (Vec::new(), 0));
+    }
+
+    #[test]
+    fn a_line_per_result() {
+        let mut result = BatchResult::failed("x.ch8", String::from("bad\tfile\nreally"));
+        assert_eq!(result.line(), "x.ch8\t-\t0\tdidn't load\tbad file really");
+        result.errors.push(String::from("again"));
+        assert!(result.line().ends_with("bad file really; again"));
+    }
+
+    #[test]
+    fn panics_are_only_quiet_while_a_rom_runs() {
+        let caught = panic::catch_unwind(|| {
+            let _quiet = QuietPanics::new();
+            assert!(QUIET.with(|quiet| quiet.get()));
+            panic!("a crashing ROM");
+        });
+        assert!(caught.is_err());
+        // put back while unwinding
+        assert!(!QUIET.with(|quiet| quiet.get()));
+    }
+
+    #[test]
+    fn a_rom_runs_to_where_it_stops() {
+        let bytes = std::fs::read("test/2-ibm-logo.ch8").unwrap();
+        let mut program = load_rom(Rom::from_bytes(&bytes).unwrap()).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let result = run("ibm", &mut program, &Settings::default(), None, 30, &dir.path().join("ibm.png"));
+        assert_eq!(result.halt, Halt::Loop(0x228));
+        assert_eq!(result.screen, Some(screen_hash(program.cpu.screen())));
+        assert!(result.errors.is_empty());
+        assert!(!QUIET.with(|quiet| quiet.get()));
+    }
+}
@@ -0,0 +1,302 @@
+use crate::machine::Framebuffer;
+use crate::quirks::Quirks;
+use crate::settings::{Palette, Rgb, Settings};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+// Octo shares programs as GIF "cartridges". The first frame is a picture of a cartridge but the low
+// 2 bits of every palette index carry the payload, most significant bits first, so 4 pixels make a
+// byte. The payload is a 4 byte big endian length followed by JSON holding the Octo source and the
+// options it was run with.
+
+const CART_WIDTH: usize = 160;
+const CART_HEIGHT: usize = 128;
+
+// where the CHIP-8 screen is drawn on the label, every CHIP-8 pixel is 2x2 cartridge pixels
+const LABEL_X: usize = 16;
+const LABEL_Y: usize = 24;
+
+// every colour of the picture takes up 4 palette entries, one for each value of the 2 payload bits
+const BACKGROUND: u8 = 0;
+const FOREGROUND: u8 = 1;
+const BODY: u8 = 2;
+const EDGE: u8 = 3;
+
+const BODY_COLOR: Rgb = Rgb(0x40, 0x40, 0x48);
+const EDGE_COLOR: Rgb = Rgb(0x70, 0x70, 0x78);
+
+// the options Octo stores in a cartridge, anything we don't use is carried along untouched
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OctoOptions {
+    // instructions per frame
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tickrate: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fill_color: Option<String>,
+    // XO-CHIP second plane and both planes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fill_color2: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blend_color: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background_color: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shift_quirks: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub load_store_quirks: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clip_quirks: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jump_quirks: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logic_quirks: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub v_blank_quirks: Option<bool>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Cartridge {
+    // Octo source
+    pub program: String,
+    #[serde(default)]
+    pub options: OctoOptions,
+}
+
+impl OctoOptions {
+    // the options describing how a program is configured to run
+    pub fn from_settings(settings: &Settings, quirks: &Quirks) -> OctoOptions {
+        return OctoOptions {
+            tickrate: Some((settings.frequency / 60.0).round() as u32),
+            fill_color: Some(settings.palette.foreground.to_string()),
+            fill_color2: Some(settings.palette.plane2.to_string()),
+            blend_color: Some(settings.palette.blend.to_string()),
+            background_color: Some(settings.palette.background.to_string()),
+            shift_quirks: Some(quirks.shifting),
+            load_store_quirks: Some(!quirks.memory),
+            clip_quirks: Some(quirks.clipping),
+            jump_quirks: Some(quirks.jumping),
+            logic_quirks: Some(quirks.vf_reset),
+            v_blank_quirks: Some(quirks.display_wait),
+            other: Map::new(),
+        };
+    }
+
+    // the quirks that were set, None if the cartridge doesn't mention any
+    pub fn quirks(&self, base: Quirks) -> Option<Quirks> {
+        let mut quirks = base;
+        let mut any = false;
+        let mut set = |value: Option<bool>, field: &mut bool| {
+            if let Some(value) = value {
+                *field = value;
+                any = true;
+            }
+        };
+        set(self.shift_quirks, &mut quirks.shifting);
+        set(self.load_store_quirks.map(|unchanged| !unchanged), &mut quirks.memory);
+        set(self.clip_quirks, &mut quirks.clipping);
+        set(self.jump_quirks, &mut quirks.jumping);
+        set(self.logic_quirks, &mut quirks.vf_reset);
+        set(self.v_blank_quirks, &mut quirks.display_wait);
+        return if any { Some(quirks) } else { None };
+    }
+
+    pub fn apply(&self, settings: &mut Settings) -> Result<(), String> {
+        if let Some(quirks) = self.quirks(settings.quirks.unwrap_or_default()) {
+            settings.quirks = Some(quirks);
+        }
+        if let Some(tickrate) = self.tickrate {
+            settings.frequency = tickrate as f32 * 60.0;
+        }
+        if let Some(color) = &self.fill_color {
+            settings.palette.foreground = color.parse()?;
+        }
+        if let Some(color) = &self.fill_color2 {
+            settings.palette.plane2 = color.parse()?;
+        }
+        if let Some(color) = &self.blend_color {
+            settings.palette.blend = color.parse()?;
+        }
+        if let Some(color) = &self.background_color {
+            settings.palette.background = color.parse()?;
+        }
+        return Ok(());
+    }
+}
+
+// GIF87a or GIF89a
+pub fn is_cartridge(bytes: &[u8]) -> bool {
+    return bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a");
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Cartridge, String> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(bytes).map_err(|e| e.to_string())?;
+    let frame = decoder
+        .read_next_frame()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| String::from("the GIF has no frames"))?;
+
+    let payload: Vec<u8> = frame
+        .buffer
+        .chunks_exact(4)
+        .map(|pixels| pixels.iter().fold(0u8, |byte, pixel| (byte << 2) | (pixel & 3)))
+        .collect();
+    if payload.len() < 4 {
+        return Err(String::from("not an Octo cartridge"));
+    }
+    let size = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]) as usize;
+    if size > payload.len() - 4 {
+        return Err(String::from("not an Octo cartridge, the payload is truncated"));
+    }
+    let json = std::str::from_utf8(&payload[4..4 + size]).map_err(|_| String::from("not an Octo cartridge"))?;
+    return serde_json::from_str(json).map_err(|e| format!("invalid cartridge payload: {}", e));
+}
+
+fn palette(colors: &Palette) -> Vec<u8> {
+    let mut palette = Vec::new();
+    for color in [colors.background, colors.foreground, BODY_COLOR, EDGE_COLOR] {
+        for _ in 0..4 {
+            palette.extend_from_slice(&[color.0, color.1, color.2]);
+        }
+    }
+    return palette;
+}
+
+// the picture before the payload is mixed in, a grey cartridge with the screen as its label
+fn picture(height: usize, label: &Framebuffer) -> Vec<u8> {
+    let mut pixels = vec![BODY; CART_WIDTH * height];
+    for x in 0..CART_WIDTH {
+        pixels[x] = EDGE;
+        pixels[(height - 1) * CART_WIDTH + x] = EDGE;
+    }
+    for y in 0..height {
+        pixels[y * CART_WIDTH] = EDGE;
+        pixels[y * CART_WIDTH + CART_WIDTH - 1] = EDGE;
+    }
+    for y in 0..64 {
+        for x in 0..128 {
+            let lit = label.pixel(x * label.width() / 128, y * label.height() / 64) > 0;
+            pixels[(LABEL_Y + y) * CART_WIDTH + LABEL_X + x] = if lit { FOREGROUND } else { BACKGROUND };
+        }
+    }
+    return pixels;
+}
+
+// packs the cartridge into a GIF using the palette for the picture, label is the CHIP-8 screen
+// drawn on the front
+pub fn encode(cartridge: &Cartridge, colors: &Palette, label: &Framebuffer) -> Result<Vec<u8>, String> {
+    let json = serde_json::to_string(cartridge).map_err(|e| e.to_string())?;
+    let mut payload = (json.len() as u32).to_be_bytes().to_vec();
+    payload.extend_from_slice(json.as_bytes());
+
+    // big programs make for a taller cartridge
+    let rows = (payload.len() * 4).div_ceil(CART_WIDTH);
+    let height = rows.max(CART_HEIGHT);
+    if height > usize::from(u16::MAX) {
+        return Err(String::from("the program is too big for a cartridge"));
+    }
+
+    let mut pixels = picture(height, label);
+    for (i, byte) in payload.iter().enumerate() {
+        for j in 0..4 {
+            let bits = (byte >> (6 - j * 2)) & 3;
+            pixels[i * 4 + j] = (pixels[i * 4 + j] << 2) | bits;
+        }
+    }
+    for pixel in pixels.iter_mut().skip(payload.len() * 4) {
+        *pixel <<= 2;
+    }
+
+    let mut gif = Vec::new();
+    {
+        let mut encoder = gif::Encoder::new(&mut gif, CART_WIDTH as u16, height as u16, &palette(colors))
+            .map_err(|e| e.to_string())?;
+        let frame = gif::Frame::from_indexed_pixels(CART_WIDTH as u16, height as u16, pixels, None);
+        encoder.write_frame(&frame).map_err(|e| e.to_string())?;
+    }
+    return Ok(gif);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::QuirkProfile;
+
+    fn cartridge(program: &str, options: OctoOptions) -> Cartridge {
+        return Cartridge {
+            program: program.to_string(),
+            options,
+        };
+    }
+
+    #[test]
+    fn a_cartridge_decodes_to_what_was_encoded() {
+        let settings = Settings {
+            frequency: 1200.0,
+            ..Default::default()
+        };
+        let mut options = OctoOptions::from_settings(&settings, &QuirkProfile::Vip.quirks());
+        options.other.insert(String::from("fontStyle"), Value::from("octo"));
+        let original = cartridge(": main\n\tclear\n", options);
+
+        let gif = encode(&original, &settings.palette, &Framebuffer::default()).unwrap();
+        assert!(is_cartridge(&gif));
+        let decoded = decode(&gif).unwrap();
+        assert_eq!(decoded.program, original.program);
+        assert_eq!(decoded.options.tickrate, Some(20));
+        assert_eq!(decoded.options.other["fontStyle"], "octo");
+        assert_eq!(decoded.options.quirks(Quirks::default()), Some(QuirkProfile::Vip.quirks()));
+    }
+
+    #[test]
+    fn a_big_program_makes_a_taller_cartridge() {
+        let program = ": main\n".to_string() + &"\tclear\n".repeat(2000);
+        let big = cartridge(&program, OctoOptions::default());
+        let gif = encode(&big, &Palette::default(), &Framebuffer::default()).unwrap();
+        let decoder = gif::DecodeOptions::new().read_info(gif.as_slice()).unwrap();
+        assert!(usize::from(decoder.height()) > CART_HEIGHT);
+        assert_eq!(decode(&gif).unwrap().program, program);
+    }
+
+    #[test]
+    fn the_label_is_the_screen() {
+        let mut label = Framebuffer::default();
+        label.set_pixel(0, 0, 1);
+        let gif = encode(&cartridge(": main", OctoOptions::default()), &Palette::default(), &label).unwrap();
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(gif.as_slice()).unwrap();
+        let frame = decoder.read_next_frame().unwrap().unwrap();
+        // the top two bits of a pixel are the picture, a lores pixel is 2x2 on the label
+        let at = |x: usize, y: usize| frame.buffer[(LABEL_Y + y) * CART_WIDTH + LABEL_X + x] >> 2;
+        assert_eq!((at(0, 0), at(1, 1), at(2, 0)), (FOREGROUND, FOREGROUND, BACKGROUND));
+    }
+
+    #[test]
+    fn options_apply_to_the_settings() {
+        let options: OctoOptions =
+            serde_json::from_str(r##"{"tickrate": 7, "fillColor": "#ff0000", "shiftQuirks": false}"##).unwrap();
+        let mut settings = Settings::default();
+        options.apply(&mut settings).unwrap();
+        assert_eq!(settings.frequency, 420.0);
+        assert_eq!(settings.palette.foreground, Rgb(0xff, 0, 0));
+        assert!(!settings.quirks.unwrap().shifting);
+        assert_eq!(OctoOptions::default().quirks(Quirks::default()), None);
+    }
+
+    #[test]
+    fn other_gifs_are_not_cartridges() {
+        let mut gif = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut gif, 4, 4, &[0, 0, 0, 255, 255, 255]).unwrap();
+            encoder.write_frame(&gif::Frame::from_indexed_pixels(4, 4, vec![1; 16], None)).unwrap();
+        }
+        assert!(is_cartridge(&gif));
+        assert!(decode(&gif).is_err());
+        assert!(!is_cartridge(b"\x00\xe0"));
+    }
+}
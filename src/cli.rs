@@ -0,0 +1,410 @@
+use chip8_rust::audio::Waveform;
+use chip8_rust::cpu::Program;
+use chip8_rust::headless_platform::{HeadlessPlatform, KeyScript};
+use chip8_rust::keymap;
+use chip8_rust::movie::{Movie, MovieHeader, MovieMode, MoviePlayer, MovieRecorder};
+use chip8_rust::persistence::Persistence;
+use chip8_rust::quirks::QuirkProfile;
+use chip8_rust::recording::{CaptureOptions, RecordingFormat};
+use chip8_rust::terminal_platform::Glyphs;
+use chip8_rust::upscale::Filter;
+use chip8_rust::settings::{KeyLayout, Palette, Rgb, Rotation, Settings};
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(name = "chip8-rust", version, about = "A CHIP-8 emulator, assembler and disassembler")]
+pub(crate) struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub(crate) enum Command {
+    /// Run a ROM in a window
+    Run {
+        rom: PathBuf,
+        #[command(flatten)]
+        emulator: EmulatorArgs,
+        #[command(flatten)]
+        display: DisplayArgs,
+        #[command(flatten)]
+        platform: Box<PlatformArgs>,
+    },
+    /// Disassemble a ROM into Octo source
+    Disasm {
+        rom: PathBuf,
+        /// Write the source to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Print an address/bytes/instruction listing instead of source
+        #[arg(long)]
+        listing: bool,
+    },
+    /// Assemble Octo source into a ROM and a symbol file
+    Asm {
+        source: PathBuf,
+        /// Defaults to the source path with a .ch8 extension
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Pack a ROM and its settings into an Octo cartridge GIF
+    Export {
+        rom: PathBuf,
+        #[command(flatten)]
+        emulator: EmulatorArgs,
+        #[command(flatten)]
+        display: DisplayArgs,
+        /// Defaults to the ROM path with a .gif extension
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Number of 60Hz frames to run before taking the screen shown on the label
+        #[arg(long, default_value_t = 120)]
+        label_frames: u64,
+    },
+    /// Show information about a ROM and the settings it would run with
+    Info {
+        rom: PathBuf,
+        #[command(flatten)]
+        emulator: EmulatorArgs,
+    },
+    /// Run a ROM headlessly printing every instruction as it executes
+    Trace {
+        rom: PathBuf,
+        #[command(flatten)]
+        emulator: EmulatorArgs,
+        /// Stop after this many 60Hz frames
+        #[arg(long)]
+        frames: Option<u64>,
+    },
+    /// Run a ROM headlessly and print its final screen, or compare it against a file
+    Test {
+        rom: PathBuf,
+        #[command(flatten)]
+        emulator: EmulatorArgs,
+        /// Number of 60Hz frames to run
+        #[arg(long, default_value_t = 300)]
+        frames: u64,
+        /// Expected screen as ASCII art, exits with 1 if the screen differs
+        #[arg(long)]
+        expect: Option<PathBuf>,
+        #[command(flatten)]
+        headless_args: HeadlessArgs,
+    },
+    /// Run every ROM in a directory headlessly and write a report of how each one ended up
+    Batch {
+        dir: PathBuf,
+        #[command(flatten)]
+        emulator: EmulatorArgs,
+        /// Number of 60Hz frames to run each ROM for
+        #[arg(long, default_value_t = 300)]
+        frames: u64,
+        /// Key presses to replay in every ROM, or a directory of scripts named after the ROMs (<name>.txt)
+        #[arg(long, value_name = "FILE|DIR")]
+        script: Option<PathBuf>,
+        /// Directory the report and the thumbnails are written to
+        #[arg(short, long, value_name = "DIR", default_value = "batch")]
+        output: PathBuf,
+        /// Earlier report to compare against, exits with 1 if a ROM ended up differently
+        #[arg(long, value_name = "FILE")]
+        baseline: Option<PathBuf>,
+    },
+    /// Let other programs drive the emulator with JSON-RPC over a local TCP port or a Unix socket
+    Serve {
+        /// ROM to have loaded before the first client connects
+        rom: Option<PathBuf>,
+        #[command(flatten)]
+        emulator: EmulatorArgs,
+        /// Loopback host:port, or the path of a Unix socket with a / in it or after unix:
+        #[arg(long, value_name = "ADDRESS", default_value = "127.0.0.1:8765")]
+        listen: String,
+    },
+}
+
+#[derive(Args)]
+pub(crate) struct EmulatorArgs {
+    /// Quirk profile: modern, vip, schip or xochip
+    #[arg(long, value_name = "PROFILE")]
+    pub quirks: Option<QuirkProfile>,
+    /// Instructions executed per second
+    #[arg(long, value_name = "IPS")]
+    pub speed: Option<f32>,
+    /// Seed for the random number generator
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Keypad layout: qwerty (the default), azerty, qwertz or hex
+    #[arg(long, value_name = "LAYOUT")]
+    pub keys: Option<KeyLayout>,
+    /// Bind a CHIP-8 key to physical keys named like SDL scancodes, e.g. --bind 5=W,Up
+    #[arg(long, value_name = "KEY=NAMES", value_parser = keymap::parse_binding)]
+    pub bind: Vec<(u8, Vec<String>)>,
+    /// Directory holding a copy of the CHIP-8 database (sha1-hashes.json, programs.json, platforms.json)
+    #[arg(long, value_name = "DIR")]
+    pub database: Option<PathBuf>,
+    /// Ignore the user config file and per-ROM TOML override files
+    #[arg(long)]
+    pub no_config: bool,
+}
+
+#[derive(Args)]
+pub(crate) struct DisplayArgs {
+    /// Size of a CHIP-8 pixel in screen pixels
+    #[arg(long)]
+    pub scale: Option<u32>,
+    /// Colour preset: mono, inverse, octo, amber, green or lcd
+    #[arg(long, value_name = "NAME")]
+    pub palette: Option<Palette>,
+    /// Foreground colour as RRGGBB
+    #[arg(long, value_name = "RRGGBB")]
+    pub fg: Option<Rgb>,
+    /// Background colour as RRGGBB
+    #[arg(long, value_name = "RRGGBB")]
+    pub bg: Option<Rgb>,
+    /// Colour of XO-CHIP pixels lit on the second plane only, as RRGGBB
+    #[arg(long, value_name = "RRGGBB")]
+    pub plane2: Option<Rgb>,
+    /// Colour of XO-CHIP pixels lit on both planes, as RRGGBB
+    #[arg(long, value_name = "RRGGBB")]
+    pub blend: Option<Rgb>,
+    /// Draw lines between the pixels, G toggles it in the window
+    #[arg(long)]
+    pub grid: bool,
+    /// Turn the screen clockwise by 0, 90, 180 or 270 degrees
+    #[arg(long, value_name = "DEGREES")]
+    pub rotate: Option<Rotation>,
+    /// Start fullscreen, F11 toggles it in the window
+    #[arg(long)]
+    pub fullscreen: bool,
+    /// Hide flicker by fading pixels out (decay[:FRAMES]) or keeping them lit for a few frames (or[:FRAMES])
+    #[arg(long, value_name = "MODE")]
+    pub persistence: Option<Persistence>,
+    /// Upscaling filter: nearest, scale2x, scale3x, scanlines or crt, F9 cycles through them in the window
+    #[arg(long, value_name = "NAME")]
+    pub filter: Option<Filter>,
+}
+
+// which Platform a run uses and how it is set up
+#[derive(Args)]
+pub(crate) struct PlatformArgs {
+    /// Run without opening a window and as fast as possible
+    #[arg(long)]
+    pub headless: bool,
+    /// Stop after this many 60Hz frames
+    #[arg(long)]
+    pub frames: Option<u64>,
+    #[command(flatten)]
+    pub headless_args: HeadlessArgs,
+    #[command(flatten)]
+    pub terminal_args: TerminalArgs,
+    #[command(flatten)]
+    pub sound: SoundArgs,
+    #[command(flatten)]
+    pub capture: CaptureArgs,
+    #[command(flatten)]
+    pub movie: MovieArgs,
+}
+
+#[derive(Args)]
+pub(crate) struct HeadlessArgs {
+    /// Key presses to replay, one 'frame down|up|tap key' per line (headless only)
+    #[arg(long, value_name = "FILE")]
+    pub script: Option<PathBuf>,
+    /// Save the screen at the end of these frames as PNG files (headless only)
+    #[arg(long, value_name = "FRAMES", value_delimiter = ',')]
+    pub dump_frames: Vec<u64>,
+    /// Directory the dumped frames are written to
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    pub dump_dir: PathBuf,
+}
+
+#[derive(Args)]
+pub(crate) struct TerminalArgs {
+    /// Draw the screen in the terminal instead of a window
+    #[arg(long, conflicts_with = "headless")]
+    pub terminal: bool,
+    /// Characters used to draw the screen in the terminal: half or braille
+    #[arg(long, value_name = "GLYPHS", default_value = "half")]
+    pub glyphs: Glyphs,
+    /// Milliseconds after the last key repeat that a key counts as released, for terminals without key up events
+    #[arg(long, value_name = "MS", default_value_t = 250)]
+    pub key_timeout: u64,
+}
+
+#[derive(Args)]
+pub(crate) struct CaptureArgs {
+    /// Save the last frame as a PNG when the emulator stops, F12 saves one at any time in the window
+    #[arg(long, value_name = "FILE")]
+    pub screenshot: Option<PathBuf>,
+    /// Record an animated GIF, or an APNG if FILE ends in .png, F10 starts and stops recording in the window
+    #[arg(long, value_name = "FILE")]
+    pub record: Option<PathBuf>,
+    /// Where screenshots and recordings made from the keyboard are saved
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    pub capture_dir: PathBuf,
+    /// Format of recordings made from the keyboard: gif or apng
+    #[arg(long, value_name = "FORMAT", default_value = "gif")]
+    pub record_format: RecordingFormat,
+}
+
+#[derive(Args)]
+pub(crate) struct MovieArgs {
+    /// Record the keys held on every frame into a movie that plays back with exactly the same result
+    #[arg(long, value_name = "FILE")]
+    pub record_movie: Option<PathBuf>,
+    /// Take the keys from a movie instead of the keyboard and report the first frame where the machine state differs
+    #[arg(long, value_name = "FILE", conflicts_with = "record_movie")]
+    pub play_movie: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub(crate) struct SoundArgs {
+    /// Pitch of the buzzer in Hz
+    #[arg(long, value_name = "HZ")]
+    pub tone: Option<f32>,
+    /// Shape of the buzzer: square, sine or triangle
+    #[arg(long, value_name = "WAVEFORM")]
+    pub waveform: Option<Waveform>,
+    /// Buzzer volume from 0 to 1
+    #[arg(long)]
+    pub volume: Option<f32>,
+    /// Start with the sound muted, M toggles it in the window
+    #[arg(long)]
+    pub mute: bool,
+    /// Record the buzzer to a WAV file, frame by frame as the emulator runs
+    #[arg(long, value_name = "FILE")]
+    pub wav: Option<PathBuf>,
+}
+
+impl EmulatorArgs {
+    pub(crate) fn apply(&self, settings: &mut Settings) {
+        if let Some(quirks) = self.quirks {
+            settings.quirks = Some(quirks.quirks());
+        }
+        if let Some(speed) = self.speed {
+            settings.frequency = speed;
+        }
+        if let Some(seed) = self.seed {
+            settings.seed = Some(seed);
+        }
+        if let Some(keys) = self.keys {
+            settings.key_layout = keys;
+        }
+        for (key, names) in self.bind.iter() {
+            settings.bindings.insert(*key, names.clone());
+        }
+    }
+}
+
+impl CaptureArgs {
+    pub(crate) fn options(&self) -> CaptureOptions {
+        return CaptureOptions {
+            dir: self.capture_dir.clone(),
+            format: self.record_format,
+            screenshot: self.screenshot.clone(),
+            record: self.record.clone(),
+        };
+    }
+}
+
+impl MovieArgs {
+    // sets the program up to record or play a movie, returns the number of frames a movie being played lasts
+    pub(crate) fn apply(&self, program: &mut Program, settings: &Settings) -> Result<Option<u64>, String> {
+        if let Some(path) = &self.record_movie {
+            let seed = settings.seed.unwrap_or_else(rand::random);
+            program.reseed(seed);
+            let header = MovieHeader {
+                rom: program.rom.sha1(),
+                seed,
+                speed: program.frequency,
+                quirks: program.cpu.quirks,
+            };
+            let recorder = MovieRecorder::create(path, &header).map_err(|e| format!("{}: {}", path.display(), e))?;
+            program.movie = Some(MovieMode::Record(recorder));
+        }
+        if let Some(path) = &self.play_movie {
+            let movie = Movie::load(path)?;
+            let sha1 = program.rom.sha1();
+            if movie.header.rom != sha1 {
+                return Err(format!(
+                    "{}: recorded with the ROM {} but this one is {}",
+                    path.display(),
+                    movie.header.rom,
+                    sha1
+                ));
+            }
+            // the movie decides everything that changes what the program does
+            program.reseed(movie.header.seed);
+            program.frequency = movie.header.speed;
+            program.cpu.quirks = movie.header.quirks;
+            let length = movie.length();
+            program.movie = Some(MovieMode::Play(MoviePlayer::new(movie)));
+            return Ok(Some(length));
+        }
+        return Ok(None);
+    }
+}
+
+impl HeadlessArgs {
+    pub(crate) fn apply(&self, platform: &mut HeadlessPlatform) -> Result<(), String> {
+        if let Some(script) = &self.script {
+            platform.set_script(KeyScript::load(script)?);
+        }
+        platform.dump(&self.dump_frames, &self.dump_dir);
+        return Ok(());
+    }
+}
+
+impl SoundArgs {
+    pub(crate) fn apply(&self, settings: &mut Settings) {
+        if let Some(tone) = self.tone {
+            settings.tone.frequency = tone;
+        }
+        if let Some(waveform) = self.waveform {
+            settings.tone.waveform = waveform;
+        }
+        if let Some(volume) = self.volume {
+            settings.tone.volume = volume.clamp(0.0, 1.0);
+        }
+        if self.mute {
+            settings.muted = true;
+        }
+    }
+}
+
+impl DisplayArgs {
+    pub(crate) fn apply(&self, settings: &mut Settings) {
+        if let Some(scale) = self.scale {
+            settings.scale = Some(scale);
+        }
+        if let Some(palette) = self.palette {
+            settings.palette = palette;
+        }
+        if let Some(fg) = self.fg {
+            settings.palette.foreground = fg;
+        }
+        if let Some(bg) = self.bg {
+            settings.palette.background = bg;
+        }
+        if let Some(plane2) = self.plane2 {
+            settings.palette.plane2 = plane2;
+        }
+        if let Some(blend) = self.blend {
+            settings.palette.blend = blend;
+        }
+        if self.grid {
+            settings.grid = true;
+        }
+        if let Some(rotation) = self.rotate {
+            settings.rotation = rotation;
+        }
+        if self.fullscreen {
+            settings.fullscreen = true;
+        }
+        if let Some(persistence) = self.persistence {
+            settings.persistence = persistence;
+        }
+        if let Some(filter) = self.filter {
+            settings.filter = filter;
+        }
+    }
+}
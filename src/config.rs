@@ -0,0 +1,260 @@
+use crate::database::{self, Database};
+use crate::keymap;
+use crate::quirks::{QuirkOverrides, QuirkProfile, Quirks};
+use crate::rom::Rom;
+use crate::settings::{Rgb, Rotation, Settings};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// a per-ROM TOML file overriding what the database says, e.g.
+//
+//   platform = "superchip"
+//   tickrate = 30
+//   layout = "azerty"
+//   palette = "amber"
+//   rotation = 90
+//   persistence = "decay:6"
+//   filter = "scale2x"
+//   [quirks]
+//   shift = false
+//   [colors]
+//   background = "#000000"
+//   foreground = "#ffcc00"
+//   [keys]
+//   up = 5
+//   [keymap]
+//   5 = ["W", "Up"]
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RomConfig {
+    pub platform: Option<String>,
+    pub tickrate: Option<u32>,
+    pub quirks: Option<QuirkOverrides>,
+    pub palette: Option<String>,
+    pub colors: Option<ColorConfig>,
+    pub layout: Option<String>,
+    pub scale: Option<u32>,
+    pub grid: Option<bool>,
+    pub rotation: Option<u32>,
+    pub persistence: Option<String>,
+    pub filter: Option<String>,
+    #[serde(default)]
+    pub keys: BTreeMap<String, u8>,
+    // CHIP-8 keys to the physical keys that press them
+    #[serde(default)]
+    pub keymap: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColorConfig {
+    pub background: Option<String>,
+    pub foreground: Option<String>,
+    pub plane2: Option<String>,
+    pub blend: Option<String>,
+}
+
+impl RomConfig {
+    pub fn load(path: &Path) -> Result<RomConfig, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        return toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e));
+    }
+
+    pub fn apply(&self, settings: &mut Settings) -> Result<(), String> {
+        if let Some(platform) = &self.platform {
+            let profile = QuirkProfile::for_platform(platform)
+                .ok_or_else(|| format!("unknown platform '{}'", platform))?;
+            settings.quirks = Some(profile.quirks());
+        }
+        if let Some(quirks) = &self.quirks {
+            quirks.apply(settings.quirks.get_or_insert_with(Quirks::default));
+        }
+        if let Some(tickrate) = self.tickrate {
+            settings.frequency = tickrate as f32 * 60.0;
+        }
+        if let Some(palette) = &self.palette {
+            settings.palette = palette.parse()?;
+        }
+        if let Some(colors) = &self.colors {
+            if let Some(background) = &colors.background {
+                settings.palette.background = background.parse::<Rgb>()?;
+            }
+            if let Some(foreground) = &colors.foreground {
+                settings.palette.foreground = foreground.parse::<Rgb>()?;
+            }
+            if let Some(plane2) = &colors.plane2 {
+                settings.palette.plane2 = plane2.parse::<Rgb>()?;
+            }
+            if let Some(blend) = &colors.blend {
+                settings.palette.blend = blend.parse::<Rgb>()?;
+            }
+        }
+        if let Some(layout) = &self.layout {
+            settings.key_layout = layout.parse()?;
+        }
+        if let Some(scale) = self.scale {
+            settings.scale = Some(scale);
+        }
+        if let Some(grid) = self.grid {
+            settings.grid = grid;
+        }
+        if let Some(rotation) = self.rotation {
+            settings.rotation = Rotation::from_degrees(rotation)?;
+        }
+        if let Some(persistence) = &self.persistence {
+            settings.persistence = persistence.parse()?;
+        }
+        if let Some(filter) = &self.filter {
+            settings.filter = filter.parse()?;
+        }
+        for (button, key) in self.keys.iter() {
+            keymap::check_button(button, *key)?;
+            settings.buttons.insert(button.clone(), *key);
+        }
+        for (key, names) in self.keymap.iter() {
+            settings.bindings.insert(keymap::parse_key(key)?, names.clone());
+        }
+        return Ok(());
+    }
+}
+
+// <config dir>/chip8-rust/config.toml, the same format as the per-ROM files but applied to every ROM
+pub fn user_config_path() -> Option<PathBuf> {
+    return dirs::config_dir().map(|dir| dir.join("chip8-rust").join("config.toml"));
+}
+
+// candidate override files from lowest to highest priority:
+// <config dir>/chip8-rust/roms/<sha1>.toml, <config dir>/chip8-rust/roms/<name>.toml and <rom>.toml next to the ROM
+pub fn config_paths(rom_path: &Path, rom: &Rom) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if let Some(dir) = dirs::config_dir() {
+        let roms = dir.join("chip8-rust").join("roms");
+        paths.push(roms.join(format!("{}.toml", rom.sha1())));
+        if let Some(stem) = rom_path.file_stem() {
+            paths.push(roms.join(stem).with_extension("toml"));
+        }
+    }
+    paths.push(rom_path.with_extension("toml"));
+    return paths;
+}
+
+// builds the settings for a ROM from the user's config file, the database, the options of an Octo cartridge and
+// any override files, returns the settings along with a description of every source that was applied
+pub fn rom_settings(
+    rom_path: &Path,
+    rom: &Rom,
+    database_dir: Option<&Path>,
+    use_config: bool,
+) -> Result<(Settings, Vec<String>), String> {
+    let mut settings = Settings::default();
+    let mut sources = Vec::new();
+
+    // the user's own defaults, anything known about the ROM beats them
+    if let Some(path) = user_config_path().filter(|path| use_config && path.is_file()) {
+        RomConfig::load(&path)?
+            .apply(&mut settings)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        sources.push(path.display().to_string());
+    }
+
+    // an explicitly requested database has to exist, the default one is optional
+    let database = match database_dir {
+        Some(dir) => Some(Database::load(dir)?),
+        None => match database::default_dir() {
+            Some(dir) if dir.join("sha1-hashes.json").exists() => Some(Database::load(&dir)?),
+            _ => None,
+        },
+    };
+    if let Some(database) = &database {
+        if let Some(found) = database.lookup(&rom.sha1()) {
+            database.apply(&found, &mut settings)?;
+            sources.push(format!("database entry '{}'", found.title));
+        }
+    }
+
+    if let Some(cartridge) = &rom.cartridge {
+        cartridge.options.apply(&mut settings).map_err(|e| format!("cartridge options: {}", e))?;
+        sources.push(String::from("cartridge options"));
+    }
+
+    if use_config {
+        for path in config_paths(rom_path, rom) {
+            if path.is_file() {
+                RomConfig::load(&path)?
+                    .apply(&mut settings)
+                    .map_err(|e| format!("{}: {}", path.display(), e))?;
+                sources.push(path.display().to_string());
+            }
+        }
+    }
+
+    return Ok((settings, sources));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Palette;
+
+    fn config(text: &str) -> Result<RomConfig, String> {
+        return toml::from_str(text).map_err(|e| e.to_string());
+    }
+
+    fn apply(text: &str) -> Result<Settings, String> {
+        let mut settings = Settings::default();
+        config(text)?.apply(&mut settings)?;
+        return Ok(settings);
+    }
+
+    #[test]
+    fn quirks_layer_over_the_platform() {
+        let settings = apply("platform = \"superchip\"\ntickrate = 30\n[quirks]\nshift = false\n").unwrap();
+        let mut quirks = QuirkProfile::Schip.quirks();
+        quirks.shifting = false;
+        assert_eq!(settings.quirks, Some(quirks));
+        assert_eq!(settings.frequency, 1800.0);
+        // quirks on their own start from the defaults
+        assert!(apply("[quirks]\njump = true\n").unwrap().quirks.unwrap().jumping);
+    }
+
+    #[test]
+    fn colours_layer_over_the_palette() {
+        let settings = apply("palette = \"amber\"\n[colors]\nbackground = \"#010203\"\n").unwrap();
+        let amber = Palette::preset("amber").unwrap();
+        assert_eq!(settings.palette.background, Rgb(1, 2, 3));
+        assert_eq!(settings.palette.foreground, amber.foreground);
+    }
+
+    #[test]
+    fn bad_values_are_errors() {
+        assert!(apply("platform = \"nes\"\n").unwrap_err().contains("unknown platform"));
+        assert!(apply("rotation = 45\n").is_err());
+        assert!(apply("layout = \"dvorak\"\n").is_err());
+        assert!(config("volume = 3\n").is_err());
+    }
+
+    #[test]
+    fn a_file_next_to_the_rom_beats_the_database() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let rom = Rom::from_bytes(&[0x12, 0x00]).unwrap();
+        fs::write(dir.join("sha1-hashes.json"), format!(r#"{{"{}": 0}}"#, rom.sha1())).unwrap();
+        let entry = r#"{"tickrate": 10, "screenRotation": 180}"#;
+        let programs = format!(r#"[{{"title": "Loop", "roms": {{"{}": {}}}}}]"#, rom.sha1(), entry);
+        fs::write(dir.join("programs.json"), programs).unwrap();
+        let rom_path = dir.join("loop.ch8");
+        fs::write(&rom_path, &rom.bytes).unwrap();
+
+        let (settings, sources) = rom_settings(&rom_path, &rom, Some(dir), false).unwrap();
+        assert_eq!(settings.frequency, 600.0);
+        assert_eq!(sources, ["database entry 'Loop'"]);
+
+        fs::write(dir.join("loop.toml"), "tickrate = 20\n").unwrap();
+        let (settings, sources) = rom_settings(&rom_path, &rom, Some(dir), true).unwrap();
+        assert_eq!(settings.frequency, 1200.0);
+        assert_eq!(settings.rotation, Rotation::UpsideDown);
+        assert_eq!(sources.last().unwrap(), &dir.join("loop.toml").display().to_string());
+    }
+}
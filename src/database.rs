@@ -0,0 +1,225 @@
+use crate::keymap;
+use crate::quirks::{QuirkOverrides, QuirkProfile, Quirks};
+use crate::settings::{Rgb, Rotation, Settings};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// a local copy of https://github.com/chip-8/chip-8-database, only the fields we use are read
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RomEntry {
+    #[serde(default)]
+    pub platforms: Vec<String>,
+    #[serde(default)]
+    pub quirky_platforms: HashMap<String, QuirkOverrides>,
+    pub tickrate: Option<u32>,
+    pub colors: Option<Colors>,
+    // degrees clockwise
+    pub screen_rotation: Option<u32>,
+    #[serde(default)]
+    pub keys: BTreeMap<String, u8>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Colors {
+    #[serde(default)]
+    pub pixels: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProgramEntry {
+    title: String,
+    #[serde(default)]
+    roms: HashMap<String, RomEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PlatformEntry {
+    id: String,
+    default_tickrate: Option<u32>,
+    #[serde(default)]
+    quirks: QuirkOverrides,
+}
+
+pub struct Database {
+    // programs.json, for errors in its entries
+    path: PathBuf,
+    hashes: HashMap<String, usize>,
+    programs: Vec<ProgramEntry>,
+    platforms: Vec<PlatformEntry>,
+}
+
+pub struct DatabaseMatch<'a> {
+    pub title: &'a str,
+    pub rom: &'a RomEntry,
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<T, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    return serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e));
+}
+
+// $CHIP8_DATABASE or <config dir>/chip8-rust/database
+pub fn default_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("CHIP8_DATABASE") {
+        return Some(PathBuf::from(dir));
+    }
+    return dirs::config_dir().map(|dir| dir.join("chip8-rust").join("database"));
+}
+
+impl Database {
+    // reads sha1-hashes.json, programs.json and the optional platforms.json from the database directory
+    pub fn load(dir: &Path) -> Result<Database, String> {
+        let hashes = read_json(&dir.join("sha1-hashes.json"))?;
+        let path = dir.join("programs.json");
+        let programs = read_json(&path)?;
+        let platforms_path = dir.join("platforms.json");
+        let platforms = if platforms_path.exists() {
+            read_json(&platforms_path)?
+        } else {
+            Vec::new()
+        };
+
+        return Ok(Database {
+            path,
+            hashes,
+            programs,
+            platforms,
+        });
+    }
+
+    pub fn lookup(&self, sha1: &str) -> Option<DatabaseMatch<'_>> {
+        let program = self.programs.get(*self.hashes.get(sha1)?)?;
+        let rom = program.roms.get(sha1)?;
+        return Some(DatabaseMatch {
+            title: &program.title,
+            rom,
+        });
+    }
+
+    // the ROM entry overrides the quirks and tickrate of its first listed platform
+    pub fn apply(&self, found: &DatabaseMatch, settings: &mut Settings) -> Result<(), String> {
+        settings.title = Some(found.title.to_string());
+
+        if let Some(platform_id) = found.rom.platforms.first() {
+            let platform = self.platforms.iter().find(|p| &p.id == platform_id);
+            if let Some(profile) = QuirkProfile::for_platform(platform_id) {
+                settings.quirks = Some(profile.quirks());
+            }
+            if let Some(platform) = platform {
+                platform.quirks.apply(settings.quirks.get_or_insert_with(Quirks::default));
+                if let Some(tickrate) = platform.default_tickrate {
+                    settings.frequency = tickrate as f32 * 60.0;
+                }
+            }
+            if let Some(overrides) = found.rom.quirky_platforms.get(platform_id) {
+                overrides.apply(settings.quirks.get_or_insert_with(Quirks::default));
+            }
+        }
+
+        if let Some(tickrate) = found.rom.tickrate {
+            settings.frequency = tickrate as f32 * 60.0;
+        }
+
+        if let Some(colors) = &found.rom.colors {
+            let pixels: Vec<Rgb> = colors.pixels.iter().filter_map(|c| c.parse().ok()).collect();
+            if pixels.len() >= 2 {
+                settings.palette.background = pixels[0];
+                settings.palette.foreground = pixels[1];
+            }
+            if pixels.len() >= 4 {
+                settings.palette.plane2 = pixels[2];
+                settings.palette.blend = pixels[3];
+            }
+        }
+        if let Some(rotation) = found.rom.screen_rotation.and_then(|degrees| Rotation::from_degrees(degrees).ok()) {
+            settings.rotation = rotation;
+        }
+
+        for (button, key) in found.rom.keys.iter() {
+            keymap::check_button(button, *key)
+                .map_err(|e| format!("{}: '{}': {}", self.path.display(), found.title, e))?;
+            settings.buttons.insert(button.clone(), *key);
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::QuirkProfile;
+    use tempfile::TempDir;
+
+    const SHA1: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    // a database with one program in a directory of its own, deleted when it is dropped
+    fn directory(rom: &str) -> TempDir {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::write(dir.join("sha1-hashes.json"), format!(r#"{{"{}": 0}}"#, SHA1)).unwrap();
+        let programs = format!(r#"[{{"title": "Test Game", "roms": {{"{}": {}}}}}]"#, SHA1, rom);
+        fs::write(dir.join("programs.json"), programs).unwrap();
+        let platforms = r#"[{"id": "superchip", "defaultTickrate": 30, "quirks": {"vblank": true}}]"#;
+        fs::write(dir.join("platforms.json"), platforms).unwrap();
+        return temp;
+    }
+
+    #[test]
+    fn looks_roms_up_by_hash() {
+        let database = Database::load(directory(r#"{"platforms": ["originalChip8"]}"#).path()).unwrap();
+        let found = database.lookup(SHA1).unwrap();
+        assert_eq!(found.title, "Test Game");
+        assert_eq!(found.rom.platforms, ["originalChip8"]);
+        assert!(database.lookup(&SHA1.replace('0', "f")).is_none());
+    }
+
+    #[test]
+    fn a_missing_database_is_an_error() {
+        assert!(Database::load(Path::new("/nonexistent")).is_err());
+    }
+
+    #[test]
+    fn the_rom_entry_beats_its_platform() {
+        let rom = r##"{
+            "platforms": ["superchip", "xochip"],
+            "quirkyPlatforms": {"superchip": {"shift": false}},
+            "colors": {"pixels": ["#102030", "#405060"]},
+            "screenRotation": 90,
+            "keys": {"up": 5}
+        }"##;
+        let database = Database::load(directory(rom).path()).unwrap();
+        let mut settings = Settings::default();
+        database.apply(&database.lookup(SHA1).unwrap(), &mut settings).unwrap();
+
+        let mut quirks = QuirkProfile::Schip.quirks();
+        quirks.display_wait = true;
+        quirks.shifting = false;
+        assert_eq!(settings.quirks, Some(quirks));
+        assert_eq!(settings.frequency, 1800.0);
+        assert_eq!(settings.title.as_deref(), Some("Test Game"));
+        assert_eq!(settings.palette.background, Rgb(0x10, 0x20, 0x30));
+        assert_eq!(settings.palette.foreground, Rgb(0x40, 0x50, 0x60));
+        assert_eq!(settings.rotation, Rotation::Clockwise);
+        assert_eq!(settings.buttons["up"], 5);
+
+        let rom = r#"{"platforms": ["superchip"], "tickrate": 15}"#;
+        let database = Database::load(directory(rom).path()).unwrap();
+        let mut settings = Settings::default();
+        database.apply(&database.lookup(SHA1).unwrap(), &mut settings).unwrap();
+        assert_eq!(settings.frequency, 900.0);
+    }
+
+    #[test]
+    fn a_bad_button_is_an_error_naming_the_file() {
+        let dir = directory(r#"{"keys": {"up": 18}}"#);
+        let database = Database::load(dir.path()).unwrap();
+        let error = database.apply(&database.lookup(SHA1).unwrap(), &mut Settings::default()).unwrap_err();
+        assert!(error.starts_with(&dir.path().join("programs.json").display().to_string()), "{}", error);
+        assert!(error.contains("invalid key 18"));
+    }
+}
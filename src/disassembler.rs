@@ -1,105 +1,373 @@
 use crate::cpu::{IOpCode, OpCode};
+use crate::rom::Rom;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
 
-pub(crate) fn disassemble_instruction(code: OpCode, pc: usize) {
-    let first_nib = code.nib1();
+// programs are loaded into RAM at this address
+const PROGRAM_START: usize = 0x200;
 
-    print!("{:04x} {:02x} {:02x}     ", pc, code.high, code.low);
-    match first_nib {
-        0x00 => match code.low {
-            0xe0 => print!("CLS"),
-            0xee => print!("RTS"),
-            _ => print!(""),
+// maximum number of bytes emitted on a single data line
+const DATA_BYTES_PER_LINE: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineKind {
+    Code,
+    Data,
+}
+
+#[derive(Clone, Debug)]
+pub struct Line {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub label: Option<String>,
+    pub kind: LineKind,
+    pub text: String,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hex: Vec<String> = self.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        return write!(f, "{:04x} {:<8} {}", self.address, hex.join(" "), self.text);
+    }
+}
+
+// how an instruction hands control to the instructions that may run after it
+enum Flow {
+    Next,
+    Skip,
+    Jump(u16),
+    Call(u16),
+    Stop,
+}
+
+fn flow(code: &OpCode) -> Flow {
+    match code.nib1() {
+        0x00 if code.low == 0xee || code.low == 0xfd => Flow::Stop,
+        0x01 => Flow::Jump(code.nib_l3()),
+        0x02 => Flow::Call(code.nib_l3()),
+        0x05 if code.nib4() != 0 => Flow::Next,
+        0x03 | 0x04 | 0x05 | 0x09 | 0x0e => Flow::Skip,
+        // the target of JUMP0 depends on V0 at runtime so we can't follow it
+        0x0b => Flow::Stop,
+        _ => Flow::Next,
+    }
+}
+
+// returns the Octo source for a single instruction or None if the opcode is not a CHIP-8, SUPER-CHIP
+// or XO-CHIP instruction. The 4 byte XO-CHIP `i := long NNNN` is handled by `decode_at`
+pub fn disassemble_instruction(code: &OpCode, labels: &BTreeMap<u16, String>) -> Option<String> {
+    let x = code.nib2();
+    let y = code.nib3();
+    let target = |address: u16| match labels.get(&address) {
+        Some(label) => label.clone(),
+        None => format!("0x{:03x}", address),
+    };
+
+    let text = match code.nib1() {
+        0x00 => match code.combine() {
+            0x00e0 => String::from("clear"),
+            0x00ee => String::from("return"),
+            0x00c0..=0x00cf => format!("scroll-down 0x{:x}", code.nib4()),
+            0x00d0..=0x00df => format!("scroll-up 0x{:x}", code.nib4()),
+            0x00fb => String::from("scroll-right"),
+            0x00fc => String::from("scroll-left"),
+            0x00fd => String::from("exit"),
+            0x00fe => String::from("lores"),
+            0x00ff => String::from("hires"),
+            _ => return None,
         },
-        0x01 => {
-            let address = code.nib_l3();
-            print!("JUMP #{:02x}", address);
-        }
-        0x02 => {
-            let address = code.nib_l3();
-            print!("CALL #{:02x}", address);
-        }
-        0x03 => {
-            let register = code.nib2();
-            print!("SKIP.EQ V{:01x}, #{:02x}", register, code.low);
-        }
-        0x04 => {
-            let register = code.nib2();
-            print!("SKIP.NE V{:01x}, #{:02x}", register, code.low);
-        }
-        0x05 => {
-            let reg_x = code.nib2();
-            let reg_y = code.nib3();
-            print!("SKIP.EQ V{:01x}, V{:01x}", reg_x, reg_y);
-        }
-        0x06 => {
-            let register = code.nib2();
-            print!("MVI V{:01x}, {:02x}", register, code.low);
-        }
-        0x07 => {
-            let register = code.nib2();
-            print!("ADD. V{:01x}, {:02x}", register, code.low);
+        0x01 => format!("jump {}", target(code.nib_l3())),
+        0x02 => match labels.get(&code.nib_l3()) {
+            Some(label) => label.clone(),
+            None => format!(":call 0x{:03x}", code.nib_l3()),
+        },
+        0x03 => format!("if v{:x} != 0x{:02x} then", x, code.low),
+        0x04 => format!("if v{:x} == 0x{:02x} then", x, code.low),
+        0x05 => match code.nib4() {
+            0x0 => format!("if v{:x} != v{:x} then", x, y),
+            0x2 => format!("save v{:x} - v{:x}", x, y),
+            0x3 => format!("load v{:x} - v{:x}", x, y),
+            _ => return None,
+        },
+        0x06 => format!("v{:x} := 0x{:02x}", x, code.low),
+        0x07 => format!("v{:x} += 0x{:02x}", x, code.low),
+        0x08 => match code.nib4() {
+            0x0 => format!("v{:x} := v{:x}", x, y),
+            0x1 => format!("v{:x} |= v{:x}", x, y),
+            0x2 => format!("v{:x} &= v{:x}", x, y),
+            0x3 => format!("v{:x} ^= v{:x}", x, y),
+            0x4 => format!("v{:x} += v{:x}", x, y),
+            0x5 => format!("v{:x} -= v{:x}", x, y),
+            0x6 => format!("v{:x} >>= v{:x}", x, y),
+            0x7 => format!("v{:x} =- v{:x}", x, y),
+            0xe => format!("v{:x} <<= v{:x}", x, y),
+            _ => return None,
+        },
+        0x09 if code.nib4() == 0 => format!("if v{:x} == v{:x} then", x, y),
+        0x0a => format!("i := 0x{:03x}", code.nib_l3()),
+        0x0b => format!("jump0 0x{:03x}", code.nib_l3()),
+        0x0c => format!("v{:x} := random 0x{:02x}", x, code.low),
+        0x0d => format!("sprite v{:x} v{:x} 0x{:x}", x, y, code.nib4()),
+        0x0e => match code.low {
+            0x9e => format!("if v{:x} -key then", x),
+            0xa1 => format!("if v{:x} key then", x),
+            _ => return None,
+        },
+        0x0f => match code.low {
+            0x01 => format!("plane 0x{:x}", x),
+            0x02 if x == 0 => String::from("audio"),
+            0x07 => format!("v{:x} := delay", x),
+            0x0a => format!("v{:x} := key", x),
+            0x15 => format!("delay := v{:x}", x),
+            0x18 => format!("buzzer := v{:x}", x),
+            0x1e => format!("i += v{:x}", x),
+            0x29 => format!("i := hex v{:x}", x),
+            0x30 => format!("i := bighex v{:x}", x),
+            0x33 => format!("bcd v{:x}", x),
+            0x55 => format!("save v{:x}", x),
+            0x3a => format!("pitch := v{:x}", x),
+            0x65 => format!("load v{:x}", x),
+            0x75 => format!("saveflags v{:x}", x),
+            0x85 => format!("loadflags v{:x}", x),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    return Some(text);
+}
+
+fn opcode_at(bytes: &[u8], offset: usize) -> Option<OpCode> {
+    if offset + 1 >= bytes.len() {
+        return None;
+    }
+    return Some(OpCode {
+        high: bytes[offset],
+        low: bytes[offset + 1],
+    });
+}
+
+// decodes the instruction at an offset into the ROM returning its length and source
+fn decode_at(bytes: &[u8], offset: usize, labels: &BTreeMap<u16, String>) -> Option<(usize, String)> {
+    let opcode = opcode_at(bytes, offset)?;
+    if opcode.combine() == 0xf000 {
+        let address = opcode_at(bytes, offset + 2)?.combine();
+        return Some((4, format!("i := long 0x{:04x}", address)));
+    }
+    return Some((2, disassemble_instruction(&opcode, labels)?));
+}
+
+// the length of the instruction at an offset, skips jump over all 4 bytes of `i := long`
+fn length_at(bytes: &[u8], offset: usize) -> usize {
+    match opcode_at(bytes, offset) {
+        Some(opcode) if opcode.combine() == 0xf000 => 4,
+        _ => 2,
+    }
+}
+
+// follows every reachable path from the entry point and returns the addresses of all instructions
+// along with the addresses that are the target of a jump or call
+fn trace(bytes: &[u8]) -> (BTreeSet<usize>, BTreeMap<usize, bool>) {
+    let end = PROGRAM_START + bytes.len();
+    let mut code = BTreeSet::new();
+    // target address -> true if it is called as a subroutine
+    let mut targets = BTreeMap::new();
+    let mut pending = vec![PROGRAM_START];
+
+    while let Some(address) = pending.pop() {
+        if address < PROGRAM_START || address >= end || code.contains(&address) {
+            continue;
         }
-        0x08 => {
-            let reg_x = code.nib2();
-            let reg_y = code.nib3();
-            match code.nib4() {
-                0 => print!("MOV V{:01x}, V{:01x}", reg_x, reg_y),
-                1 => print!("OR V{:01x}, V{:01x}", reg_x, reg_y),
-                2 => print!("AND V{:01x}, V{:01x}", reg_x, reg_y),
-                3 => print!("XOR V{:01x}, V{:01x}", reg_x, reg_y),
-                4 => print!("ADD. V{:01x}, V{:01x}", reg_x, reg_y),
-                5 => print!("SUB. V{:01x}, V{:01x}", reg_x, reg_y),
-                6 => print!("SHR. V{:01x}", reg_x),
-                7 => print!("SUBB. V{:01x}, V{:01x}", reg_x, reg_y),
-                8 => print!("SHL. V{:01x}", reg_x),
-                _ => print!(""),
+        let offset = address - PROGRAM_START;
+        let length = match decode_at(bytes, offset, &BTreeMap::new()) {
+            Some((length, _)) => length,
+            None => continue,
+        };
+        let opcode = opcode_at(bytes, offset).unwrap();
+        code.insert(address);
+
+        match flow(&opcode) {
+            Flow::Next => pending.push(address + length),
+            Flow::Skip => {
+                pending.push(address + 2);
+                pending.push(address + 2 + length_at(bytes, offset + 2));
             }
+            Flow::Jump(target) => {
+                targets.entry(usize::from(target)).or_insert(false);
+                pending.push(usize::from(target));
+            }
+            Flow::Call(target) => {
+                targets.insert(usize::from(target), true);
+                pending.push(usize::from(target));
+                pending.push(address + 2);
+            }
+            Flow::Stop => (),
         }
-        0x09 => {
-            let reg_x = code.nib2();
-            let reg_y = code.nib3();
-            print!("SKIP.NE V{:01x}, V{:01x}", reg_x, reg_y);
+    }
+
+    return (code, targets);
+}
+
+pub fn disassemble(rom: &Rom) -> Vec<Line> {
+    let bytes = &rom.bytes;
+    let end = PROGRAM_START + bytes.len();
+    let (code, targets) = trace(bytes);
+
+    let mut labels = BTreeMap::new();
+    labels.insert(PROGRAM_START as u16, String::from("main"));
+    for (&address, &is_call) in targets.iter() {
+        if address >= PROGRAM_START && address < end && address != PROGRAM_START {
+            let prefix = if is_call { "sub" } else { "label" };
+            labels.insert(address as u16, format!("{}_{:03x}", prefix, address));
         }
-        0x0a => {
-            let address = code.nib_l3();
-            print!("MVI I {:01x}", address);
+    }
+
+    let mut lines = Vec::new();
+    let mut address = PROGRAM_START;
+    while address < end {
+        let label = labels.get(&(address as u16)).cloned();
+
+        if code.contains(&address) {
+            let (length, text) = decode_at(bytes, address - PROGRAM_START, &labels).unwrap();
+            // an instruction can only be emitted if no label or other instruction starts inside of it
+            let overlapped = (address + 1..address + length)
+                .any(|inside| code.contains(&inside) || labels.contains_key(&(inside as u16)));
+            if !overlapped {
+                lines.push(Line {
+                    address: address as u16,
+                    bytes: bytes[address - PROGRAM_START..address - PROGRAM_START + length].to_vec(),
+                    label,
+                    kind: LineKind::Code,
+                    text,
+                });
+                address += length;
+                continue;
+            }
         }
-        0x0b => {
-            let address = code.nib_l3();
-            print!("JUMP #{:02x}(V0)", address);
+
+        // everything else is emitted as raw bytes up to the next instruction or label
+        let start = address;
+        address += 1;
+        while address < end
+            && address - start < DATA_BYTES_PER_LINE
+            && !code.contains(&address)
+            && !labels.contains_key(&(address as u16))
+        {
+            address += 1;
         }
-        0x0c => {
-            let register = code.nib2();
-            print!("li V{:01x}, rand() & {:01x}", register, code.low);
+        let data = bytes[start - PROGRAM_START..address - PROGRAM_START].to_vec();
+        let text: Vec<String> = data.iter().map(|b| format!("0x{:02x}", b)).collect();
+        lines.push(Line {
+            address: start as u16,
+            bytes: data,
+            label,
+            kind: LineKind::Data,
+            text: text.join(" "),
+        });
+    }
+
+    return lines;
+}
+
+// renders the lines as Octo source which assembles back into the original ROM
+pub fn to_source(lines: &[Line]) -> String {
+    let mut source = String::new();
+    for line in lines {
+        if let Some(label) = &line.label {
+            source.push_str(&format!("\n: {}\n", label));
         }
-        0x0d => {
-            let reg_x = code.nib2();
-            let reg_y = code.nib3();
-            print!("SPRITE V{:01x}, V{:01x}, {:01x}", reg_x, reg_y, code.nib4());
+        let hex: Vec<String> = line.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        source.push_str(&format!(
+            "\t{:<24} # {:04x}  {}\n",
+            line.text,
+            line.address,
+            hex.join(" ")
+        ));
+    }
+    return source.trim_start().to_string();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+    use proptest::prelude::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn rom(bytes: &[u8]) -> Rom {
+        return Rom { bytes: bytes.to_vec(), cartridge: None };
+    }
+
+    fn round_trip(bytes: &[u8]) -> Vec<u8> {
+        let source = to_source(&disassemble(&rom(bytes)));
+        match assembler::assemble(&source) {
+            Ok(assembly) => return assembly.bytes,
+            Err(e) => panic!("{}\n{}", e, source),
         }
-        0x0e => {
-            let register = code.high & 0x0f;
-            match code.low {
-                0x9e => print!("SKIP.KEY V{:01x}", register),
-                0xa1 => print!("SKIP.NOKEY V{:01x}", register),
-                _ => print!(""),
+    }
+
+    fn line_at(lines: &[Line], address: u16) -> &Line {
+        return lines.iter().find(|line| line.address == address).unwrap();
+    }
+
+    #[test]
+    fn every_test_rom_assembles_back_to_the_same_bytes() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test");
+        let mut count = 0;
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|e| e == "ch8") {
+                let bytes = fs::read(&path).unwrap();
+                assert!(round_trip(&bytes) == bytes, "{}", path.display());
+                count += 1;
             }
         }
-        0x0f => {
-            let register = code.high & 0x0f;
-            match code.low {
-                0x07 => print!("MOV V{:01x}, DELAY", register),
-                0x0a => print!("WAITKEY V{:01x}", register),
-                0x15 => print!("MOV DELAY, V{:01x}", register),
-                0x18 => print!("MOV SOUND, V{:01x}", register),
-                0x1e => print!("ADD I, V{:01x}", register),
-                0x29 => print!("SPRITECHAR V{:01x}", register),
-                0x33 => print!("MOVBCD V{:01x}", register),
-                0x55 => print!("MOVM (I), V0-V{:01x}", register),
-                0x65 => print!("MOVM V0-V{:01x}, (I)", register),
-                _ => print!(""),
-            }
+        assert!(count > 0);
+    }
+
+    #[test]
+    fn labels_jump_and_call_targets() {
+        // call 0x206, jump 0x204, ..., return
+        let lines = disassemble(&rom(&[0x22, 0x06, 0x12, 0x04, 0x12, 0x04, 0x00, 0xee]));
+        assert_eq!(line_at(&lines, 0x200).label.as_deref(), Some("main"));
+        assert_eq!(line_at(&lines, 0x200).text, "sub_206");
+        assert_eq!(line_at(&lines, 0x202).text, "jump label_204");
+        assert_eq!(line_at(&lines, 0x204).label.as_deref(), Some("label_204"));
+        assert_eq!(line_at(&lines, 0x206).label.as_deref(), Some("sub_206"));
+        assert_eq!(line_at(&lines, 0x206).text, "return");
+        assert!(lines.iter().all(|line| line.kind == LineKind::Code));
+    }
+
+    #[test]
+    fn bytes_that_are_never_reached_are_data() {
+        // jump over two bytes, then skip into the middle of `i := long`
+        let lines = disassemble(&rom(&[0x12, 0x04, 0xab, 0xcd, 0x00, 0xfd]));
+        assert_eq!(line_at(&lines, 0x202).kind, LineKind::Data);
+        assert_eq!(line_at(&lines, 0x202).text, "0xab 0xcd");
+        assert_eq!(line_at(&lines, 0x204).text, "exit");
+
+        // an odd byte at the end can't be an instruction
+        let lines = disassemble(&rom(&[0x00, 0xfd, 0x01]));
+        assert_eq!(line_at(&lines, 0x202).kind, LineKind::Data);
+
+        // nothing follows a jump0, its target isn't known
+        let lines = disassemble(&rom(&[0xb3, 0x00, 0x00, 0xe0]));
+        assert_eq!(line_at(&lines, 0x202).kind, LineKind::Data);
+    }
+
+    #[test]
+    fn skips_reach_both_ways_and_step_over_long() {
+        // if v0 == 0 then i := long 0x1234, exit
+        let lines = disassemble(&rom(&[0x30, 0x00, 0xf0, 0x00, 0x12, 0x34, 0x00, 0xfd]));
+        assert_eq!(line_at(&lines, 0x202).text, "i := long 0x1234");
+        assert_eq!(line_at(&lines, 0x202).bytes.len(), 4);
+        assert_eq!(line_at(&lines, 0x206).kind, LineKind::Code);
+    }
+
+    proptest! {
+        #[test]
+        fn any_bytes_assemble_back(bytes in prop::collection::vec(any::<u8>(), 1..256)) {
+            prop_assert_eq!(round_trip(&bytes), bytes);
         }
-        _ => print!("impossible first nib"),
     }
 }
@@ -0,0 +1,318 @@
+use crate::cpu::{Program, Runnable};
+use crate::machine::Framebuffer;
+use crate::platform::{create_contexts, Command, Event, KeyInput, Platform, PlatformContext, ScreenUpdate};
+use crate::screenshot;
+use crate::settings::{Palette, Rotation, Settings};
+use crate::recording::{Recorder, RecordingFormat};
+use crate::upscale::{Filter, Image};
+use crossbeam_channel::TryRecvError;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// how many frames a `tap` holds its key down for
+const TAP_FRAMES: u64 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyAction {
+    Down,
+    Up,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub frame: u64,
+    pub key: u8,
+    pub action: KeyAction,
+}
+
+// key presses at fixed frames, one per line:
+//
+//   # frame action key
+//   30 down 5
+//   45 up 5
+//   90 tap a
+#[derive(Clone, Debug, Default)]
+pub struct KeyScript {
+    events: Vec<KeyEvent>,
+}
+
+impl KeyScript {
+    pub fn parse(text: &str) -> Result<KeyScript, String> {
+        let mut events = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", number + 1, message);
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.len() != 3 {
+                return Err(error(format!("expected 'frame action key' but found '{}'", line)));
+            }
+            let frame = words[0]
+                .parse::<u64>()
+                .map_err(|_| error(format!("invalid frame number '{}'", words[0])))?;
+            let key = u8::from_str_radix(words[2], 16)
+                .ok()
+                .filter(|key| *key < 16)
+                .ok_or_else(|| error(format!("invalid key '{}' (expected 0-F)", words[2])))?;
+
+            match words[1] {
+                "down" => events.push(KeyEvent { frame, key, action: KeyAction::Down }),
+                "up" => events.push(KeyEvent { frame, key, action: KeyAction::Up }),
+                "tap" => {
+                    events.push(KeyEvent { frame, key, action: KeyAction::Down });
+                    events.push(KeyEvent { frame: frame + TAP_FRAMES, key, action: KeyAction::Up });
+                }
+                action => return Err(error(format!("unknown action '{}' (expected down, up or tap)", action))),
+            }
+        }
+
+        // events on the same frame keep the order they were written in
+        events.sort_by_key(|event| event.frame);
+        return Ok(KeyScript { events });
+    }
+
+    pub fn load(path: &Path) -> Result<KeyScript, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        return KeyScript::parse(&text).map_err(|e| format!("{}:{}", path.display(), e));
+    }
+}
+
+// a Platform without a window, keys come from a script and every screen the emulator draws is kept
+pub struct HeadlessPlatform {
+    running: bool,
+    frame: u64,
+
+    script: KeyScript,
+    next_event: usize,
+    keyboard_state: [u8; 16],
+    sounding: bool,
+
+    // every change to the screen the emulator sent along with the frame it was drawn in
+    pub frames: Vec<(u64, ScreenUpdate)>,
+    // number of frames the sound timer was running for
+    pub sound_frames: u64,
+    pub errors: Vec<String>,
+
+    dump_frames: BTreeSet<u64>,
+    dump_dir: PathBuf,
+    screen: Framebuffer,
+    palette: Palette,
+    rotation: Rotation,
+    filter: Filter,
+    scale: u32,
+}
+
+impl HeadlessPlatform {
+    pub fn new(settings: &Settings) -> Self {
+        return Self {
+            running: false,
+            frame: 0,
+            script: KeyScript::default(),
+            next_event: 0,
+            keyboard_state: [0u8; 16],
+            sounding: false,
+            frames: Vec::new(),
+            sound_frames: 0,
+            errors: Vec::new(),
+            dump_frames: BTreeSet::new(),
+            dump_dir: PathBuf::from("."),
+            screen: Framebuffer::default(),
+            palette: settings.palette,
+            rotation: settings.rotation,
+            filter: settings.filter,
+            scale: settings.scale.unwrap_or(10),
+        };
+    }
+
+    pub fn set_script(&mut self, script: KeyScript) {
+        self.script = script;
+        self.next_event = 0;
+    }
+
+    // writes the screen at the end of each of these frames to <dir>/frame-NNNNNN.png
+    pub fn dump(&mut self, frames: &[u64], dir: &Path) {
+        self.dump_frames = frames.iter().copied().collect();
+        self.dump_dir = dir.to_path_buf();
+    }
+
+    // the screen as it was at the end of the last frame
+    pub fn screen(&self) -> &Framebuffer {
+        return &self.screen;
+    }
+
+    // the last frame as a PNG
+    pub fn save_screenshot(&self, path: &Path) -> Result<(), String> {
+        let image = screenshot::screen_image(&self.screen, &self.palette, self.rotation, self.filter, self.scale);
+        return screenshot::save_png(path, &image);
+    }
+
+    // every screen the emulator drew, starting from the blank one
+    pub fn save_recording(&self, path: &Path) -> Result<(), String> {
+        let mut recorder = Recorder::new(self.filter, self.scale);
+        let mut screen = Framebuffer::default();
+        recorder.add(0, &Image::from_screen(&screen, &self.palette).rotated(self.rotation));
+        for (frame, update) in self.frames.iter() {
+            update.apply(&mut screen);
+            recorder.add(*frame, &Image::from_screen(&screen, &self.palette).rotated(self.rotation));
+        }
+        return recorder.save(path, RecordingFormat::for_path(path), self.frame);
+    }
+
+    // runs the program on this thread one frame at a time so scripted keys land on exact frames
+    pub fn run(&mut self, program: &mut Program) {
+        let (platform_context, cpu_context) = create_contexts();
+        self.running = true;
+        loop {
+            Platform::update(self, &platform_context);
+            if !program.run_frame(&cpu_context) {
+                break;
+            }
+            Platform::render(self, &platform_context);
+        }
+        self.running = false;
+    }
+}
+
+impl Platform for HeadlessPlatform {
+    // for a program running on another thread, frames are counted at 60Hz until the program stops
+    fn start(&mut self, context: &PlatformContext) {
+        self.running = true;
+        while self.running {
+            Platform::update(self, context);
+            ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+            Platform::render(self, context);
+        }
+    }
+
+    fn update(&mut self, context: &PlatformContext) {
+        while let Some(event) = self.script.events.get(self.next_event) {
+            if event.frame > self.frame {
+                break;
+            }
+            let k = usize::from(event.key);
+            let pressed = event.action == KeyAction::Down;
+            if self.keyboard_state[k] != u8::from(pressed) {
+                self.keyboard_state[k] = u8::from(pressed);
+                let key = match pressed {
+                    true => KeyInput::press(event.key),
+                    false => KeyInput::release(event.key),
+                };
+                context.send(Command::Key(key));
+            }
+            self.next_event += 1;
+        }
+    }
+
+    fn render(&mut self, context: &PlatformContext) {
+        loop {
+            match context.events.try_recv() {
+                Ok(Event::Screen(update)) => {
+                    update.apply(&mut self.screen);
+                    self.frames.push((self.frame, update));
+                }
+                Ok(Event::Sound(on)) => self.sounding = on,
+                Ok(Event::Status(_)) | Ok(Event::State(_)) => (),
+                Ok(Event::Stopped) | Err(TryRecvError::Disconnected) => {
+                    self.running = false;
+                    break;
+                }
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+        if self.sounding {
+            self.sound_frames += 1;
+        }
+
+        if self.dump_frames.contains(&self.frame) {
+            let path = self.dump_dir.join(format!("frame-{:06}.png", self.frame));
+            let image = screenshot::screen_image(&self.screen, &self.palette, self.rotation, self.filter, self.scale);
+            if let Err(e) = screenshot::save_png(&path, &image) {
+                self.errors.push(e);
+            }
+        }
+
+        self.frame += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::load_rom;
+    use crate::rom::Rom;
+
+    fn event(frame: u64, key: u8, action: KeyAction) -> KeyEvent {
+        return KeyEvent { frame, key, action };
+    }
+
+    fn program(opcodes: &[u16]) -> Program {
+        let bytes: Vec<u8> = opcodes.iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+        let mut program = load_rom(Rom::from_bytes(&bytes).unwrap()).unwrap();
+        program.realtime = false;
+        return program;
+    }
+
+    #[test]
+    fn taps_hold_the_key_for_a_few_frames() {
+        let script = KeyScript::parse("# frame action key\n10 tap A\n\n2 down 5  # early\n20 up 5\n").unwrap();
+        assert_eq!(
+            script.events,
+            [
+                event(2, 5, KeyAction::Down),
+                event(10, 0xa, KeyAction::Down),
+                event(10 + TAP_FRAMES, 0xa, KeyAction::Up),
+                event(20, 5, KeyAction::Up),
+            ]
+        );
+    }
+
+    #[test]
+    fn script_errors_name_the_line() {
+        let error = KeyScript::parse("1 down 5\n2 down").unwrap_err();
+        assert_eq!(error, "line 2: expected 'frame action key' but found '2 down'");
+        assert!(KeyScript::parse("x down 5").unwrap_err().contains("invalid frame number"));
+        assert!(KeyScript::parse("1 down 10").unwrap_err().contains("invalid key"));
+        assert!(KeyScript::parse("1 hold 5").unwrap_err().contains("unknown action"));
+    }
+
+    #[test]
+    fn keys_are_sent_on_their_frame_and_only_when_they_change() {
+        let (platform_context, cpu_context) = create_contexts();
+        let mut platform = HeadlessPlatform::new(&Settings::default());
+        platform.set_script(KeyScript::parse("1 down 5\n1 down 5\n3 up 5\n").unwrap());
+        let mut sent = Vec::new();
+        for frame in 0..5 {
+            platform.update(&platform_context);
+            for command in cpu_context.commands.try_iter() {
+                if let Command::Key(key) = command {
+                    sent.push((frame, key.key, key.pressed));
+                }
+            }
+            platform.render(&platform_context);
+        }
+        assert_eq!(sent, [(1, 5, true), (3, 5, false)]);
+    }
+
+    #[test]
+    fn runs_a_program_with_its_script() {
+        // waits for key 5, then sounds the buzzer for 4 frames, draws a digit and loops
+        let mut program = program(&[0x6505, 0xe59e, 0x1202, 0x6004, 0xf018, 0xf029, 0xd015, 0x120e]);
+        program.frame_limit = Some(20);
+        let mut platform = HeadlessPlatform::new(&Settings::default());
+        platform.set_script(KeyScript::parse("5 tap 5").unwrap());
+        platform.run(&mut program);
+
+        assert_eq!(program.cpu.pc(), 0x20e);
+        let (frame, _) = platform.frames.last().unwrap();
+        assert_eq!(*frame, 5);
+        // the 4 at v0, v1 = 4, 0
+        assert_eq!(platform.screen().pixel(4, 0), 1);
+        assert_eq!(platform.sound_frames, 4);
+        assert!(platform.errors.is_empty());
+    }
+}
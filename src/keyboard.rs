@@ -1,55 +1,131 @@
-pub(crate) fn char_to_index(key: char) -> usize {
-    match key {
-        '0' => {
+use crate::platform::KeyInput;
+use std::time::Instant;
+
+// a point in the run: a frame and the instruction within it
+type Position = (u64, u32);
+
+// key presses and releases from the platform waiting for the instruction they belong to. The frames run in
+// bursts, so an event is placed in the frame after the one it happened during, as far into it as it was into
+// that one. A release is held back until a frame after its press, so EX9E and EXA1 see every press however
+// short it was.
+#[derive(Clone, Debug, Default)]
+pub struct KeyQueue {
+    // the keys as the events applied so far left them
+    held: [u8; 16],
+    // the key released last, until FX0A takes it
+    released: Option<u8>,
+    // events in the order they were sent, with where they apply
+    pending: Vec<(Position, KeyInput)>,
+    // the keys once every pending event is applied, where each was last pressed and where its last event goes
+    queued: [u8; 16],
+    pressed_at: [Position; 16],
+    last: [Position; 16],
+    // the frame the events go into, its instructions and the wall clock time the frame before it covered
+    frame: u64,
+    instructions: u32,
+    window: Option<(Instant, Instant)>,
+    started: Option<Instant>,
+}
+
+impl KeyQueue {
+    pub fn held(&self) -> &[u8; 16] {
+        return &self.held;
+    }
+
+    pub fn released(&self) -> Option<u8> {
+        return self.released;
+    }
+
+    pub fn take_released(&mut self) -> Option<u8> {
+        return self.released.take();
+    }
+
+    // called before the events for a frame are pushed, with when the frame started. Untimed, say for a
+    // headless run or a movie, every event lands at the start of the frame.
+    pub fn begin_frame(&mut self, frame: u64, instructions: u32, started: Option<Instant>) {
+        self.window = match (self.started, started) {
+            (Some(before), Some(now)) => Some((before, now)),
+            _ => None,
+        };
+        self.started = started;
+        self.frame = frame;
+        self.instructions = instructions;
+    }
+
+    pub fn push(&mut self, event: KeyInput) {
+        let k = usize::from(event.key & 0x0f);
+        let down = u8::from(event.pressed);
+        if self.queued[k] == down {
+            return;
+        }
+        let mut at = (self.frame, self.instruction(event.at)).max(self.last[k]);
+        if event.pressed {
+            self.pressed_at[k] = at;
+        } else {
+            let (frame, instruction) = self.pressed_at[k];
+            at = at.max((frame + 1, instruction));
+        }
+        self.queued[k] = down;
+        self.last[k] = at;
+        self.pending.push((at, event));
+    }
+
+    // the instruction of the frame that is as far in as the time was into the frame before
+    fn instruction(&self, at: Instant) -> u32 {
+        let last = self.instructions.saturating_sub(1);
+        let (start, end) = match self.window {
+            Some(window) => window,
+            None => return 0,
+        };
+        if at <= start || end <= start {
             return 0;
         }
-        '1' => {
-            return 1;
-        }
-        '2' => {
-            return 2;
-        }
-        '3' => {
-            return 3;
-        }
-        '4' => {
-            return 4;
-        }
-        '5' => {
-            return 5;
-        }
-        '6' => {
-            return 6;
-        }
-        '7' => {
-            return 7;
-        }
-        '8' => {
-            return 8;
-        }
-        '9' => {
-            return 9;
-        }
-        'A' => {
-            return 10;
-        }
-        'B' => {
-            return 11;
-        }
-        'C' => {
-            return 12;
-        }
-        'D' => {
-            return 13;
-        }
-        'E' => {
-            return 14;
-        }
-        'F' => {
-            return 15;
-        }
-        _ => {
-            return 0;
+        let fraction = at.duration_since(start).as_secs_f64() / end.duration_since(start).as_secs_f64();
+        return ((fraction * f64::from(self.instructions)) as u32).min(last);
+    }
+
+    // applies the events due by an instruction of the frame, the keys come back when they changed
+    pub fn apply(&mut self, instruction: u32) -> Option<[u8; 16]> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let now = (self.frame, instruction);
+        let mut changed = false;
+        let mut n = 0;
+        while n < self.pending.len() {
+            let (at, event) = self.pending[n];
+            if at > now {
+                n += 1;
+                continue;
+            }
+            self.pending.remove(n);
+            let k = usize::from(event.key & 0x0f);
+            self.held[k] = u8::from(event.pressed);
+            // FX0A takes the first key released
+            if !event.pressed && self.released.is_none() {
+                self.released = Some(event.key & 0x0f);
+            }
+            changed = true;
+        }
+        return if changed { Some(self.held) } else { None };
+    }
+
+    // key releases only count while FX0A is waiting for one
+    pub fn end_frame(&mut self, waiting_for_key: bool) {
+        if !waiting_for_key {
+            self.released = None;
         }
     }
+
+    // throws away what the platform sent in favour of keys from elsewhere, a movie being played
+    pub fn replace(&mut self, keys: [u8; 16], released: Option<u8>) {
+        self.pending.clear();
+        self.held = keys;
+        self.queued = keys;
+        self.released = released;
+    }
+
+    pub fn clear_released(&mut self) {
+        self.released = None;
+    }
 }
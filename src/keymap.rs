@@ -0,0 +1,266 @@
+use crate::settings::{KeyLayout, Settings};
+use std::collections::BTreeMap;
+
+// Physical keys are named like SDL scancodes, after what they are labelled on a US keyboard, so a binding
+// means the same place on the keyboard whatever layout it has. The COSMAC VIP keypad is
+//
+//   1 2 3 C
+//   4 5 6 D
+//   7 8 9 E
+//   A 0 B F
+//
+// and the qwerty, azerty and qwertz layouts put it on the 4x4 block of keys under 1 2 3 4.
+
+const COSMAC_KEYS: [(&str, u8); 16] = [
+    ("1", 0x1),
+    ("2", 0x2),
+    ("3", 0x3),
+    ("4", 0xC),
+    ("Q", 0x4),
+    ("W", 0x5),
+    ("E", 0x6),
+    ("R", 0xD),
+    ("A", 0x7),
+    ("S", 0x8),
+    ("D", 0x9),
+    ("F", 0xE),
+    ("Z", 0xA),
+    ("X", 0x0),
+    ("C", 0xB),
+    ("V", 0xF),
+];
+
+const HEX_KEYS: [(&str, u8); 16] = [
+    ("0", 0x0),
+    ("1", 0x1),
+    ("2", 0x2),
+    ("3", 0x3),
+    ("4", 0x4),
+    ("5", 0x5),
+    ("6", 0x6),
+    ("7", 0x7),
+    ("8", 0x8),
+    ("9", 0x9),
+    ("A", 0xA),
+    ("B", 0xB),
+    ("C", 0xC),
+    ("D", 0xD),
+    ("E", 0xE),
+    ("F", 0xF),
+];
+
+// the physical keys for the gamepad style buttons a ROM can bind to keypad keys
+fn button_key(button: &str) -> Option<&'static str> {
+    match button {
+        "up" => Some("Up"),
+        "down" => Some("Down"),
+        "left" => Some("Left"),
+        "right" => Some("Right"),
+        "a" => Some("Space"),
+        "b" => Some("Return"),
+        _ => None,
+    }
+}
+
+// physical key names to the CHIP-8 keys they press, any number of physical keys can press the same one
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Keymap {
+    pub keys: BTreeMap<String, u8>,
+}
+
+impl Keymap {
+    pub fn preset(layout: KeyLayout) -> Keymap {
+        let keys: &[(&str, u8)] = match layout {
+            KeyLayout::Hex => &HEX_KEYS,
+            KeyLayout::Qwerty | KeyLayout::Azerty | KeyLayout::Qwertz => &COSMAC_KEYS,
+        };
+        return Keymap {
+            keys: keys.iter().map(|(name, key)| (name.to_string(), *key)).collect(),
+        };
+    }
+
+    // the preset of the settings' layout, then the ROM's buttons and finally the keys bound by hand
+    pub fn for_settings(settings: &Settings) -> Keymap {
+        let mut keymap = Keymap::preset(settings.key_layout);
+        for (button, key) in settings.buttons.iter() {
+            if let Some(name) = button_key(button) {
+                keymap.keys.insert(name.to_string(), *key);
+            }
+        }
+        for (key, names) in settings.bindings.iter() {
+            keymap.bind(*key, names);
+        }
+        return keymap;
+    }
+
+    // the CHIP-8 key is pressed by these physical keys and no others
+    pub fn bind(&mut self, key: u8, names: &[String]) {
+        self.keys.retain(|_, bound| *bound != key);
+        for name in names {
+            self.keys.insert(name.clone(), key);
+        }
+    }
+}
+
+// KEY=NAME,NAME,... where KEY is a CHIP-8 key from 0 to F
+pub fn parse_binding(s: &str) -> Result<(u8, Vec<String>), String> {
+    let (key, names) = s
+        .split_once('=')
+        .ok_or_else(|| format!("invalid binding '{}' (expected KEY=NAME,NAME,...)", s))?;
+    let key = parse_key(key)?;
+    let names: Vec<String> =
+        names.split(',').map(|name| name.trim().to_string()).filter(|name| !name.is_empty()).collect();
+    return Ok((key, names));
+}
+
+// a button a ROM binds has to be one there is a physical key for, and the key one from 0 to F
+pub fn check_button(button: &str, key: u8) -> Result<(), String> {
+    if button_key(button).is_none() {
+        return Err(format!("unknown button '{}' (expected up, down, left, right, a or b)", button));
+    }
+    if key > 0xF {
+        return Err(format!("invalid key {} for button '{}' (expected 0-15)", key, button));
+    }
+    return Ok(());
+}
+
+// a CHIP-8 key from 0 to F
+pub fn parse_key(s: &str) -> Result<u8, String> {
+    return u8::from_str_radix(s.trim(), 16)
+        .ok()
+        .filter(|key| *key < 16)
+        .ok_or_else(|| format!("invalid key '{}' (expected 0-F)", s));
+}
+
+// the character a physical key types on the layout, for frontends that only see characters
+pub fn key_char(name: &str, layout: KeyLayout) -> Option<char> {
+    // the numeric keypad types the same characters on every layout
+    if let Some(digit) = name.strip_prefix("Keypad ") {
+        return single_char(digit);
+    }
+    let c = single_char(name)?;
+    let c = match layout {
+        KeyLayout::Azerty => match c {
+            'q' => 'a',
+            'a' => 'q',
+            'w' => 'z',
+            'z' => 'w',
+            'm' => ',',
+            ';' => 'm',
+            '1' => '&',
+            '2' => 'é',
+            '3' => '"',
+            '4' => '\'',
+            '5' => '(',
+            '6' => '-',
+            '7' => 'è',
+            '8' => '_',
+            '9' => 'ç',
+            '0' => 'à',
+            c => c,
+        },
+        KeyLayout::Qwertz => match c {
+            'y' => 'z',
+            'z' => 'y',
+            c => c,
+        },
+        KeyLayout::Hex | KeyLayout::Qwerty => c,
+    };
+    return Some(c);
+}
+
+fn single_char(name: &str) -> Option<char> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c.to_ascii_lowercase()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RomConfig;
+
+    fn names(keys: &[&str]) -> Vec<String> {
+        return keys.iter().map(|name| name.to_string()).collect();
+    }
+
+    // the physical keys that press a CHIP-8 key
+    fn pressing(keymap: &Keymap, key: u8) -> Vec<&str> {
+        return keymap.keys.iter().filter(|(_, bound)| **bound == key).map(|(name, _)| name.as_str()).collect();
+    }
+
+    #[test]
+    fn bindings_parse() {
+        assert_eq!(parse_binding("a=Up, W").unwrap(), (0xA, names(&["Up", "W"])));
+        assert_eq!(parse_binding("5=").unwrap(), (5, Vec::new()));
+        assert!(parse_binding("5").is_err());
+        assert!(parse_binding("10=W").is_err());
+        assert!(parse_binding("G=W").unwrap_err().contains("invalid key 'G'"));
+    }
+
+    #[test]
+    fn presets_cover_the_keypad() {
+        let cosmac = Keymap::preset(KeyLayout::Qwerty);
+        assert_eq!(cosmac, Keymap::preset(KeyLayout::Azerty));
+        assert_eq!((cosmac.keys["X"], cosmac.keys["4"], cosmac.keys["V"]), (0x0, 0xC, 0xF));
+        let hex = Keymap::preset(KeyLayout::Hex);
+        assert_eq!((hex.keys["0"], hex.keys["4"], hex.keys["A"], hex.keys.get("X")), (0x0, 0x4, 0xA, None));
+        for keymap in [cosmac, hex] {
+            for key in 0..16 {
+                assert_eq!(pressing(&keymap, key).len(), 1, "key {:x}", key);
+            }
+        }
+    }
+
+    #[test]
+    fn bindings_replace_the_keys_they_bind() {
+        let mut keymap = Keymap::preset(KeyLayout::Qwerty);
+        keymap.bind(5, &names(&["Up", "K"]));
+        assert_eq!(pressing(&keymap, 5), ["K", "Up"]);
+        assert!(!keymap.keys.contains_key("W"));
+        assert_eq!(keymap.keys["Q"], 4);
+    }
+
+    #[test]
+    fn rom_bindings_win_over_the_users() {
+        let mut settings = Settings::default();
+        let user: RomConfig = toml::from_str("[keymap]\n5 = [\"W\", \"Up\"]\n6 = [\"L\"]\n").unwrap();
+        let rom: RomConfig = toml::from_str("keys = { a = 6 }\n[keymap]\n5 = [\"K\"]\n").unwrap();
+        user.apply(&mut settings).unwrap();
+        rom.apply(&mut settings).unwrap();
+        let keymap = Keymap::for_settings(&settings);
+        assert_eq!(pressing(&keymap, 5), ["K"]);
+        // the user's binding for 6 still stands and replaces the ROM's button for it
+        assert_eq!(pressing(&keymap, 6), ["L"]);
+        assert_eq!(keymap.keys["Q"], 4);
+    }
+
+    #[test]
+    fn buttons_add_to_the_preset() {
+        let mut settings = Settings::default();
+        settings.buttons.insert(String::from("up"), 2);
+        let keymap = Keymap::for_settings(&settings);
+        assert_eq!(pressing(&keymap, 2), ["2", "Up"]);
+        assert_eq!(keymap.keys.len(), 17);
+    }
+
+    #[test]
+    fn buttons_have_to_exist_and_press_a_key() {
+        assert_eq!(check_button("up", 0xF), Ok(()));
+        // 18 isn't key 2
+        assert!(check_button("up", 18).unwrap_err().contains("invalid key 18"));
+        assert!(check_button("start", 1).unwrap_err().contains("unknown button 'start'"));
+        let rom: RomConfig = toml::from_str("[keys]\nup = 18\n").unwrap();
+        assert!(rom.apply(&mut Settings::default()).is_err());
+    }
+
+    #[test]
+    fn characters_follow_the_layout() {
+        assert_eq!(key_char("Q", KeyLayout::Azerty), Some('a'));
+        assert_eq!(key_char("Z", KeyLayout::Qwertz), Some('y'));
+        assert_eq!(key_char("Keypad 7", KeyLayout::Azerty), Some('7'));
+        assert_eq!(key_char("Up", KeyLayout::Qwerty), None);
+    }
+}
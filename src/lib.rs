@@ -0,0 +1,94 @@
+// the machine module builds without std for microcontrollers, everything else needs the std feature
+#![cfg_attr(not(feature = "std"), no_std)]
+// explicit returns and matching on results we ignore are the house style
+#![allow(clippy::needless_return, clippy::single_match)]
+
+// the emulator as a library: load a Rom from a file or from bytes, turn it into a Program and run it
+// frame by frame on any Platform. The chip8-rust binary is a command line around this.
+//
+//   let rom = Rom::from_bytes(&bytes)?;
+//   let mut program = load_rom(rom)?;
+//   program.realtime = false;
+//   HeadlessPlatform::new(&Settings::default()).run(&mut program);
+//
+// Without std there is only machine::Cpu, which is run with random numbers, keys, a screen and a clock
+// supplied by the host:
+//
+//   let mut cpu = Cpu::new(Quirks::default());
+//   cpu.load(ROM)?;
+//   let mut runner = Runner::new(cpu, 700);
+//   loop {
+//       runner.poll(&mut clock, &mut rng, &mut buttons, &mut lcd);
+//   }
+//
+// SdlPlatform needs the sdl feature, which is on by default.
+
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "std")]
+pub mod assembler;
+#[cfg(feature = "std")]
+pub mod audio;
+#[cfg(feature = "std")]
+pub mod batch;
+#[cfg(feature = "std")]
+pub mod cartridge;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "std")]
+pub mod cpu;
+#[cfg(feature = "std")]
+pub mod database;
+#[cfg(feature = "std")]
+pub mod disassembler;
+#[cfg(feature = "std")]
+pub mod headless_platform;
+#[cfg(feature = "std")]
+pub mod keyboard;
+#[cfg(feature = "std")]
+pub mod keymap;
+pub mod machine;
+#[cfg(feature = "std")]
+pub mod movie;
+#[cfg(feature = "std")]
+pub mod persistence;
+#[cfg(feature = "std")]
+pub mod platform;
+#[cfg(feature = "std")]
+pub mod quirks;
+#[cfg(feature = "std")]
+pub mod recording;
+#[cfg(feature = "std")]
+pub mod rom;
+#[cfg(feature = "std")]
+pub mod screenshot;
+#[cfg(feature = "sdl")]
+pub mod sdl_platform;
+#[cfg(feature = "std")]
+pub mod server;
+#[cfg(feature = "std")]
+pub mod settings;
+#[cfg(feature = "std")]
+pub mod terminal_platform;
+#[cfg(feature = "std")]
+pub mod upscale;
+
+pub use machine::{Clock, Cpu, Framebuffer, IOpCode, Keypad, OpCode, Quirks, Random, Runner, Screen};
+#[cfg(feature = "std")]
+pub use cpu::{init_program, load_rom, Program, Runnable};
+#[cfg(feature = "std")]
+pub use disassembler::{disassemble, disassemble_instruction, Line, LineKind};
+#[cfg(feature = "std")]
+pub use headless_platform::HeadlessPlatform;
+#[cfg(feature = "std")]
+pub use platform::{create_contexts, Command, CpuContext, Event, KeyInput, Platform, PlatformContext, Status};
+#[cfg(feature = "std")]
+pub use quirks::QuirkProfile;
+#[cfg(feature = "std")]
+pub use rom::Rom;
+#[cfg(feature = "sdl")]
+pub use sdl_platform::SdlPlatform;
+#[cfg(feature = "std")]
+pub use settings::Settings;
+#[cfg(feature = "std")]
+pub use terminal_platform::TerminalPlatform;
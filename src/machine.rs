@@ -0,0 +1,1335 @@
+use core::fmt;
+
+// the CHIP-8 machine on its own: RAM, registers, timers, the screen and the instructions. It only needs
+// core so it runs without an operating system, whoever runs it supplies random numbers, the keys,
+// something to draw on and, to run at the right speed, the time.
+
+// where programs are loaded and start running
+pub const PROGRAM_START: usize = 0x200;
+
+// the sprites for 0-9,a-f start here and are 5 bytes each
+const FONT_START: usize = 0x50;
+
+const HEX_DIGIT_SPRITES: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0x10, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+// the 8x10 SUPER-CHIP digits for FX30, Octo's A-F included, 10 bytes each right after the small font
+const BIG_FONT_START: usize = FONT_START + HEX_DIGIT_SPRITES.len();
+
+const BIG_HEX_DIGIT_SPRITES: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+// the largest screen there is, SUPER-CHIP's high resolution mode
+pub const MAX_WIDTH: usize = 128;
+pub const MAX_HEIGHT: usize = 64;
+
+// XO-CHIP draws on two planes, the colour of a pixel is the planes it is lit in
+pub const PLANES: usize = 2;
+
+// a 60Hz frame
+const FRAME_MICROS: u64 = 1_000_000 / 60;
+
+// behaviours that differ between the interpreters CHIP-8 programs were written for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    // 8XY1, 8XY2 and 8XY3 reset VF to 0
+    pub vf_reset: bool,
+    // FX55 and FX65 leave I pointing past the last register
+    pub memory: bool,
+    // DXYN waits for the next frame before drawing
+    pub display_wait: bool,
+    // sprites are clipped at the screen edges instead of wrapping around
+    pub clipping: bool,
+    // 8XY6 and 8XYE shift VX in place instead of shifting VY into VX
+    pub shifting: bool,
+    // BNNN jumps to NNN + VX instead of NNN + V0
+    pub jumping: bool,
+}
+
+// what this emulator has always done
+impl Default for Quirks {
+    fn default() -> Self {
+        return Quirks {
+            vf_reset: false,
+            memory: false,
+            display_wait: false,
+            clipping: true,
+            shifting: true,
+            jumping: false,
+        };
+    }
+}
+
+// random numbers for CXNN
+pub trait Random {
+    fn random(&mut self) -> u8;
+}
+
+// the keypad, 1 for a key that is down
+pub trait Keypad {
+    // the keys held right now, None when they haven't changed
+    fn keys(&mut self) -> Option<[u8; 16]>;
+    // a key that was released, FX0A waits for one
+    fn released(&mut self) -> Option<u8>;
+}
+
+// where the screen goes, it is only drawn after it changed and rows has a bit set for every row that did
+pub trait Screen {
+    fn draw(&mut self, screen: &Framebuffer, rows: u64);
+}
+
+// microseconds since any fixed point, only the time between calls matters
+pub trait Clock {
+    fn micros(&mut self) -> u64;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpCode {
+    pub high: u8,
+    pub low: u8,
+}
+
+pub trait IOpCode {
+    fn combine(&self) -> u16;
+
+    fn nib1(&self) -> u8;
+    fn nib2(&self) -> u8;
+    fn nib3(&self) -> u8;
+    fn nib4(&self) -> u8;
+    fn nib_l3(&self) -> u16;
+
+    fn destructure(&self) -> (u8, u8, u8, u8, u16);
+}
+
+impl IOpCode for OpCode {
+    fn combine(&self) -> u16 {
+        return (u16::from(self.high) << 8) | u16::from(self.low);
+    }
+    fn nib1(&self) -> u8 {
+        return self.high >> 4;
+    }
+    fn nib2(&self) -> u8 {
+        return self.high & 0x0f;
+    }
+    fn nib3(&self) -> u8 {
+        return self.low >> 4;
+    }
+    fn nib4(&self) -> u8 {
+        return self.low & 0x0f;
+    }
+    fn nib_l3(&self) -> u16 {
+        return (u16::from(self.high & 0x0f) << 8) | u16::from(self.low);
+    }
+
+    fn destructure(&self) -> (u8, u8, u8, u8, u16) {
+        return (
+            self.nib1(),
+            self.nib2(),
+            self.nib3(),
+            self.nib4(),
+            self.nib_l3(),
+        );
+    }
+}
+
+// methods are named after the instruction mnemonics
+#[allow(non_snake_case)]
+trait Chip8 {
+    fn CLS(&mut self);
+    fn RTS(&mut self);
+    fn JUMP(&mut self, address: u16);
+    fn CALL(&mut self, address: u16);
+    fn SKIPEQI(&mut self, reg: u8, nn: u8);
+    fn SKIPNEI(&mut self, reg: u8, nn: u8);
+    fn SKIPEQ(&mut self, reg1: u8, reg2: u8);
+    fn MVI(&mut self, reg1: u8, nn: u8);
+    fn ADDI(&mut self, reg1: u8, nn: u8);
+    fn MV(&mut self, reg1: u8, reg2: u8);
+    fn OR(&mut self, reg1: u8, reg2: u8);
+    fn AND(&mut self, reg1: u8, reg2: u8);
+    fn XOR(&mut self, reg1: u8, reg2: u8);
+    fn ADD(&mut self, reg1: u8, reg2: u8);
+    fn SUB(&mut self, reg1: u8, reg2: u8);
+    fn SHR(&mut self, reg1: u8, reg2: u8);
+    fn SUB2(&mut self, reg1: u8, reg2: u8);
+    fn SHL(&mut self, reg1: u8, reg2: u8);
+    fn SKIPNE(&mut self, reg1: u8, reg2: u8);
+    fn MVII(&mut self, nn: u16);
+    fn JUMPV0(&mut self, reg: u8, address: u16);
+    fn RAND(&mut self, reg: u8, nn: u8, random: &mut impl Random);
+    fn SPRITE(&mut self, reg1: u8, reg2: u8, height: u8);
+    fn SKIPKEY(&mut self, reg: u8);
+    fn SKIPNOKEY(&mut self, reg: u8);
+    fn MVDELAY(&mut self, reg: u8);
+    fn MVKEY(&mut self, reg: u8, keypad: &mut impl Keypad);
+    fn DELAYMV(&mut self, reg: u8);
+    fn SOUNDMV(&mut self, reg: u8);
+    fn ADDVI(&mut self, reg: u8);
+    fn SPRITECHAR(&mut self, reg: u8);
+    fn MOVBCD(&mut self, reg: u8);
+    fn MOVM(&mut self, reg: u8);
+    fn MOVMI(&mut self, reg: u8);
+    // SUPER-CHIP
+    fn SCROLLDOWN(&mut self, rows: u8);
+    fn SCROLLRIGHT(&mut self);
+    fn SCROLLLEFT(&mut self);
+    fn EXIT(&mut self);
+    fn LORES(&mut self);
+    fn HIRES(&mut self);
+    fn BIGSPRITECHAR(&mut self, reg: u8);
+    fn SAVEFLAGS(&mut self, reg: u8);
+    fn LOADFLAGS(&mut self, reg: u8);
+    // XO-CHIP
+    fn SCROLLUP(&mut self, rows: u8);
+    fn SAVERANGE(&mut self, reg1: u8, reg2: u8);
+    fn LOADRANGE(&mut self, reg1: u8, reg2: u8);
+    fn LONGI(&mut self);
+    fn PLANE(&mut self, planes: u8);
+}
+
+// the program was too big for RAM
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RomTooBig {
+    pub size: usize,
+    pub max: usize,
+}
+
+impl fmt::Display for RomTooBig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "ROM is {} bytes but only {} fit in RAM", self.size, self.max);
+    }
+}
+
+// the screen as a row of bits per line, the leftmost pixel in the top bit of a row, so a sprite is drawn
+// by shifting each of its rows into place and XORing it in
+#[derive(Clone, Debug)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    planes: [[u128; MAX_HEIGHT]; PLANES],
+    // a bit for every row that changed since the screen was last drawn
+    changed: u64,
+}
+
+impl Framebuffer {
+    // 64 or 128 pixels wide and 32 or 64 high, the sizes of every CHIP-8 there is. Being powers of two
+    // coordinates wrap around with a mask, and a row rotates within a u64 or a u128.
+    pub fn new(width: usize, height: usize) -> Self {
+        return Framebuffer {
+            width: width.clamp(MAX_WIDTH / 2, MAX_WIDTH).next_power_of_two(),
+            height: height.clamp(MAX_HEIGHT / 2, MAX_HEIGHT).next_power_of_two(),
+            planes: [[0; MAX_HEIGHT]; PLANES],
+            changed: 0,
+        };
+    }
+
+    pub fn width(&self) -> usize {
+        return self.width;
+    }
+
+    pub fn height(&self) -> usize {
+        return self.height;
+    }
+
+    // the bits of a row that are on the screen
+    fn mask(&self) -> u128 {
+        return !0u128 << (MAX_WIDTH - self.width);
+    }
+
+    fn all_rows(&self) -> u64 {
+        return u64::MAX >> (MAX_HEIGHT - self.height);
+    }
+
+    // which planes the pixel is lit in, bit 0 for the first plane
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        let bit = 1u128 << (MAX_WIDTH - 1 - x);
+        let mut value = 0;
+        for plane in 0..PLANES {
+            if self.planes[plane][y] & bit != 0 {
+                value |= 1 << plane;
+            }
+        }
+        return value;
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, value: u8) {
+        let bit = 1u128 << (MAX_WIDTH - 1 - x);
+        for plane in 0..PLANES {
+            let row = self.planes[plane][y];
+            self.set_row(plane, y, if value & (1 << plane) != 0 { row | bit } else { row & !bit });
+        }
+    }
+
+    pub fn row(&self, plane: usize, y: usize) -> u128 {
+        return self.planes[plane][y];
+    }
+
+    pub fn set_row(&mut self, plane: usize, y: usize, bits: u128) {
+        let bits = bits & self.mask();
+        if self.planes[plane][y] != bits {
+            self.planes[plane][y] = bits;
+            self.changed |= 1 << y;
+        }
+    }
+
+    // XORs a sprite into a plane with its top left corner at x, y, returns whether that turned off a lit
+    // pixel. Every row of the sprite has its leftmost pixel in the top bit, whatever goes past the edges
+    // of the screen is clipped or wraps around to the other side.
+    pub fn draw(&mut self, plane: usize, x: usize, y: usize, sprite: &[u128], wrap: bool) -> bool {
+        if sprite.is_empty() {
+            return false;
+        }
+        // rotating a row into place brings whatever went past the right edge round to the left
+        let keep = if wrap { self.mask() } else { self.mask() & (!0u128 >> x) };
+        let (wide, height) = (self.width == MAX_WIDTH, self.height);
+        let rows = &mut self.planes[plane];
+        let mut collision = 0;
+        let mut row = y;
+        for line in sprite.iter() {
+            let bits = if wide {
+                line.rotate_right(x as u32)
+            } else {
+                u128::from(((line >> 64) as u64).rotate_right(x as u32)) << 64
+            } & keep;
+            collision |= rows[row] & bits;
+            rows[row] ^= bits;
+            row += 1;
+            if row == height {
+                if !wrap {
+                    break;
+                }
+                row = 0;
+            }
+        }
+        // every row the sprite covers, the ones past the bottom wrap around to the top
+        let span = u128::from(u64::MAX >> (MAX_HEIGHT - sprite.len().min(height))) << y;
+        let span = if wrap { span | span >> height } else { span };
+        self.changed |= span as u64 & self.all_rows();
+        return collision != 0;
+    }
+
+    pub fn clear(&mut self) {
+        for plane in 0..PLANES {
+            self.clear_plane(plane);
+        }
+    }
+
+    pub fn clear_plane(&mut self, plane: usize) {
+        for y in 0..self.height {
+            self.set_row(plane, y, 0);
+        }
+    }
+
+    // moves a plane down by n rows, the rows that come in at the top are blank
+    pub fn scroll_down(&mut self, plane: usize, n: usize) {
+        for y in (0..self.height).rev() {
+            let row = if y >= n { self.planes[plane][y - n] } else { 0 };
+            self.set_row(plane, y, row);
+        }
+    }
+
+    pub fn scroll_up(&mut self, plane: usize, n: usize) {
+        for y in 0..self.height {
+            let row = if y + n < self.height { self.planes[plane][y + n] } else { 0 };
+            self.set_row(plane, y, row);
+        }
+    }
+
+    // moves a plane right by n pixels, what goes past the edge is lost
+    pub fn scroll_right(&mut self, plane: usize, n: usize) {
+        for y in 0..self.height {
+            self.set_row(plane, y, self.planes[plane][y] >> n);
+        }
+    }
+
+    pub fn scroll_left(&mut self, plane: usize, n: usize) {
+        for y in 0..self.height {
+            self.set_row(plane, y, self.planes[plane][y] << n);
+        }
+    }
+
+    // a blank screen with another resolution
+    pub fn resize(&mut self, width: usize, height: usize) {
+        *self = Framebuffer::new(width, height);
+        self.changed = self.all_rows();
+    }
+
+    // the rows that changed since the last call
+    pub fn take_changed(&mut self) -> u64 {
+        let changed = self.changed;
+        self.changed = 0;
+        return changed;
+    }
+
+    // marks every row as changed so the whole screen is drawn next time
+    pub fn redraw(&mut self) {
+        self.changed = self.all_rows();
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        return Framebuffer::new(64, 32);
+    }
+}
+
+// two screens are the same when they show the same thing, whether or not it has been drawn yet
+impl PartialEq for Framebuffer {
+    fn eq(&self, other: &Self) -> bool {
+        return self.width == other.width && self.height == other.height && self.planes == other.planes;
+    }
+}
+
+impl Eq for Framebuffer {}
+
+// what a single instruction did
+#[derive(Clone, Copy, Debug)]
+pub struct Step {
+    pub address: u16,
+    pub opcode: OpCode,
+    // the display wait quirk ends the frame once a sprite is drawn
+    pub end_frame: bool,
+}
+
+// everything about a machine that decides what it does next, for setting one up or comparing two
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct State {
+    pub ram: [u8; 4096],
+    pub registers: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub sp: u16,
+    pub delay: u8,
+    pub sound: u8,
+    pub keys: [u8; 16],
+    pub screen: Framebuffer,
+    pub waiting_for_key: bool,
+    // a bit for each plane that XO-CHIP draws on, clears and scrolls
+    pub planes: u8,
+    // the SUPER-CHIP flag registers FX75 and FX85 save to and load from
+    pub flags: [u8; 16],
+    // 00FD stopped the program
+    pub exited: bool,
+}
+
+pub struct Cpu {
+    ram: [u8; 4096],
+    gp_reg: [u8; 16],
+    i: u16,
+    delay: u8,
+    sound: u8,
+    keystate: [u8; 16],
+    screen: Framebuffer,
+    pc: u16,
+    sp: u16,
+    // one past the last byte of the program, the machine stops when it gets there
+    end: usize,
+    pub quirks: Quirks,
+    waiting_for_key: bool,
+    vblank_wait: bool,
+    planes: u8,
+    flags: [u8; 16],
+    exited: bool,
+}
+
+impl Cpu {
+    pub fn new(quirks: Quirks) -> Self {
+        let mut cpu = Cpu {
+            ram: [0u8; 4096],
+            gp_reg: [0u8; 16],
+            i: 0,
+            delay: 0,
+            sound: 0,
+            keystate: [0u8; 16],
+            screen: Framebuffer::default(),
+            pc: PROGRAM_START as u16,
+            sp: 0,
+            end: PROGRAM_START,
+            quirks,
+            waiting_for_key: false,
+            vblank_wait: false,
+            planes: 1,
+            flags: [0u8; 16],
+            exited: false,
+        };
+        cpu.ram[FONT_START..FONT_START + HEX_DIGIT_SPRITES.len()].copy_from_slice(&HEX_DIGIT_SPRITES);
+        cpu.ram[BIG_FONT_START..BIG_FONT_START + BIG_HEX_DIGIT_SPRITES.len()].copy_from_slice(&BIG_HEX_DIGIT_SPRITES);
+        return cpu;
+    }
+
+    // copies the program into RAM at 0x200 which is where programs are supposed to start
+    pub fn load(&mut self, program: &[u8]) -> Result<(), RomTooBig> {
+        let max = self.ram.len() - PROGRAM_START;
+        if program.len() > max {
+            return Err(RomTooBig {
+                size: program.len(),
+                max,
+            });
+        }
+        self.ram[PROGRAM_START..PROGRAM_START + program.len()].copy_from_slice(program);
+        self.end = PROGRAM_START + program.len();
+        return Ok(());
+    }
+
+    pub fn screen(&self) -> &Framebuffer {
+        return &self.screen;
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        return &self.gp_reg;
+    }
+
+    pub fn memory(&self) -> &[u8; 4096] {
+        return &self.ram;
+    }
+
+    // copies bytes into RAM from an address on, wrapping around at 4K like the instructions do
+    pub fn write_memory(&mut self, address: u16, bytes: &[u8]) {
+        for (n, byte) in bytes.iter().enumerate() {
+            self.poke(usize::from(address) + n, *byte);
+        }
+    }
+
+    pub fn i(&self) -> u16 {
+        return self.i;
+    }
+
+    pub fn pc(&self) -> u16 {
+        return self.pc;
+    }
+
+    pub fn delay(&self) -> u8 {
+        return self.delay;
+    }
+
+    pub fn sound(&self) -> u8 {
+        return self.sound;
+    }
+
+    pub fn waiting_for_key(&self) -> bool {
+        return self.waiting_for_key;
+    }
+
+    // the program ran off its end or exited
+    pub fn halted(&self) -> bool {
+        return self.exited || (self.pc as usize) >= self.end;
+    }
+
+    // the instruction the program counter points at
+    pub fn opcode(&self) -> Option<OpCode> {
+        if self.halted() {
+            return None;
+        }
+        return Some(self.fetch());
+    }
+
+    pub fn state(&self) -> State {
+        return State {
+            ram: self.ram,
+            registers: self.gp_reg,
+            i: self.i,
+            pc: self.pc,
+            sp: self.sp,
+            delay: self.delay,
+            sound: self.sound,
+            keys: self.keystate,
+            screen: self.screen.clone(),
+            waiting_for_key: self.waiting_for_key,
+            planes: self.planes,
+            flags: self.flags,
+            exited: self.exited,
+        };
+    }
+
+    // the end of the program stays where load put it
+    pub fn set_state(&mut self, state: &State) {
+        self.ram = state.ram;
+        self.gp_reg = state.registers;
+        self.i = state.i;
+        self.pc = state.pc;
+        self.sp = state.sp;
+        self.delay = state.delay;
+        self.sound = state.sound;
+        self.keystate = state.keys;
+        self.screen = state.screen.clone();
+        self.screen.redraw();
+        self.waiting_for_key = state.waiting_for_key;
+        self.planes = state.planes;
+        self.flags = state.flags;
+        self.exited = state.exited;
+    }
+
+    // the whole screen is drawn at the end of the frame, for a screen that lost track of it
+    pub fn redraw(&mut self) {
+        self.screen.redraw();
+    }
+
+    pub fn keys(&self) -> &[u8; 16] {
+        return &self.keystate;
+    }
+
+    pub fn set_keys(&mut self, state: &[u8; 16]) {
+        self.keystate.copy_from_slice(state);
+    }
+
+    fn get_reg(&self, reg: u8) -> u8 {
+        return self.gp_reg[usize::from(reg)];
+    }
+
+    // memory addresses wrap around at 4K like they did on the VIP
+    fn peek(&self, address: usize) -> u8 {
+        return self.ram[address & 0xfff];
+    }
+
+    fn poke(&mut self, address: usize, value: u8) {
+        self.ram[address & 0xfff] = value;
+    }
+
+    fn fetch(&self) -> OpCode {
+        return OpCode {
+            high: self.peek(usize::from(self.pc)),
+            low: self.peek(usize::from(self.pc) + 1),
+        };
+    }
+
+    // skips the next instruction, all 4 bytes of it when it is XO-CHIP's F000 NNNN
+    fn skip(&mut self) {
+        self.pc += if self.fetch().combine() == 0xf000 { 4 } else { 2 };
+    }
+
+    fn peek_stack(&self, sp: u16) -> u16 {
+        return (u16::from(self.peek(usize::from(sp))) << 8) | u16::from(self.peek(usize::from(sp) + 1));
+    }
+
+    fn set_stack(&mut self, value: u16, sp: u16) {
+        let [high, low] = value.to_be_bytes();
+        self.poke(usize::from(sp), high);
+        self.poke(usize::from(sp) + 1, low);
+    }
+
+    fn flush_screen(&mut self, screen: &mut impl Screen) {
+        let rows = self.screen.take_changed();
+        if rows != 0 {
+            screen.draw(&self.screen, rows);
+        }
+    }
+
+    // runs the next instruction, None once the program has stopped
+    pub fn step(
+        &mut self,
+        random: &mut impl Random,
+        keypad: &mut impl Keypad,
+        screen: &mut impl Screen,
+    ) -> Option<Step> {
+        if self.halted() {
+            return None;
+        }
+
+        // decode the current opcode.
+        // each is 2 bytes in big endian order
+        let address = self.pc;
+        let opcode = self.fetch();
+
+        // immediately increment the program counter
+        self.pc += 2;
+
+        if let Some(state) = keypad.keys() {
+            self.set_keys(&state);
+        }
+
+        self.execute(&opcode, random, keypad, screen);
+
+        // with the display wait quirk nothing else runs until the next frame after a sprite is drawn
+        let end_frame = self.vblank_wait;
+        self.vblank_wait = false;
+        return Some(Step {
+            address,
+            opcode,
+            end_frame,
+        });
+    }
+
+    // counts the timers down at the end of a 60Hz frame and draws the screen if it changed, returns
+    // whether the buzzer sounded during the frame
+    pub fn end_frame(&mut self, screen: &mut impl Screen) -> bool {
+        if self.delay > 0 {
+            self.delay -= 1;
+        }
+        let sound = self.sound > 0;
+        if sound {
+            self.sound -= 1;
+        }
+        self.flush_screen(screen);
+        return sound;
+    }
+
+    // runs up to this many instructions and ends the frame, returns false once the program has stopped
+    pub fn run_frame(
+        &mut self,
+        instructions: u32,
+        random: &mut impl Random,
+        keypad: &mut impl Keypad,
+        screen: &mut impl Screen,
+    ) -> bool {
+        if self.halted() {
+            return false;
+        }
+        for _ in 0..instructions {
+            match self.step(random, keypad, screen) {
+                Some(step) if step.end_frame => break,
+                Some(_) => (),
+                None => break,
+            }
+        }
+        self.end_frame(screen);
+        return true;
+    }
+
+    // FNV-1a over everything that decides what the program does next
+    pub fn checksum(&self) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut write = |bytes: &[u8]| {
+            for byte in bytes {
+                hash ^= u64::from(*byte);
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        };
+        write(&self.ram);
+        write(&self.gp_reg);
+        write(&self.i.to_be_bytes());
+        write(&[self.delay, self.sound, self.waiting_for_key as u8]);
+        write(&self.pc.to_be_bytes());
+        write(&self.sp.to_be_bytes());
+        write(&[self.screen.width() as u8, self.screen.height() as u8]);
+        for plane in 0..PLANES {
+            for y in 0..self.screen.height() {
+                write(&self.screen.row(plane, y).to_be_bytes());
+            }
+        }
+        write(&[self.planes, self.exited as u8]);
+        write(&self.flags);
+        return hash;
+    }
+
+    fn execute(
+        &mut self,
+        opcode: &OpCode,
+        random: &mut impl Random,
+        keypad: &mut impl Keypad,
+        screen: &mut impl Screen,
+    ) {
+        let (n1, n2, n3, n4, l3) = opcode.destructure();
+
+        match n1 {
+            0 => match opcode.combine() {
+                0x00c0..=0x00cf => self.SCROLLDOWN(n4),
+                0x00d0..=0x00df => self.SCROLLUP(n4),
+                0x00e0 => self.CLS(),
+                0x00ee => self.RTS(),
+                0x00fb => self.SCROLLRIGHT(),
+                0x00fc => self.SCROLLLEFT(),
+                0x00fd => self.EXIT(),
+                0x00fe => self.LORES(),
+                0x00ff => self.HIRES(),
+                _ => (),
+            },
+            1 => self.JUMP(l3),
+            2 => self.CALL(l3),
+            3 => self.SKIPEQI(n2, opcode.low),
+            4 => self.SKIPNEI(n2, opcode.low),
+            5 => match n4 {
+                0 => self.SKIPEQ(n2, n3),
+                2 => self.SAVERANGE(n2, n3),
+                3 => self.LOADRANGE(n2, n3),
+                _ => (),
+            },
+            6 => self.MVI(n2, opcode.low),
+            7 => self.ADDI(n2, opcode.low),
+            8 => match n4 {
+                0 => self.MV(n2, n3),
+                1 => self.OR(n2, n3),
+                2 => self.AND(n2, n3),
+                3 => self.XOR(n2, n3),
+                4 => self.ADD(n2, n3),
+                5 => self.SUB(n2, n3),
+                6 => self.SHR(n2, n3),
+                7 => self.SUB2(n2, n3),
+                0x0E => self.SHL(n2, n3),
+                _ => (),
+            },
+            9 if n4 == 0 => self.SKIPNE(n2, n3),
+            0x0a => self.MVII(l3),
+            0x0b => self.JUMPV0(n2, l3),
+            0x0c => self.RAND(n2, opcode.low, random),
+            0x0d => self.SPRITE(n2, n3, n4),
+            0x0e => match opcode.low {
+                0x9e => self.SKIPKEY(n2),
+                0xa1 => self.SKIPNOKEY(n2),
+                _ => (),
+            },
+            0x0f => match opcode.low {
+                0x00 if n2 == 0 => self.LONGI(),
+                0x01 => self.PLANE(n2),
+                0x07 => self.MVDELAY(n2),
+                0x0a => {
+                    // draw the screen right before we start waiting for a key
+                    self.flush_screen(screen);
+                    self.MVKEY(n2, keypad);
+                }
+                0x15 => self.DELAYMV(n2),
+                0x18 => self.SOUNDMV(n2),
+                0x1e => self.ADDVI(n2),
+                0x29 => self.SPRITECHAR(n2),
+                0x30 => self.BIGSPRITECHAR(n2),
+                0x33 => self.MOVBCD(n2),
+                0x55 => self.MOVM(n2),
+                0x65 => self.MOVMI(n2),
+                0x75 => self.SAVEFLAGS(n2),
+                0x85 => self.LOADFLAGS(n2),
+                // XO-CHIP's audio patterns (F002 and FX3A) aren't played, the buzzer sounds as it always has
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+impl Chip8 for Cpu {
+    fn CLS(&mut self) {
+        for plane in 0..PLANES {
+            if self.planes & (1 << plane) != 0 {
+                self.screen.clear_plane(plane);
+            }
+        }
+    }
+
+    fn RTS(&mut self) {
+        let return_addr = self.peek_stack(self.sp);
+        self.pc = return_addr;
+        self.sp = self.sp.wrapping_sub(2);
+    }
+
+    fn JUMP(&mut self, address: u16) {
+        self.pc = address;
+    }
+
+    fn CALL(&mut self, address: u16) {
+        self.sp = self.sp.wrapping_add(2);
+        self.set_stack(self.pc, self.sp);
+        self.pc = address;
+    }
+
+    fn SKIPEQI(&mut self, reg: u8, nn: u8) {
+        if self.get_reg(reg) == nn {
+            self.skip();
+        }
+    }
+
+    fn SKIPNEI(&mut self, reg: u8, nn: u8) {
+        if self.get_reg(reg) != nn {
+            self.skip();
+        }
+    }
+
+    fn SKIPEQ(&mut self, reg1: u8, reg2: u8) {
+        if self.get_reg(reg1) == self.get_reg(reg2) {
+            self.skip();
+        }
+    }
+
+    fn MVI(&mut self, reg1: u8, nn: u8) {
+        self.gp_reg[usize::from(reg1)] = nn;
+    }
+
+    fn ADDI(&mut self, reg1: u8, nn: u8) {
+        self.gp_reg[usize::from(reg1)] = u8::wrapping_add(self.get_reg(reg1), nn);
+    }
+
+    fn MV(&mut self, reg1: u8, reg2: u8) {
+        self.gp_reg[usize::from(reg1)] = self.gp_reg[usize::from(reg2)];
+    }
+
+    fn OR(&mut self, reg1: u8, reg2: u8) {
+        self.gp_reg[usize::from(reg1)] |= self.gp_reg[usize::from(reg2)];
+        if self.quirks.vf_reset {
+            self.gp_reg[0x0F] = 0;
+        }
+    }
+
+    fn AND(&mut self, reg1: u8, reg2: u8) {
+        self.gp_reg[usize::from(reg1)] &= self.gp_reg[usize::from(reg2)];
+        if self.quirks.vf_reset {
+            self.gp_reg[0x0F] = 0;
+        }
+    }
+
+    fn XOR(&mut self, reg1: u8, reg2: u8) {
+        self.gp_reg[usize::from(reg1)] ^= self.gp_reg[usize::from(reg2)];
+        if self.quirks.vf_reset {
+            self.gp_reg[0x0F] = 0;
+        }
+    }
+
+    fn ADD(&mut self, reg1: u8, reg2: u8) {
+        let res = u16::from(self.get_reg(reg1)) + u16::from(self.get_reg(reg2));
+        self.gp_reg[usize::from(reg1)] = (res & 0x00FF) as u8;
+        if res > 255 {
+            self.gp_reg[0x0F] = 1;
+        } else {
+            self.gp_reg[0x0F] = 0;
+        }
+    }
+
+    fn SUB(&mut self, reg1: u8, reg2: u8) {
+        let r1 = self.get_reg(reg1) as i16;
+        let r2 = self.get_reg(reg2) as i16;
+        self.gp_reg[usize::from(reg1)] = (r1 - r2) as u8;
+        // VF is 1 when there is no borrow
+        if r1 >= r2 {
+            self.gp_reg[0x0F] = 1;
+        } else {
+            self.gp_reg[0x0F] = 0;
+        }
+    }
+
+    fn SHR(&mut self, reg1: u8, reg2: u8) {
+        let val = self.get_reg(if self.quirks.shifting { reg1 } else { reg2 });
+        self.gp_reg[usize::from(reg1)] = val / 2;
+        self.gp_reg[0x0F] = val & 0x01;
+    }
+
+    fn SUB2(&mut self, reg1: u8, reg2: u8) {
+        let r1 = self.get_reg(reg1) as i16;
+        let r2 = self.get_reg(reg2) as i16;
+        self.gp_reg[usize::from(reg1)] = (r2 - r1) as u8;
+        if r2 >= r1 {
+            self.gp_reg[0x0F] = 1;
+        } else {
+            self.gp_reg[0x0F] = 0;
+        }
+    }
+
+    fn SHL(&mut self, reg1: u8, reg2: u8) {
+        let val = self.get_reg(if self.quirks.shifting { reg1 } else { reg2 });
+        self.gp_reg[usize::from(reg1)] = ((val as u16) << 1) as u8;
+        self.gp_reg[0x0F] = val >> 7;
+    }
+
+    fn SKIPNE(&mut self, reg1: u8, reg2: u8) {
+        if self.get_reg(reg1) != self.get_reg(reg2) {
+            self.skip();
+        }
+    }
+
+    fn MVII(&mut self, nn: u16) {
+        self.i = nn;
+    }
+
+    fn JUMPV0(&mut self, reg: u8, address: u16) {
+        let offset_reg = if self.quirks.jumping { reg } else { 0 };
+        self.pc = address + u16::from(self.get_reg(offset_reg));
+    }
+
+    fn RAND(&mut self, reg: u8, nn: u8, random: &mut impl Random) {
+        self.gp_reg[usize::from(reg)] = random.random() & nn;
+    }
+
+    fn SPRITE(&mut self, reg1: u8, reg2: u8, height: u8) {
+        let (width, rows) = (self.screen.width(), self.screen.height());
+        let x = usize::from(self.get_reg(reg1)) & (width - 1);
+        let y = usize::from(self.get_reg(reg2)) & (rows - 1);
+        self.gp_reg[0x0F] = 0;
+
+        // DXY0 draws 16x16 with two bytes a row
+        let (wide, height) = if height == 0 { (true, 16) } else { (false, usize::from(height)) };
+        let mut address = usize::from(self.i);
+        let mut collision = false;
+        // every plane that is drawn on has a sprite of its own, one after the other from I
+        for plane in 0..PLANES {
+            if self.planes & (1 << plane) == 0 {
+                continue;
+            }
+            // the sprite's rows with their first pixel in the top bit
+            let mut sprite = [0u128; 16];
+            for row in sprite.iter_mut().take(height) {
+                *row = if wide {
+                    let bits = u16::from_be_bytes([self.peek(address), self.peek(address + 1)]);
+                    address += 2;
+                    u128::from(bits) << (MAX_WIDTH - 16)
+                } else {
+                    address += 1;
+                    u128::from(self.peek(address - 1)) << (MAX_WIDTH - 8)
+                };
+            }
+            collision |= self.screen.draw(plane, x, y, &sprite[..height], !self.quirks.clipping);
+        }
+        // set VF to 1 if a pixel went from set to unset
+        if collision {
+            self.gp_reg[0x0F] = 1;
+        }
+
+        if self.quirks.display_wait {
+            self.vblank_wait = true;
+        }
+    }
+
+    fn SKIPKEY(&mut self, reg: u8) {
+        // only the low nibble picks the key
+        if self.keystate[usize::from(self.get_reg(reg) & 0x0f)] != 0 {
+            self.skip();
+        }
+    }
+
+    fn SKIPNOKEY(&mut self, reg: u8) {
+        if self.keystate[usize::from(self.get_reg(reg) & 0x0f)] == 0 {
+            self.skip();
+        }
+    }
+
+    fn MVDELAY(&mut self, reg: u8) {
+        self.gp_reg[reg as usize] = self.delay;
+    }
+
+    fn MVKEY(&mut self, reg: u8, keypad: &mut impl Keypad) {
+        match keypad.released() {
+            Some(key) => {
+                self.gp_reg[reg as usize] = key & 0x0f;
+                self.waiting_for_key = false;
+            }
+            None => {
+                // no key was released yet so run this instruction again next time around
+                self.pc -= 2;
+                self.waiting_for_key = true;
+            }
+        }
+    }
+
+    fn DELAYMV(&mut self, reg: u8) {
+        self.delay = self.get_reg(reg);
+    }
+
+    fn SOUNDMV(&mut self, reg: u8) {
+        self.sound = self.get_reg(reg);
+    }
+
+    fn ADDVI(&mut self, reg: u8) {
+        self.i = self.i.wrapping_add(u16::from(self.get_reg(reg)));
+    }
+
+    fn SPRITECHAR(&mut self, reg: u8) {
+        // the digit is in VX, only its low nibble counts
+        self.i = FONT_START as u16 + u16::from(self.get_reg(reg) & 0x0f) * 5;
+    }
+
+    fn MOVBCD(&mut self, reg: u8) {
+        let mut value = self.get_reg(reg);
+        let ones = value % 10;
+        value /= 10;
+        let tens = value % 10;
+        value /= 10;
+        let hundreds = value % 10;
+        let i = usize::from(self.i);
+        self.poke(i, hundreds);
+        self.poke(i + 1, tens);
+        self.poke(i + 2, ones);
+    }
+
+    fn MOVM(&mut self, reg: u8) {
+        for i in 0..(reg + 1) {
+            self.poke(usize::from(self.i) + usize::from(i), self.get_reg(i));
+        }
+        if self.quirks.memory {
+            self.i = self.i.wrapping_add(u16::from(reg) + 1);
+        }
+    }
+
+    fn MOVMI(&mut self, reg: u8) {
+        for i in 0..(reg + 1) {
+            self.gp_reg[usize::from(i)] = self.peek(usize::from(self.i) + usize::from(i));
+        }
+        if self.quirks.memory {
+            self.i = self.i.wrapping_add(u16::from(reg) + 1);
+        }
+    }
+
+    fn SCROLLDOWN(&mut self, rows: u8) {
+        for plane in 0..PLANES {
+            if self.planes & (1 << plane) != 0 {
+                self.screen.scroll_down(plane, usize::from(rows));
+            }
+        }
+    }
+
+    fn SCROLLRIGHT(&mut self) {
+        for plane in 0..PLANES {
+            if self.planes & (1 << plane) != 0 {
+                self.screen.scroll_right(plane, 4);
+            }
+        }
+    }
+
+    fn SCROLLLEFT(&mut self) {
+        for plane in 0..PLANES {
+            if self.planes & (1 << plane) != 0 {
+                self.screen.scroll_left(plane, 4);
+            }
+        }
+    }
+
+    fn EXIT(&mut self) {
+        self.exited = true;
+    }
+
+    // switching resolution starts over with a blank screen, like Octo
+    fn LORES(&mut self) {
+        self.screen.resize(MAX_WIDTH / 2, MAX_HEIGHT / 2);
+    }
+
+    fn HIRES(&mut self) {
+        self.screen.resize(MAX_WIDTH, MAX_HEIGHT);
+    }
+
+    fn BIGSPRITECHAR(&mut self, reg: u8) {
+        self.i = BIG_FONT_START as u16 + u16::from(self.get_reg(reg) & 0x0f) * 10;
+    }
+
+    fn SAVEFLAGS(&mut self, reg: u8) {
+        let n = usize::from(reg) + 1;
+        self.flags[..n].copy_from_slice(&self.gp_reg[..n]);
+    }
+
+    fn LOADFLAGS(&mut self, reg: u8) {
+        let n = usize::from(reg) + 1;
+        self.gp_reg[..n].copy_from_slice(&self.flags[..n]);
+    }
+
+    fn SCROLLUP(&mut self, rows: u8) {
+        for plane in 0..PLANES {
+            if self.planes & (1 << plane) != 0 {
+                self.screen.scroll_up(plane, usize::from(rows));
+            }
+        }
+    }
+
+    // VX to VY go to memory from I on, in reverse order when X is past Y, and I stays where it is
+    fn SAVERANGE(&mut self, reg1: u8, reg2: u8) {
+        let count = usize::from(reg1.abs_diff(reg2)) + 1;
+        for n in 0..count {
+            let reg = if reg1 <= reg2 { reg1 + n as u8 } else { reg1 - n as u8 };
+            self.poke(usize::from(self.i) + n, self.get_reg(reg));
+        }
+    }
+
+    fn LOADRANGE(&mut self, reg1: u8, reg2: u8) {
+        let count = usize::from(reg1.abs_diff(reg2)) + 1;
+        for n in 0..count {
+            let reg = if reg1 <= reg2 { reg1 + n as u8 } else { reg1 - n as u8 };
+            self.gp_reg[usize::from(reg)] = self.peek(usize::from(self.i) + n);
+        }
+    }
+
+    // F000 NNNN, the address is the instruction's second half
+    fn LONGI(&mut self) {
+        self.i = u16::from_be_bytes([self.peek(usize::from(self.pc)), self.peek(usize::from(self.pc) + 1)]);
+        self.pc += 2;
+    }
+
+    fn PLANE(&mut self, planes: u8) {
+        self.planes = planes & 0x03;
+    }
+}
+
+// runs a Cpu at its real speed by the clock, for hosts that poll it from a main loop instead of
+// giving it a thread
+pub struct Runner {
+    pub cpu: Cpu,
+    // instructions per second
+    pub frequency: u32,
+    // when the next 60Hz frame is due
+    next_frame: Option<u64>,
+}
+
+impl Runner {
+    pub fn new(cpu: Cpu, frequency: u32) -> Self {
+        return Runner {
+            cpu,
+            frequency,
+            next_frame: None,
+        };
+    }
+
+    // runs every frame that is due, returns false once the program has stopped
+    pub fn poll(
+        &mut self,
+        clock: &mut impl Clock,
+        random: &mut impl Random,
+        keypad: &mut impl Keypad,
+        screen: &mut impl Screen,
+    ) -> bool {
+        let now = clock.micros();
+        let mut next = self.next_frame.unwrap_or(now);
+        // after a stall carry on from now instead of racing to catch up
+        if now.saturating_sub(next) > 4 * FRAME_MICROS {
+            next = now;
+        }
+        while next <= now {
+            if !self.cpu.run_frame(self.frequency / 60, random, keypad, screen) {
+                return false;
+            }
+            next += FRAME_MICROS;
+        }
+        self.next_frame = Some(next);
+        return true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a host's side of things with nothing but core, the machine builds without std
+
+    struct Time(u64);
+
+    impl Clock for Time {
+        fn micros(&mut self) -> u64 {
+            return self.0;
+        }
+    }
+
+    struct Fixed(u8);
+
+    impl Random for Fixed {
+        fn random(&mut self) -> u8 {
+            return self.0;
+        }
+    }
+
+    struct Held(Option<[u8; 16]>);
+
+    impl Keypad for Held {
+        fn keys(&mut self) -> Option<[u8; 16]> {
+            return self.0.take();
+        }
+
+        fn released(&mut self) -> Option<u8> {
+            return None;
+        }
+    }
+
+    struct Draws(u32);
+
+    impl Screen for Draws {
+        fn draw(&mut self, _screen: &Framebuffer, _rows: u64) {
+            self.0 += 1;
+        }
+    }
+
+    fn runner(program: &[u8], frequency: u32) -> Runner {
+        let mut cpu = Cpu::new(Quirks::default());
+        cpu.load(program).unwrap();
+        return Runner::new(cpu, frequency);
+    }
+
+    fn poll(runner: &mut Runner, clock: &mut Time) -> bool {
+        return runner.poll(clock, &mut Fixed(0), &mut Held(None), &mut Draws(0));
+    }
+
+    // V0 counts up forever, 10 instructions a frame are 5 counts
+    const COUNT: [u8; 4] = [0x70, 0x01, 0x12, 0x00];
+
+    #[test]
+    fn frames_run_when_they_are_due() {
+        let mut runner = runner(&COUNT, 600);
+        let mut clock = Time(1_000_000);
+        assert!(poll(&mut runner, &mut clock));
+        assert_eq!(runner.cpu.registers()[0], 5);
+        clock.0 += FRAME_MICROS / 2;
+        assert!(poll(&mut runner, &mut clock));
+        assert_eq!(runner.cpu.registers()[0], 5);
+        clock.0 += FRAME_MICROS / 2;
+        assert!(poll(&mut runner, &mut clock));
+        assert_eq!(runner.cpu.registers()[0], 10);
+        // a late poll catches up on the frames it missed
+        clock.0 += 3 * FRAME_MICROS;
+        assert!(poll(&mut runner, &mut clock));
+        assert_eq!(runner.cpu.registers()[0], 25);
+    }
+
+    #[test]
+    fn a_stall_is_not_made_up_for() {
+        let mut runner = runner(&COUNT, 600);
+        let mut clock = Time(0);
+        assert!(poll(&mut runner, &mut clock));
+        clock.0 += 60 * FRAME_MICROS;
+        assert!(poll(&mut runner, &mut clock));
+        assert_eq!(runner.cpu.registers()[0], 10);
+        clock.0 += FRAME_MICROS;
+        assert!(poll(&mut runner, &mut clock));
+        assert_eq!(runner.cpu.registers()[0], 15);
+    }
+
+    #[test]
+    fn keys_random_numbers_and_the_screen_come_from_the_host() {
+        // V1 = a random number, then waits for key 5 before drawing the digit 0 and stopping
+        let program = [0xc1, 0xff, 0x65, 0x05, 0xe5, 0x9e, 0x12, 0x04, 0xf2, 0x29, 0xd0, 0x05, 0x00, 0xfd];
+        let mut runner = runner(&program, 600);
+        let (mut clock, mut random, mut screen) = (Time(0), Fixed(0x5a), Draws(0));
+        assert!(runner.poll(&mut clock, &mut random, &mut Held(None), &mut screen));
+        assert_eq!(runner.cpu.registers()[1], 0x5a);
+        assert!(!runner.cpu.halted());
+        let draws = screen.0;
+
+        let mut keys = [0u8; 16];
+        keys[5] = 1;
+        clock.0 += FRAME_MICROS;
+        assert!(runner.poll(&mut clock, &mut random, &mut Held(Some(keys)), &mut screen));
+        assert!(runner.cpu.halted());
+        assert_eq!(screen.0, draws + 1);
+        assert!(runner.cpu.screen().pixel(0, 0) > 0);
+
+        // stopped for good
+        clock.0 += FRAME_MICROS;
+        assert!(!runner.poll(&mut clock, &mut random, &mut Held(None), &mut screen));
+    }
+
+    #[test]
+    fn a_rom_too_big_for_ram_is_refused() {
+        let mut cpu = Cpu::new(Quirks::default());
+        let error = cpu.load(&[0; 4096]).unwrap_err();
+        assert_eq!((error.size, error.max), (4096, 4096 - PROGRAM_START));
+    }
+}
@@ -1,44 +1,448 @@
-mod cpu;
-mod disassembler;
-mod keyboard;
-mod platform;
-mod rom;
-mod sdl_platform;
-use cpu::{init_program, Runnable};
-use platform::{create_contexts, Platform};
-use sdl_platform::SdlPlatform;
-use std::env;
+// explicit returns and matching on results we ignore are the house style
+#![allow(clippy::needless_return, clippy::single_match)]
 
-#[macro_use]
-extern crate lazy_static;
+mod cli;
+use chip8_rust::audio::SoundCapture;
+use chip8_rust::cartridge::{self, Cartridge, OctoOptions};
+use chip8_rust::cpu::{init_program, load_rom, Program, Runnable};
+use chip8_rust::disassembler::{self, LineKind};
+use chip8_rust::headless_platform::{HeadlessPlatform, KeyScript};
+use chip8_rust::movie::MovieMode;
+use chip8_rust::platform::{create_contexts, Platform};
+use chip8_rust::recording::CaptureOptions;
+#[cfg(feature = "sdl")]
+use chip8_rust::sdl_platform::SdlPlatform;
+use chip8_rust::settings::Settings;
+use chip8_rust::terminal_platform::TerminalPlatform;
+use chip8_rust::server::Server;
+use chip8_rust::{analysis, assembler, batch, config, rom, screenshot};
+use clap::Parser;
+use cli::{Cli, Command, DisplayArgs, EmulatorArgs, HeadlessArgs, PlatformArgs};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
 
-fn main() -> Result<(), String> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Missing file arg!");
-        return Ok(());
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let result = match cli.command {
+        Command::Run {
+            rom,
+            emulator,
+            display,
+            platform,
+        } => run_rom(&rom, &emulator, &display, &platform),
+        Command::Disasm {
+            rom,
+            output,
+            listing,
+        } => disassemble_rom(&rom, output.as_deref(), listing),
+        Command::Asm { source, output } => assemble_file(&source, output.as_deref()),
+        Command::Export {
+            rom,
+            emulator,
+            display,
+            output,
+            label_frames,
+        } => export_cartridge(&rom, &emulator, &display, output.as_deref(), label_frames),
+        Command::Info { rom, emulator } => rom_info(&rom, &emulator),
+        Command::Trace {
+            rom,
+            emulator,
+            frames,
+        } => trace_rom(&rom, &emulator, frames),
+        Command::Test {
+            rom,
+            emulator,
+            frames,
+            expect,
+            headless_args,
+        } => test_rom(&rom, &emulator, frames, expect.as_deref(), &headless_args),
+        Command::Batch {
+            dir,
+            emulator,
+            frames,
+            script,
+            output,
+            baseline,
+        } => batch_roms(&dir, &emulator, frames, script.as_deref(), &output, baseline.as_deref()),
+        Command::Serve { rom, emulator, listen } => serve(rom.as_deref(), &emulator, &listen),
+    };
+
+    match result {
+        Ok(code) => code,
+        Err(message) => {
+            eprintln!("error: {}", message);
+            ExitCode::FAILURE
+        }
     }
+}
 
-    let file_name = &args[1];
+fn load_program(path: &Path, settings: &Settings) -> Result<Program, String> {
+    let mut program = init_program(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    program.configure(settings);
+    return Ok(program);
+}
 
-    let program_res = init_program(&file_name);
-    if program_res.is_err() {
-        eprint!("Error loading program!");
-        return Ok(());
+// settings come from the database, then the per-ROM override files and finally the command line
+fn configure_program(
+    path: &Path,
+    emulator: &EmulatorArgs,
+    display: Option<&DisplayArgs>,
+) -> Result<(Program, Settings, Vec<String>), String> {
+    let rom = rom::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let (mut settings, sources) = config::rom_settings(path, &rom, emulator.database.as_deref(), !emulator.no_config)?;
+    emulator.apply(&mut settings);
+    if let Some(display) = display {
+        display.apply(&mut settings);
     }
 
-    let mut program = program_res.unwrap();
+    let mut program = load_rom(rom).map_err(|e| format!("{}: {}", path.display(), e))?;
+    program.configure(&settings);
+    return Ok((program, settings, sources));
+}
+
+// runs the program as fast as possible on the HeadlessPlatform, keys only come from its script
+fn run_headless(program: &mut Program, platform: &mut HeadlessPlatform, frames: Option<u64>) -> Result<(), String> {
+    program.realtime = false;
+    program.frame_limit = frames;
+    platform.run(program);
+    if !platform.errors.is_empty() {
+        return Err(platform.errors.join("\n"));
+    }
+    return Ok(());
+}
+
+fn run_rom(
+    path: &Path,
+    emulator: &EmulatorArgs,
+    display: &DisplayArgs,
+    platform_args: &PlatformArgs,
+) -> Result<ExitCode, String> {
+    let (mut program, mut settings, _) = configure_program(path, emulator, Some(display))?;
+    platform_args.sound.apply(&mut settings);
+    if let Some(wav) = &platform_args.sound.wav {
+        let capture = SoundCapture::create(wav, settings.tone).map_err(|e| format!("{}: {}", wav.display(), e))?;
+        program.capture = Some(capture);
+    }
+    let movie_length = platform_args.movie.apply(&mut program, &settings)?;
+    let headless_args = &platform_args.headless_args;
+    let terminal_args = &platform_args.terminal_args;
+    let capture = platform_args.capture.options();
+    // a movie plays to its end unless told otherwise
+    let frames = platform_args.frames.or(movie_length);
+
+    if platform_args.headless {
+        let mut platform = HeadlessPlatform::new(&settings);
+        headless_args.apply(&mut platform)?;
+        run_headless(&mut program, &mut platform, frames)?;
+        if let Some(path) = &capture.screenshot {
+            platform.save_screenshot(path)?;
+        }
+        if let Some(path) = &capture.record {
+            platform.save_recording(path)?;
+        }
+        program.finish_movie();
+        return Ok(movie_exit_code(&program));
+    }
+    if headless_args.script.is_some() || !headless_args.dump_frames.is_empty() {
+        return Err(String::from("--script and --dump-frames only work with --headless"));
+    }
+    if terminal_args.terminal && (capture.screenshot.is_some() || capture.record.is_some()) {
+        return Err(String::from("--screenshot and --record don't work with --terminal"));
+    }
+
+    // the platform owns the main thread (SDL insists on it) so the emulator runs on its own thread
+    program.frame_limit = frames;
+    let mut platform: Box<dyn Platform> = if terminal_args.terminal {
+        let key_timeout = Duration::from_millis(terminal_args.key_timeout);
+        Box::new(TerminalPlatform::new(&settings, terminal_args.glyphs, key_timeout)?)
+    } else {
+        window_platform(&settings, capture)?
+    };
     let (platform_context, cpu_context) = create_contexts();
-    let platform_thread = std::thread::spawn(move || {
-        let mut platform = SdlPlatform::default();
-        platform.start(&platform_context);
+    let emulator = std::thread::spawn(move || {
+        program.run(&cpu_context);
+        program.finish_movie();
+        return program;
     });
 
-    program.run(&cpu_context);
+    platform.start(&platform_context);
+    // closing the window hangs up on the emulator, waiting for it lets the movie and the WAV file be finished
+    drop(platform_context);
+    match emulator.join() {
+        Ok(program) => return Ok(movie_exit_code(&program)),
+        Err(_) => return Err(String::from("the emulator thread panicked")),
+    }
+}
 
-    match platform_thread.join() {
-        Ok(_) => (),
-        Err(_) => (),
+// a movie that played back differently fails the run
+fn movie_exit_code(program: &Program) -> ExitCode {
+    if let Some(MovieMode::Play(player)) = &program.movie {
+        if player.divergence.is_some() {
+            return ExitCode::from(1);
+        }
     }
-    return Ok(());
+    return ExitCode::SUCCESS;
+}
+
+#[cfg(feature = "sdl")]
+fn window_platform(settings: &Settings, capture: CaptureOptions) -> Result<Box<dyn Platform>, String> {
+    let mut sdl_platform = SdlPlatform::new(settings)?;
+    sdl_platform.set_capture(capture);
+    return Ok(Box::new(sdl_platform));
+}
+
+#[cfg(not(feature = "sdl"))]
+fn window_platform(_settings: &Settings, _capture: CaptureOptions) -> Result<Box<dyn Platform>, String> {
+    return Err(String::from("built without the sdl feature, run with --terminal or --headless"));
+}
+
+fn disassemble_rom(path: &Path, output: Option<&Path>, listing: bool) -> Result<ExitCode, String> {
+    let program = load_program(path, &Settings::default())?;
+    let lines = program.disassemble();
+
+    let text = if listing {
+        let listing: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        listing.join("\n") + "\n"
+    } else {
+        disassembler::to_source(&lines)
+    };
+
+    match output {
+        Some(output) => fs::write(output, text).map_err(|e| format!("{}: {}", output.display(), e))?,
+        None => print!("{}", text),
+    }
+    return Ok(ExitCode::SUCCESS);
+}
+
+fn assemble_file(source_path: &Path, output: Option<&Path>) -> Result<ExitCode, String> {
+    let output_path = match output {
+        Some(output) => output.to_path_buf(),
+        None => source_path.with_extension("ch8"),
+    };
+
+    let source = fs::read_to_string(source_path).map_err(|e| format!("{}: {}", source_path.display(), e))?;
+    let assembly = assembler::assemble(&source).map_err(|e| format!("{}:{}", source_path.display(), e))?;
+
+    fs::write(&output_path, &assembly.bytes).map_err(|e| format!("{}: {}", output_path.display(), e))?;
+    let symbol_path = output_path.with_extension("sym");
+    fs::write(&symbol_path, assembly.symbol_file()).map_err(|e| format!("{}: {}", symbol_path.display(), e))?;
+    return Ok(ExitCode::SUCCESS);
+}
+
+// cartridges loaded from a GIF keep their original source, anything else is disassembled
+fn export_cartridge(
+    path: &Path,
+    emulator: &EmulatorArgs,
+    display: &DisplayArgs,
+    output: Option<&Path>,
+    label_frames: u64,
+) -> Result<ExitCode, String> {
+    let output_path = match output {
+        Some(output) => output.to_path_buf(),
+        None => path.with_extension("gif"),
+    };
+    if output_path == path {
+        return Err(format!("{}: refusing to overwrite the input, pass --output", path.display()));
+    }
+
+    let (mut program, settings, _) = configure_program(path, emulator, Some(display))?;
+    let mut options = OctoOptions::from_settings(&settings, &program.cpu.quirks);
+    let source = match &program.rom.cartridge {
+        Some(cartridge) => {
+            options.other = cartridge.options.other.clone();
+            cartridge.program.clone()
+        }
+        None => disassembler::to_source(&program.disassemble()),
+    };
+    let cartridge = Cartridge {
+        program: source,
+        options,
+    };
+
+    run_headless(&mut program, &mut HeadlessPlatform::new(&settings), Some(label_frames))?;
+    let gif = cartridge::encode(&cartridge, &settings.palette, program.cpu.screen())?;
+    fs::write(&output_path, gif).map_err(|e| format!("{}: {}", output_path.display(), e))?;
+    return Ok(ExitCode::SUCCESS);
+}
+
+fn rom_info(path: &Path, emulator: &EmulatorArgs) -> Result<ExitCode, String> {
+    let (program, settings, sources) = configure_program(path, emulator, None)?;
+    let lines = program.disassemble();
+
+    let instructions = lines.iter().filter(|line| line.kind == LineKind::Code).count();
+    let data_bytes: usize = lines
+        .iter()
+        .filter(|line| line.kind == LineKind::Data)
+        .map(|line| line.bytes.len())
+        .sum();
+    let subroutines = lines
+        .iter()
+        .filter(|line| line.label.as_deref().is_some_and(|label| label.starts_with("sub_")))
+        .count();
+
+    println!("file:         {}", path.display());
+    println!("sha1:         {}", program.rom.sha1());
+    if let Some(title) = &settings.title {
+        println!("title:        {}", title);
+    }
+    println!("size:         {} bytes", program.rom.bytes.len());
+    println!("instructions: {}", instructions);
+    println!("data:         {} bytes", data_bytes);
+    println!("subroutines:  {}", subroutines);
+    println!("speed:        {} instructions/s", settings.frequency);
+    println!("quirks:       {:?}", program.cpu.quirks);
+    match analysis::analyse(&program.rom) {
+        Some(suggestion) => {
+            println!(
+                "detected:     {} ({} quirks, {:.0}% confidence{})",
+                suggestion.platform,
+                suggestion.profile,
+                suggestion.confidence * 100.0,
+                if settings.quirks.is_some() { ", overridden by the configuration" } else { "" }
+            );
+            for evidence in suggestion.evidence {
+                println!("  {}", evidence);
+            }
+        }
+        None => println!("detected:     nothing platform specific"),
+    }
+    for source in sources {
+        println!("configured by {}", source);
+    }
+    return Ok(ExitCode::SUCCESS);
+}
+
+fn trace_rom(path: &Path, emulator: &EmulatorArgs, frames: Option<u64>) -> Result<ExitCode, String> {
+    let (mut program, settings, _) = configure_program(path, emulator, None)?;
+    program.trace = true;
+    run_headless(&mut program, &mut HeadlessPlatform::new(&settings), frames)?;
+    return Ok(ExitCode::SUCCESS);
+}
+
+fn test_rom(
+    path: &Path,
+    emulator: &EmulatorArgs,
+    frames: u64,
+    expect: Option<&Path>,
+    headless_args: &HeadlessArgs,
+) -> Result<ExitCode, String> {
+    let (mut program, settings, _) = configure_program(path, emulator, None)?;
+    let mut platform = HeadlessPlatform::new(&settings);
+    headless_args.apply(&mut platform)?;
+    run_headless(&mut program, &mut platform, Some(frames))?;
+    let screen = screenshot::ascii(platform.screen());
+
+    let expect = match expect {
+        Some(expect) => expect,
+        None => {
+            print!("{}", screen);
+            return Ok(ExitCode::SUCCESS);
+        }
+    };
+
+    let expected = fs::read_to_string(expect).map_err(|e| format!("{}: {}", expect.display(), e))?;
+    if expected.trim_end() == screen.trim_end() {
+        println!("{}: ok", path.display());
+        return Ok(ExitCode::SUCCESS);
+    }
+    println!("{}: screen differs from {}", path.display(), expect.display());
+    print!("{}", screen);
+    return Ok(ExitCode::from(1));
+}
+
+// every ROM in the directory runs with its own settings but the same seed, so the report only changes
+// when the emulator does
+fn batch_roms(
+    dir: &Path,
+    emulator: &EmulatorArgs,
+    frames: u64,
+    script: Option<&Path>,
+    output: &Path,
+    baseline: Option<&Path>,
+) -> Result<ExitCode, String> {
+    // read first, the baseline is often the report this run replaces
+    let baseline = match baseline {
+        Some(path) => Some(fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?),
+        None => None,
+    };
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|e| format!("{}: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| batch::is_rom(path))
+        .collect();
+    paths.sort();
+    if paths.is_empty() {
+        return Err(format!("{}: no ROMs found", dir.display()));
+    }
+    fs::create_dir_all(output).map_err(|e| format!("{}: {}", output.display(), e))?;
+
+    let mut results = Vec::new();
+    for path in paths.iter() {
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        let result = match configure_program(path, emulator, None) {
+            Ok((mut program, settings, _)) => {
+                if settings.seed.is_none() {
+                    program.reseed(0);
+                }
+                match batch_script(script, path) {
+                    Ok(script) => {
+                        let thumbnail = output.join(format!("{}.png", name));
+                        batch::run(&name, &mut program, &settings, script, frames, &thumbnail)
+                    }
+                    Err(e) => batch::BatchResult::failed(&name, e),
+                }
+            }
+            Err(e) => batch::BatchResult::failed(&name, e),
+        };
+        println!("{}", result.line());
+        results.push(result);
+    }
+
+    let report = batch::report(frames, &results);
+    let report_path = output.join("report.txt");
+    fs::write(&report_path, &report).map_err(|e| format!("{}: {}", report_path.display(), e))?;
+    println!("wrote {}", report_path.display());
+
+    if let Some(baseline) = baseline {
+        let (lines, differences) = batch::compare(&baseline, &report);
+        for line in lines.iter() {
+            println!("{}", line);
+        }
+        if differences > 0 {
+            println!("{} of {} ROMs differ from the baseline", differences, results.len());
+            return Ok(ExitCode::from(1));
+        }
+        println!("all ROMs match the baseline");
+    }
+    return Ok(ExitCode::SUCCESS);
+}
+
+// a script for every ROM, or the one named after the ROM in a directory of scripts
+fn batch_script(script: Option<&Path>, rom: &Path) -> Result<Option<KeyScript>, String> {
+    let script = match script {
+        Some(script) if script.is_dir() => script.join(rom.file_stem().unwrap()).with_extension("txt"),
+        Some(script) => script.to_path_buf(),
+        None => return Ok(None),
+    };
+    if !script.exists() {
+        return Ok(None);
+    }
+    return KeyScript::load(&script).map(Some);
+}
+
+// every ROM a client loads is configured the way run would configure it
+fn serve(rom: Option<&Path>, emulator: &EmulatorArgs, listen: &str) -> Result<ExitCode, String> {
+    let load = |path: &Path| configure_program(path, emulator, None).map(|(program, _, _)| program);
+    let mut server = Server::bind(listen, load)?;
+    if let Some(rom) = rom {
+        server.load(rom)?;
+    }
+    println!("listening on {}", server.address());
+    server.serve()?;
+    return Ok(ExitCode::SUCCESS);
 }
@@ -0,0 +1,462 @@
+use crate::quirks::Quirks;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// a movie is everything a run needs to be repeated exactly: the ROM, the seed of the random number
+// generator, the quirks and speed, and the input frame by frame. It is a text file, e.g.
+//
+//   chip8-rust movie 1
+//   rom 0df2789f01ec7ba1a55ef7b0e6ae2c2e5ed1b81a
+//   seed 1234
+//   speed 2000
+//   quirks vip
+//   # frame event
+//   0 keys -
+//   30 keys 5a
+//   33 keys -
+//   33 release 5
+//   59 check 8b1f0e6a4c2d9e71
+//
+// keys lists the keys held from that frame on, release is a key released for FX0A to read and
+// check is a checksum of the machine state at the end of that frame.
+
+const MAGIC: &str = "chip8-rust movie 1";
+
+// how often a recording stores a checksum of the machine state
+pub const CHECK_FRAMES: u64 = 60;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MovieHeader {
+    // SHA-1 of the ROM the movie was recorded with
+    pub rom: String,
+    pub seed: u64,
+    // instructions per second
+    pub speed: f32,
+    pub quirks: Quirks,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MovieEvent {
+    Keys([u8; 16]),
+    Release(u8),
+    Check(u64),
+}
+
+#[derive(Clone, Debug)]
+pub struct Movie {
+    pub header: MovieHeader,
+    // in frame order
+    pub events: Vec<(u64, MovieEvent)>,
+}
+
+impl Movie {
+    pub fn parse(text: &str) -> Result<Movie, String> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(number, line)| (number + 1, line.split('#').next().unwrap().trim()))
+            .filter(|(_, line)| !line.is_empty());
+        match lines.next() {
+            Some((_, MAGIC)) => (),
+            _ => return Err(format!("not a movie (expected '{}' on the first line)", MAGIC)),
+        }
+
+        let (mut rom, mut seed, mut speed, mut quirks) = (None, None, None, None);
+        let mut events: Vec<(u64, MovieEvent)> = Vec::new();
+        for (number, line) in lines {
+            let error = |message: String| format!("line {}: {}", number, message);
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["rom", sha1] => rom = Some(sha1.to_string()),
+                ["seed", value] => {
+                    seed = Some(value.parse::<u64>().map_err(|_| error(format!("invalid seed '{}'", value)))?)
+                }
+                ["speed", value] => {
+                    speed = Some(value.parse::<f32>().map_err(|_| error(format!("invalid speed '{}'", value)))?)
+                }
+                ["quirks", value] => quirks = Some(value.parse::<Quirks>().map_err(error)?),
+                [frame, event, value] => {
+                    let frame = frame
+                        .parse::<u64>()
+                        .map_err(|_| error(format!("invalid frame number '{}'", frame)))?;
+                    if events.last().is_some_and(|(last, _)| *last > frame) {
+                        return Err(error(format!("frame {} comes after frame {}", frame, events.last().unwrap().0)));
+                    }
+                    let event = match *event {
+                        "keys" => MovieEvent::Keys(parse_keys(value).map_err(error)?),
+                        "release" => MovieEvent::Release(
+                            u8::from_str_radix(value, 16)
+                                .ok()
+                                .filter(|key| *key < 16)
+                                .ok_or_else(|| error(format!("invalid key '{}' (expected 0-F)", value)))?,
+                        ),
+                        "check" => MovieEvent::Check(
+                            u64::from_str_radix(value, 16).map_err(|_| error(format!("invalid checksum '{}'", value)))?,
+                        ),
+                        event => {
+                            return Err(error(format!("unknown event '{}' (expected keys, release or check)", event)))
+                        }
+                    };
+                    events.push((frame, event));
+                }
+                _ => return Err(error(format!("unexpected '{}'", line))),
+            }
+        }
+
+        let missing = |name: &str| format!("missing '{}' line", name);
+        let header = MovieHeader {
+            rom: rom.ok_or_else(|| missing("rom"))?,
+            seed: seed.ok_or_else(|| missing("seed"))?,
+            speed: speed.ok_or_else(|| missing("speed"))?,
+            quirks: quirks.ok_or_else(|| missing("quirks"))?,
+        };
+        return Ok(Movie { header, events });
+    }
+
+    pub fn load(path: &Path) -> Result<Movie, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        return Movie::parse(&text).map_err(|e| format!("{}: {}", path.display(), e));
+    }
+
+    // the number of frames the movie covers, up to and including its last event
+    pub fn length(&self) -> u64 {
+        return self.events.last().map(|(frame, _)| frame + 1).unwrap_or(0);
+    }
+}
+
+// the held keys as hex digits, - for none
+fn parse_keys(s: &str) -> Result<[u8; 16], String> {
+    let mut state = [0u8; 16];
+    if s == "-" {
+        return Ok(state);
+    }
+    for c in s.chars() {
+        let key = c.to_digit(16).ok_or_else(|| format!("invalid keys '{}' (expected hex digits or -)", s))?;
+        state[key as usize] = 1;
+    }
+    return Ok(state);
+}
+
+fn format_keys(state: &[u8; 16]) -> String {
+    let keys: String = (0..16u32)
+        .filter(|key| state[*key as usize] != 0)
+        .map(|key| char::from_digit(key, 16).unwrap())
+        .collect();
+    if keys.is_empty() {
+        return String::from("-");
+    }
+    return keys;
+}
+
+// writes a movie while the program runs, every line is flushed as it is written so the file is
+// usable even if the process exits in the middle of a recording
+pub struct MovieRecorder {
+    file: BufWriter<File>,
+    // the keys written last, None before the first frame
+    keys: Option<[u8; 16]>,
+    last_check: Option<u64>,
+}
+
+impl MovieRecorder {
+    pub fn create(path: &Path, header: &MovieHeader) -> io::Result<MovieRecorder> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "{}", MAGIC)?;
+        writeln!(file, "rom {}", header.rom)?;
+        writeln!(file, "seed {}", header.seed)?;
+        writeln!(file, "speed {}", header.speed)?;
+        writeln!(file, "quirks {}", header.quirks)?;
+        writeln!(file, "# frame event")?;
+        file.flush()?;
+        return Ok(MovieRecorder {
+            file,
+            keys: None,
+            last_check: None,
+        });
+    }
+
+    // the input the program saw at the start of a frame
+    pub fn input(&mut self, frame: u64, keys: &[u8; 16], release: Option<u8>) -> io::Result<()> {
+        if self.keys != Some(*keys) {
+            writeln!(self.file, "{} keys {}", frame, format_keys(keys))?;
+            self.keys = Some(*keys);
+        }
+        if let Some(key) = release {
+            writeln!(self.file, "{} release {:x}", frame, key)?;
+        }
+        return self.file.flush();
+    }
+
+    // the state at the end of a frame
+    pub fn check(&mut self, frame: u64, checksum: u64) -> io::Result<()> {
+        if self.last_check == Some(frame) {
+            return Ok(());
+        }
+        writeln!(self.file, "{} check {:016x}", frame, checksum)?;
+        self.last_check = Some(frame);
+        return self.file.flush();
+    }
+}
+
+// where a playback stopped matching the movie
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub frame: u64,
+    pub expected: u64,
+    // None when the program stopped before reaching the frame
+    pub found: Option<u64>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.found {
+            Some(found) => write!(
+                f,
+                "diverges at frame {}: state checksum is {:016x}, the movie expects {:016x}",
+                self.frame, found, self.expected
+            ),
+            None => write!(f, "diverges at frame {}: the program stopped before reaching it", self.frame),
+        }
+    }
+}
+
+// feeds a movie's input to the program and compares the machine state against its checksums
+pub struct MoviePlayer {
+    movie: Movie,
+    next: usize,
+    keys: [u8; 16],
+    // checksums that matched so far
+    checked: usize,
+    // the first checksum that didn't match
+    pub divergence: Option<Divergence>,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> Self {
+        return MoviePlayer {
+            movie,
+            next: 0,
+            keys: [0u8; 16],
+            checked: 0,
+            divergence: None,
+        };
+    }
+
+    // the keys held during a frame and the key released at its start, if any
+    pub fn input(&mut self, frame: u64) -> ([u8; 16], Option<u8>) {
+        let mut release = None;
+        while let Some((event_frame, event)) = self.movie.events.get(self.next) {
+            if *event_frame > frame {
+                break;
+            }
+            match event {
+                MovieEvent::Keys(keys) => self.keys = *keys,
+                MovieEvent::Release(key) => release = Some(*key),
+                // checksums are compared at the end of their frame
+                MovieEvent::Check(_) if *event_frame == frame => break,
+                MovieEvent::Check(_) => (),
+            }
+            self.next += 1;
+        }
+        return (self.keys, release);
+    }
+
+    // compares the state at the end of a frame against the movie, the first divergence is reported as
+    // soon as it is found
+    pub fn check(&mut self, frame: u64, checksum: u64) {
+        while let Some((event_frame, event)) = self.movie.events.get(self.next) {
+            if *event_frame != frame {
+                break;
+            }
+            if let MovieEvent::Check(expected) = event {
+                if *expected == checksum {
+                    self.checked += 1;
+                } else if self.divergence.is_none() {
+                    self.diverge(Divergence {
+                        frame,
+                        expected: *expected,
+                        found: Some(checksum),
+                    });
+                }
+            }
+            self.next += 1;
+        }
+    }
+
+    // once the program has stopped, a checksum before the frame limit that it never reached counts as a
+    // divergence, otherwise the playback matched
+    pub fn finish(&mut self, limit: u64) {
+        if self.divergence.is_some() {
+            return;
+        }
+        let unreached = self.movie.events[self.next..].iter().find_map(|(frame, event)| match event {
+            MovieEvent::Check(expected) if *frame < limit => Some((*frame, *expected)),
+            _ => None,
+        });
+        if let Some((frame, expected)) = unreached {
+            self.diverge(Divergence {
+                frame,
+                expected,
+                found: None,
+            });
+            return;
+        }
+        println!("movie matches, {} checksums verified", self.checked);
+    }
+
+    fn diverge(&mut self, divergence: Divergence) {
+        eprintln!("movie {}", divergence);
+        self.divergence = Some(divergence);
+    }
+}
+
+// a program either records its input into a movie or takes it from one
+pub enum MovieMode {
+    Record(MovieRecorder),
+    Play(MoviePlayer),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{load_rom, Program, Runnable};
+    use crate::platform::{create_contexts, Command, KeyInput};
+    use crate::rom::Rom;
+
+    // stores a random number and a count of the loops key 5 was held for at 0x300, forever
+    const PROGRAM: [u16; 7] = [0xc0ff, 0x6505, 0xe5a1, 0x7101, 0xa300, 0xf155, 0x1200];
+
+    const FRAMES: u64 = 130;
+
+    fn program(seed: u64) -> Program {
+        let bytes: Vec<u8> = PROGRAM.iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+        let mut program = load_rom(Rom::from_bytes(&bytes).unwrap()).unwrap();
+        program.realtime = false;
+        program.reseed(seed);
+        return program;
+    }
+
+    fn header(program: &Program, seed: u64) -> MovieHeader {
+        return MovieHeader {
+            rom: program.rom.sha1(),
+            seed,
+            speed: program.frequency,
+            quirks: program.cpu.quirks,
+        };
+    }
+
+    // records FRAMES frames with key 5 held from frame 10 to 20, returns the movie and the state at the end
+    fn record() -> (String, u64) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.movie");
+        let mut program = program(7);
+        let recorder = MovieRecorder::create(&path, &header(&program, 7)).unwrap();
+        program.movie = Some(MovieMode::Record(recorder));
+        let (platform, cpu) = create_contexts();
+        for frame in 0..FRAMES {
+            match frame {
+                10 => platform.send(Command::Key(KeyInput::press(5))),
+                20 => platform.send(Command::Key(KeyInput::release(5))),
+                _ => (),
+            }
+            assert!(program.run_frame(&cpu));
+        }
+        program.finish_movie();
+        let checksum = program.cpu.checksum();
+        drop(program);
+        let text = fs::read_to_string(&path).unwrap();
+        return (text, checksum);
+    }
+
+    // plays a movie back for some frames, ignoring key presses from the platform
+    fn play(movie: Movie, frames: u64) -> (MoviePlayer, u64) {
+        let mut program = program(movie.header.seed);
+        program.frame_limit = Some(frames);
+        program.movie = Some(MovieMode::Play(MoviePlayer::new(movie)));
+        let (platform, cpu) = create_contexts();
+        platform.send(Command::Key(KeyInput::press(5)));
+        for _ in 0..frames {
+            assert!(program.run_frame(&cpu));
+        }
+        program.finish_movie();
+        let checksum = program.cpu.checksum();
+        match program.movie.take() {
+            Some(MovieMode::Play(player)) => return (player, checksum),
+            _ => panic!("the movie stopped"),
+        }
+    }
+
+    #[test]
+    fn a_recording_plays_back_the_same() {
+        let (text, recorded) = record();
+        assert!(text.contains("\n10 keys 5\n") && text.contains("\n20 keys -\n"), "{}", text);
+        let movie = Movie::parse(&text).unwrap();
+        assert_eq!(movie.length(), FRAMES);
+        let (player, played) = play(movie, FRAMES);
+        assert_eq!(player.divergence, None);
+        // every 60 frames and the last one
+        assert_eq!(player.checked, 3);
+        assert_eq!(played, recorded);
+    }
+
+    #[test]
+    fn a_checksum_that_differs_is_reported_at_its_frame() {
+        let (text, _) = record();
+        let line = text.lines().find(|line| line.starts_with("119 check ")).unwrap();
+        let wrong = format!("119 check {:016x}", 1);
+        let movie = Movie::parse(&text.replace(line, &wrong)).unwrap();
+        let (player, _) = play(movie, FRAMES);
+        let expected = u64::from_str_radix(&line[10..], 16).unwrap();
+        assert_eq!(
+            player.divergence,
+            Some(Divergence {
+                frame: 119,
+                expected: 1,
+                found: Some(expected),
+            })
+        );
+        // the checks before and after it still match
+        assert_eq!(player.checked, 2);
+    }
+
+    #[test]
+    fn other_input_diverges_at_the_first_check() {
+        let (text, _) = record();
+        // key 5 held for the rest of the movie
+        let movie = Movie::parse(&text.replace("\n20 keys -\n", "\n20 keys 5\n")).unwrap();
+        let (player, _) = play(movie, FRAMES);
+        assert_eq!(player.divergence.unwrap().frame, 59);
+    }
+
+    #[test]
+    fn a_check_the_program_never_reached_is_a_divergence() {
+        let (text, _) = record();
+        let mut movie = Movie::parse(&text).unwrap();
+        // a limit past the end of the run
+        movie.events.push((200, MovieEvent::Check(1)));
+        let mut player = play(movie, FRAMES).0;
+        player.finish(300);
+        assert_eq!(
+            player.divergence,
+            Some(Divergence {
+                frame: 200,
+                expected: 1,
+                found: None,
+            })
+        );
+        assert!(player.divergence.unwrap().to_string().contains("stopped before reaching it"));
+    }
+
+    #[test]
+    fn parse_errors_name_the_line() {
+        let header = "chip8-rust movie 1\nrom 00\nseed 1\nspeed 700\nquirks vip\n";
+        assert!(Movie::parse(&format!("{}0 keys 5a\n3 release f\n", header)).is_ok());
+        let error = |events: &str| Movie::parse(&format!("{}{}", header, events)).unwrap_err();
+        assert_eq!(error("0 keys 5g\n"), "line 6: invalid keys '5g' (expected hex digits or -)");
+        assert_eq!(error("4 keys -\n2 keys -\n"), "line 7: frame 2 comes after frame 4");
+        assert!(error("0 jump 1\n").contains("unknown event"));
+        assert!(Movie::parse("rom 00\n").unwrap_err().starts_with("not a movie"));
+        assert_eq!(Movie::parse("chip8-rust movie 1\nrom 00\n").unwrap_err(), "missing 'seed' line");
+    }
+}
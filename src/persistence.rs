@@ -0,0 +1,294 @@
+use crate::machine::{Framebuffer, MAX_HEIGHT, MAX_WIDTH};
+use crate::platform::ScreenUpdate;
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// games erase and redraw sprites with XOR so a moving sprite is missing from every other screen, keeping
+// pixels lit for a while after they go dark hides the flicker. Only the platforms see this, the CPU's
+// screen is left alone.
+
+const FRAME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+// a decaying pixel goes dark once it is dimmer than this
+const DARK: f32 = 1.0 / 16.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Persistence {
+    #[default]
+    Off,
+    // a pixel that goes dark fades out over this many frames like the phosphor of a CRT
+    Decay(u32),
+    // a pixel stays lit while it was lit in any of the last this many frames
+    Blend(u32),
+}
+
+impl FromStr for Persistence {
+    type Err = String;
+
+    // off, decay, decay:FRAMES, or and or:FRAMES
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("unknown persistence '{}' (expected off, decay[:FRAMES] or or[:FRAMES])", s);
+        let (mode, frames) = match s.split_once(':') {
+            Some((mode, frames)) => (mode, Some(frames.parse::<u32>().ok().filter(|n| *n > 0).ok_or_else(error)?)),
+            None => (s, None),
+        };
+        match mode.to_ascii_lowercase().as_str() {
+            "off" if frames.is_none() => Ok(Persistence::Off),
+            "decay" => Ok(Persistence::Decay(frames.unwrap_or(6))),
+            "or" => Ok(Persistence::Blend(frames.unwrap_or(2))),
+            _ => Err(error()),
+        }
+    }
+}
+
+// the screen as a platform should show it, the platform pushes every update it receives and calls
+// advance before drawing so the filter keeps time with the 60Hz frames
+pub struct PersistenceFilter {
+    persistence: Persistence,
+    // what the emulator shows right now
+    screen: Framebuffer,
+    // every pixel lit at some point during the current frame
+    frame: Framebuffer,
+    // the frames before the current one, newest last
+    history: VecDeque<Framebuffer>,
+    // how bright each pixel is and the colour it was last lit in
+    brightness: [[f32; MAX_HEIGHT]; MAX_WIDTH],
+    lit: [[u8; MAX_HEIGHT]; MAX_WIDTH],
+    frame_started: Instant,
+}
+
+impl PersistenceFilter {
+    pub fn new(persistence: Persistence) -> Self {
+        return PersistenceFilter {
+            persistence,
+            screen: Framebuffer::default(),
+            frame: Framebuffer::default(),
+            history: VecDeque::new(),
+            brightness: [[0.0; MAX_HEIGHT]; MAX_WIDTH],
+            lit: [[0u8; MAX_HEIGHT]; MAX_WIDTH],
+            frame_started: Instant::now(),
+        };
+    }
+
+    // only the rows in the update are touched
+    pub fn push(&mut self, update: &ScreenUpdate) {
+        update.apply(&mut self.screen);
+        // a change of resolution starts the frame over
+        if self.frame.width() != update.width || self.frame.height() != update.height {
+            self.frame.clone_from(&self.screen);
+            self.history.clear();
+            return;
+        }
+        for (y, planes) in update.rows.iter() {
+            for (plane, bits) in planes.iter().enumerate() {
+                self.frame.set_row(plane, *y, self.frame.row(plane, *y) | bits);
+            }
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        return self.screen.width();
+    }
+
+    pub fn height(&self) -> usize {
+        return self.screen.height();
+    }
+
+    // the colour value of a pixel and how bright it is from 0 to 1
+    pub fn pixel(&self, x: usize, y: usize) -> (u8, f32) {
+        match self.persistence {
+            Persistence::Off => {
+                let value = self.screen.pixel(x, y);
+                return (value, if value > 0 { 1.0 } else { 0.0 });
+            }
+            Persistence::Decay(_) => {
+                let value = self.frame.pixel(x, y);
+                if value > 0 {
+                    return (value, 1.0);
+                }
+                return (self.lit[x][y], self.brightness[x][y]);
+            }
+            Persistence::Blend(_) => {
+                let value = self.history.iter().fold(self.frame.pixel(x, y), |value, frame| value | frame.pixel(x, y));
+                return (value, if value > 0 { 1.0 } else { 0.0 });
+            }
+        }
+    }
+
+    // finishes the frames that went by since the last call, returns true if that changed the picture
+    pub fn advance(&mut self) -> bool {
+        let frames = (self.frame_started.elapsed().as_nanos() / FRAME.as_nanos()) as u32;
+        if frames == 0 || self.persistence == Persistence::Off {
+            self.frame_started += FRAME * frames;
+            return false;
+        }
+
+        let before = self.pixels();
+        // after a long pause everything has faded anyway
+        for _ in 0..frames.min(self.length() + 1) {
+            self.end_frame();
+        }
+        self.frame_started += FRAME * frames;
+        return self.pixels() != before;
+    }
+
+    fn length(&self) -> u32 {
+        match self.persistence {
+            Persistence::Off => 0,
+            Persistence::Decay(frames) | Persistence::Blend(frames) => frames,
+        }
+    }
+
+    fn end_frame(&mut self) {
+        match self.persistence {
+            Persistence::Off => (),
+            Persistence::Decay(frames) => {
+                // dims to DARK over the given number of frames
+                let factor = DARK.powf(1.0 / frames as f32);
+                for x in 0..self.frame.width() {
+                    for y in 0..self.frame.height() {
+                        let value = self.frame.pixel(x, y);
+                        if value > 0 {
+                            self.brightness[x][y] = 1.0;
+                            self.lit[x][y] = value;
+                        } else {
+                            self.brightness[x][y] *= factor;
+                            if self.brightness[x][y] < DARK {
+                                self.brightness[x][y] = 0.0;
+                            }
+                        }
+                    }
+                }
+            }
+            Persistence::Blend(frames) => {
+                // the current frame is one of them
+                self.history.push_back(self.frame.clone());
+                while self.history.len() >= frames as usize {
+                    self.history.pop_front();
+                }
+            }
+        }
+        // the next frame starts with whatever is on the screen
+        self.frame.clone_from(&self.screen);
+    }
+
+    fn pixels(&self) -> Vec<(u8, f32)> {
+        let mut pixels = Vec::with_capacity(self.width() * self.height());
+        for x in 0..self.width() {
+            for y in 0..self.height() {
+                pixels.push(self.pixel(x, y));
+            }
+        }
+        return pixels;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::PLANES;
+
+    // pixel 3, 2 lit in the given colour or dark, on a lores screen
+    fn show(filter: &mut PersistenceFilter, value: u8) {
+        let mut screen = Framebuffer::default();
+        for plane in 0..PLANES {
+            if value & (1 << plane) != 0 {
+                screen.set_row(plane, 2, 1 << (MAX_WIDTH - 1 - 3));
+            }
+        }
+        filter.push(&ScreenUpdate::new(&screen, u64::MAX));
+    }
+
+    #[test]
+    fn parses() {
+        assert_eq!("off".parse(), Ok(Persistence::Off));
+        assert_eq!("decay".parse(), Ok(Persistence::Decay(6)));
+        assert_eq!("Decay:10".parse(), Ok(Persistence::Decay(10)));
+        assert_eq!("or".parse(), Ok(Persistence::Blend(2)));
+        assert_eq!("or:3".parse(), Ok(Persistence::Blend(3)));
+        for bad in ["off:2", "decay:0", "or:x", "fade"] {
+            assert!(bad.parse::<Persistence>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn off_shows_the_screen_as_it_is() {
+        let mut filter = PersistenceFilter::new(Persistence::Off);
+        show(&mut filter, 1);
+        assert_eq!(filter.pixel(3, 2), (1, 1.0));
+        show(&mut filter, 0);
+        assert_eq!(filter.pixel(3, 2), (0, 0.0));
+        assert!(!filter.advance());
+    }
+
+    #[test]
+    fn decay_fades_a_pixel_out_in_its_colour() {
+        let mut filter = PersistenceFilter::new(Persistence::Decay(4));
+        show(&mut filter, 2);
+        filter.end_frame();
+        show(&mut filter, 0);
+        // still lit for the rest of the frame it was lit in
+        assert_eq!(filter.pixel(3, 2), (2, 1.0));
+        filter.end_frame();
+        assert_eq!(filter.pixel(3, 2), (2, 1.0));
+
+        let mut brightness = 1.0;
+        for _ in 0..3 {
+            filter.end_frame();
+            let (value, dimmer) = filter.pixel(3, 2);
+            assert_eq!(value, 2);
+            assert!(dimmer < brightness && dimmer >= DARK, "{} after {}", dimmer, brightness);
+            brightness = dimmer;
+        }
+        filter.end_frame();
+        filter.end_frame();
+        assert_eq!(filter.pixel(3, 2).1, 0.0);
+        assert_eq!(filter.pixel(4, 2), (0, 0.0));
+    }
+
+    #[test]
+    fn decay_relights_a_fading_pixel() {
+        let mut filter = PersistenceFilter::new(Persistence::Decay(4));
+        show(&mut filter, 1);
+        filter.end_frame();
+        show(&mut filter, 0);
+        filter.end_frame();
+        filter.end_frame();
+        assert!(filter.pixel(3, 2).1 < 1.0);
+        show(&mut filter, 1);
+        assert_eq!(filter.pixel(3, 2), (1, 1.0));
+    }
+
+    #[test]
+    fn or_keeps_a_flickering_pixel_lit() {
+        let mut filter = PersistenceFilter::new(Persistence::Blend(2));
+        show(&mut filter, 1);
+        filter.end_frame();
+        // erased to be redrawn, the frame before still has it
+        show(&mut filter, 0);
+        filter.end_frame();
+        assert_eq!(filter.pixel(3, 2), (1, 1.0));
+        filter.end_frame();
+        assert_eq!(filter.pixel(3, 2), (0, 0.0));
+    }
+
+    #[test]
+    fn or_combines_colours() {
+        let mut filter = PersistenceFilter::new(Persistence::Blend(3));
+        show(&mut filter, 1);
+        filter.end_frame();
+        show(&mut filter, 2);
+        assert_eq!(filter.pixel(3, 2), (3, 1.0));
+    }
+
+    #[test]
+    fn a_change_of_resolution_forgets_the_old_frames() {
+        let mut filter = PersistenceFilter::new(Persistence::Blend(4));
+        show(&mut filter, 1);
+        filter.end_frame();
+        filter.push(&ScreenUpdate::new(&Framebuffer::new(128, 64), u64::MAX));
+        assert_eq!((filter.width(), filter.height()), (128, 64));
+        assert_eq!(filter.pixel(3, 2), (0, 0.0));
+    }
+}
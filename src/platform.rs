@@ -1,42 +1,178 @@
-use crossbeam_channel::{bounded, Receiver, Sender};
+use crate::machine::{Framebuffer, State, PLANES};
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
+use std::time::Instant;
 
-pub(crate) struct PlatformContext {
-    pub keyboard: Sender<[u8; 16]>,
-    pub single_key: Sender<char>,
-    pub sound: Receiver<bool>,
-    pub display: Receiver<[[u8; 32]; 64]>,
+// what the emulator is doing, sent at the end of every frame for platforms that want to show it
+#[derive(Clone, Copy, Debug)]
+pub struct Status {
+    pub frame: u64,
+    pub pc: u16,
+    pub i: u16,
+    // configured instructions per second
+    pub frequency: f32,
+    pub waiting_for_key: bool,
 }
 
-pub(crate) struct CpuContext {
-    pub keyboard: Receiver<[u8; 16]>,
-    pub single_key: Receiver<char>,
-    pub sound: Sender<bool>,
-    pub display: Sender<[[u8; 32]; 64]>,
+// the rows of the screen that changed in a frame, a platform keeps its own Framebuffer and brings it up
+// to date with every update it receives, so none of them can be skipped
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScreenUpdate {
+    pub width: usize,
+    pub height: usize,
+    // a row number and the row in every plane
+    pub rows: Vec<(usize, [u128; PLANES])>,
 }
 
-pub(crate) fn create_contexts() -> (PlatformContext, CpuContext) {
-    let (ks, kr) = bounded::<[u8; 16]>(1);
-    let (sks, skr) = bounded::<char>(0);
-    let (ss, sr) = bounded::<bool>(1);
-    let (ds, dr) = bounded::<[[u8; 32]; 64]>(2);
+impl ScreenUpdate {
+    // the rows of a screen with a bit set in rows
+    pub fn new(screen: &Framebuffer, rows: u64) -> Self {
+        let rows = (0..screen.height())
+            .filter(|y| rows & (1 << y) != 0)
+            .map(|y| (y, core::array::from_fn(|plane| screen.row(plane, y))))
+            .collect();
+        return ScreenUpdate {
+            width: screen.width(),
+            height: screen.height(),
+            rows,
+        };
+    }
+
+    // a screen that changed resolution starts over blank
+    pub fn apply(&self, screen: &mut Framebuffer) {
+        if screen.width() != self.width || screen.height() != self.height {
+            screen.resize(self.width, self.height);
+        }
+        for (y, planes) in self.rows.iter() {
+            for (plane, bits) in planes.iter().enumerate() {
+                screen.set_row(plane, *y, *bits);
+            }
+        }
+    }
+}
+
+// a key going down or up, stamped with when it happened so the emulator can apply it at the same point of a frame
+#[derive(Clone, Copy, Debug)]
+pub struct KeyInput {
+    pub key: u8,
+    pub pressed: bool,
+    pub at: Instant,
+}
+
+impl KeyInput {
+    pub fn press(key: u8) -> Self {
+        return KeyInput {
+            key: key & 0x0f,
+            pressed: true,
+            at: Instant::now(),
+        };
+    }
+
+    pub fn release(key: u8) -> Self {
+        return KeyInput {
+            key: key & 0x0f,
+            pressed: false,
+            at: Instant::now(),
+        };
+    }
+}
+
+// what the platform asks of the emulator, taken at the start of the next frame in the order they were sent
+#[derive(Clone, Debug)]
+pub enum Command {
+    // a key was pressed or released, a release is what FX0A waits for
+    Key(KeyInput),
+    // stops running frames until Resume, the screen and timers stay as they are
+    Pause,
+    Resume,
+    // starts the program over from its ROM with the same settings
+    Reset,
+    // asks for an Event::State with the machine as it is
+    SaveState,
+    // puts the machine back the way a saved State had it
+    LoadState(Box<State>),
+    // stops the emulator, so does dropping the PlatformContext
+    Quit,
+}
+
+// what the emulator tells the platform
+#[derive(Clone, Debug)]
+pub enum Event {
+    // the rows drawn to during a frame, or the whole screen after a reset, a load or a change of resolution
+    Screen(ScreenUpdate),
+    // the buzzer started or stopped
+    Sound(bool),
+    // sent at the end of every frame, dropped when the platform is behind
+    Status(Status),
+    // the answer to Command::SaveState
+    State(Box<State>),
+    // the program ran off its end, reached its frame limit or was told to quit, nothing is sent after this
+    Stopped,
+}
+
+// the emulator and the platform talk over two channels, commands one way and events the other. A run goes:
+//
+//   1. create_contexts makes both ends, the emulator gets the CpuContext and runs with Runnable::run on a
+//      thread of its own while the platform gets the PlatformContext and runs Platform::start
+//   2. the platform sends a Command::Key for every key that goes down or up, and any of the other commands
+//      whenever it likes
+//   3. every frame the emulator takes the commands sent so far, runs its instructions and sends an
+//      Event::Screen if anything was drawn, an Event::Sound if the buzzer changed and an Event::Status.
+//      Key events land at the instruction matching when they happened during the frame before, see
+//      keyboard::KeyQueue, so a tap shorter than a frame still counts
+//   4. on Command::Quit, or when the program stops by itself, the emulator sends Event::Stopped and its
+//      thread ends. The platform can carry on showing the last screen until it is closed.
+//
+// Commands are never dropped. Events other than Status aren't either, the emulator waits for a platform that
+// is far behind.
+pub struct PlatformContext {
+    pub commands: Sender<Command>,
+    pub events: Receiver<Event>,
+}
+
+impl PlatformContext {
+    pub fn send(&self, command: Command) {
+        // only fails once the emulator has stopped, when it doesn't matter
+        match self.commands.send(command) {
+            Ok(_) => (),
+            Err(_) => (),
+        }
+    }
+}
+
+pub struct CpuContext {
+    pub commands: Receiver<Command>,
+    pub events: Sender<Event>,
+}
+
+impl CpuContext {
+    pub fn send(&self, event: Event) {
+        match self.events.send(event) {
+            Ok(_) => (),
+            Err(_) => (),
+        }
+    }
+}
+
+// how many events can wait for the platform before the emulator does
+const EVENTS: usize = 64;
+
+pub fn create_contexts() -> (PlatformContext, CpuContext) {
+    let (cs, cr) = unbounded::<Command>();
+    let (es, er) = bounded::<Event>(EVENTS);
 
     return (
         PlatformContext {
-            keyboard: ks,
-            single_key: sks,
-            sound: sr,
-            display: dr,
+            commands: cs,
+            events: er,
         },
         CpuContext {
-            keyboard: kr,
-            single_key: skr,
-            sound: ss,
-            display: ds,
+            commands: cr,
+            events: es,
         },
     );
 }
 
-pub(crate) trait Platform {
+pub trait Platform {
     fn start(&mut self, context: &PlatformContext);
     fn update(&mut self, context: &PlatformContext);
     fn render(&mut self, context: &PlatformContext);
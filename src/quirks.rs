@@ -0,0 +1,207 @@
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+
+pub use crate::machine::Quirks;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuirkProfile {
+    // what this emulator has always done
+    Modern,
+    // the original COSMAC VIP interpreter
+    Vip,
+    // SUPER-CHIP 1.1 on the HP48
+    Schip,
+    // Octo's XO-CHIP
+    XoChip,
+}
+
+impl QuirkProfile {
+    pub const ALL: [QuirkProfile; 4] = [
+        QuirkProfile::Modern,
+        QuirkProfile::Vip,
+        QuirkProfile::Schip,
+        QuirkProfile::XoChip,
+    ];
+
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            QuirkProfile::Modern => Quirks::default(),
+            QuirkProfile::Vip => Quirks {
+                vf_reset: true,
+                memory: true,
+                display_wait: true,
+                clipping: true,
+                shifting: false,
+                jumping: false,
+            },
+            QuirkProfile::Schip => Quirks {
+                vf_reset: false,
+                memory: false,
+                display_wait: false,
+                clipping: true,
+                shifting: true,
+                jumping: true,
+            },
+            QuirkProfile::XoChip => Quirks {
+                vf_reset: false,
+                memory: true,
+                display_wait: false,
+                clipping: false,
+                shifting: false,
+                jumping: false,
+            },
+        }
+    }
+
+    // maps the platform ids used by the CHIP-8 database onto the closest profile
+    pub fn for_platform(id: &str) -> Option<QuirkProfile> {
+        match id {
+            "originalChip8" | "hybridVIP" | "chip8x" => Some(QuirkProfile::Vip),
+            "modernChip8" => Some(QuirkProfile::Modern),
+            "chip48" | "superchip1" | "superchip" | "megachip8" => Some(QuirkProfile::Schip),
+            "xochip" => Some(QuirkProfile::XoChip),
+            _ => id.parse().ok(),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            QuirkProfile::Modern => "modern",
+            QuirkProfile::Vip => "vip",
+            QuirkProfile::Schip => "schip",
+            QuirkProfile::XoChip => "xochip",
+        }
+    }
+}
+
+impl Default for QuirkProfile {
+    fn default() -> Self {
+        return QuirkProfile::Modern;
+    }
+}
+
+impl fmt::Display for QuirkProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}", self.name());
+    }
+}
+
+impl FromStr for QuirkProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "modern" => Ok(QuirkProfile::Modern),
+            "vip" | "chip8" | "chip-8" | "cosmac" => Ok(QuirkProfile::Vip),
+            "schip" | "superchip" | "super-chip" => Ok(QuirkProfile::Schip),
+            "xochip" | "xo-chip" => Ok(QuirkProfile::XoChip),
+            _ => Err(format!(
+                "unknown quirk profile '{}' (expected modern, vip, schip or xochip)",
+                s
+            )),
+        }
+    }
+}
+
+impl Quirks {
+    // every quirk by name along with whether it is on
+    fn flags(&self) -> [(&'static str, bool); 6] {
+        return [
+            ("vf_reset", self.vf_reset),
+            ("memory", self.memory),
+            ("display_wait", self.display_wait),
+            ("clipping", self.clipping),
+            ("shifting", self.shifting),
+            ("jumping", self.jumping),
+        ];
+    }
+}
+
+// the name of the profile they match, otherwise the names of the quirks that are on
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(profile) = QuirkProfile::ALL.iter().find(|profile| profile.quirks() == *self) {
+            return write!(f, "{}", profile);
+        }
+        let on: Vec<&str> = self.flags().iter().filter(|(_, on)| *on).map(|(name, _)| *name).collect();
+        if on.is_empty() {
+            return write!(f, "none");
+        }
+        return write!(f, "{}", on.join(","));
+    }
+}
+
+// a profile name, none or a comma separated list of the quirks that are on
+impl FromStr for Quirks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(profile) = s.parse::<QuirkProfile>() {
+            return Ok(profile.quirks());
+        }
+        let mut quirks = Quirks {
+            vf_reset: false,
+            memory: false,
+            display_wait: false,
+            clipping: false,
+            shifting: false,
+            jumping: false,
+        };
+        if s == "none" {
+            return Ok(quirks);
+        }
+        for name in s.split(',') {
+            match name.trim() {
+                "vf_reset" => quirks.vf_reset = true,
+                "memory" => quirks.memory = true,
+                "display_wait" => quirks.display_wait = true,
+                "clipping" => quirks.clipping = true,
+                "shifting" => quirks.shifting = true,
+                "jumping" => quirks.jumping = true,
+                _ => return Err(format!("unknown quirk '{}'", name)),
+            }
+        }
+        return Ok(quirks);
+    }
+}
+
+// individual quirks as named by the CHIP-8 database, anything left out keeps its current value
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuirkOverrides {
+    pub shift: Option<bool>,
+    pub memory_increment_by_x: Option<bool>,
+    pub memory_leave_i_unchanged: Option<bool>,
+    pub wrap: Option<bool>,
+    pub jump: Option<bool>,
+    pub vblank: Option<bool>,
+    pub logic: Option<bool>,
+}
+
+impl QuirkOverrides {
+    pub fn apply(&self, quirks: &mut Quirks) {
+        if let Some(shift) = self.shift {
+            quirks.shifting = shift;
+        }
+        // incrementing I by X instead of X + 1 is close enough to the VIP behaviour
+        if let Some(true) = self.memory_increment_by_x {
+            quirks.memory = true;
+        }
+        if let Some(unchanged) = self.memory_leave_i_unchanged {
+            quirks.memory = !unchanged;
+        }
+        if let Some(wrap) = self.wrap {
+            quirks.clipping = !wrap;
+        }
+        if let Some(jump) = self.jump {
+            quirks.jumping = jump;
+        }
+        if let Some(vblank) = self.vblank {
+            quirks.display_wait = vblank;
+        }
+        if let Some(logic) = self.logic {
+            quirks.vf_reset = logic;
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use crate::upscale::{Filter, Image};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RecordingFormat {
+    #[default]
+    Gif,
+    Apng,
+}
+
+impl RecordingFormat {
+    // .png and .apng are APNG, anything else is a GIF
+    pub fn for_path(path: &Path) -> RecordingFormat {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("png") || extension.eq_ignore_ascii_case("apng") => {
+                RecordingFormat::Apng
+            }
+            _ => RecordingFormat::Gif,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            RecordingFormat::Gif => "gif",
+            RecordingFormat::Apng => "png",
+        }
+    }
+}
+
+impl FromStr for RecordingFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "gif" => Ok(RecordingFormat::Gif),
+            "apng" | "png" => Ok(RecordingFormat::Apng),
+            _ => Err(format!("unknown recording format '{}' (expected gif or apng)", s)),
+        }
+    }
+}
+
+// collects what was on the screen frame by frame and writes it as an animation at 60 frames per
+// second. A picture that stays on the screen is kept once along with how long it stayed.
+pub struct Recorder {
+    filter: Filter,
+    scale: u32,
+    // each picture before it is upscaled and the 60Hz frame it appeared in
+    pictures: Vec<(u64, Image)>,
+}
+
+impl Recorder {
+    pub fn new(filter: Filter, scale: u32) -> Self {
+        return Recorder {
+            filter,
+            scale,
+            pictures: Vec::new(),
+        };
+    }
+
+    // what is shown from this frame on, frames must only go forwards
+    pub fn add(&mut self, frame: u64, picture: &Image) {
+        if let Some((last_frame, last)) = self.pictures.last_mut() {
+            if last == picture {
+                return;
+            }
+            // the last picture was replaced before it was ever shown
+            if *last_frame >= frame {
+                *last = picture.clone();
+                return;
+            }
+        }
+        self.pictures.push((frame, picture.clone()));
+    }
+
+    // every picture upscaled along with the frame it appears in and how many frames it stays for
+    fn frames(&self, end: u64) -> Vec<(u64, u64, Image)> {
+        let mut frames = Vec::new();
+        for (i, (frame, picture)) in self.pictures.iter().enumerate() {
+            let until = match self.pictures.get(i + 1) {
+                Some((next, _)) => *next,
+                None => end.max(frame + 1),
+            };
+            frames.push((*frame, until - frame, picture.upscaled(self.filter, self.scale)));
+        }
+        return frames;
+    }
+
+    // writes everything up to the end frame
+    pub fn save(&self, path: &Path, format: RecordingFormat, end: u64) -> Result<(), String> {
+        if self.pictures.is_empty() {
+            return Err(String::from("nothing was recorded"));
+        }
+        let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let result = match format {
+            RecordingFormat::Gif => self.save_gif(file, end),
+            RecordingFormat::Apng => self.save_apng(file, end),
+        };
+        return result.map_err(|e| format!("{}: {}", path.display(), e));
+    }
+
+    fn save_gif(&self, file: File, end: u64) -> Result<(), String> {
+        let frames = self.frames(end);
+        let (width, height) = (frames[0].2.width as u16, frames[0].2.height as u16);
+        let mut encoder = gif::Encoder::new(BufWriter::new(file), width, height, &[]).map_err(|e| e.to_string())?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(|e| e.to_string())?;
+
+        let start = frames[0].0;
+        for (frame, length, image) in frames.iter() {
+            // GIF delays are in 1/100s, rounding where each picture starts and stops keeps the
+            // animation at 60fps on average
+            let centiseconds = |frame: u64| ((frame - start) * 100 + 30) / 60;
+            let delay = centiseconds(frame + length) - centiseconds(*frame);
+            let mut gif_frame = gif::Frame::from_rgb_speed(width, height, &image.to_rgb(), 10);
+            gif_frame.delay = delay.clamp(1, u16::MAX as u64) as u16;
+            encoder.write_frame(&gif_frame).map_err(|e| e.to_string())?;
+        }
+        return Ok(());
+    }
+
+    fn save_apng(&self, file: File, end: u64) -> Result<(), String> {
+        let frames = self.frames(end);
+        let (width, height) = (frames[0].2.width as u32, frames[0].2.height as u32);
+        let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(frames.len() as u32, 0).map_err(|e| e.to_string())?;
+        let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+
+        for (_, length, image) in frames.iter() {
+            writer
+                .set_frame_delay((*length).min(u16::MAX as u64) as u16, 60)
+                .map_err(|e| e.to_string())?;
+            writer.write_image_data(&image.to_rgb()).map_err(|e| e.to_string())?;
+        }
+        return writer.finish().map_err(|e| e.to_string());
+    }
+}
+
+// where screenshots and recordings made while the emulator runs are saved
+#[derive(Clone, Debug, Default)]
+pub struct CaptureOptions {
+    pub dir: PathBuf,
+    // format of recordings started from the keyboard
+    pub format: RecordingFormat,
+    // the last frame is saved here when the emulator stops
+    pub screenshot: Option<PathBuf>,
+    // recorded from the start until the emulator stops or recording is stopped from the keyboard
+    pub record: Option<PathBuf>,
+}
+
+impl CaptureOptions {
+    // <dir>/<name>-NNNN.<extension> with the first number that isn't taken
+    pub fn next_path(&self, name: &str, extension: &str) -> PathBuf {
+        let mut n = 1;
+        loop {
+            let path = self.dir.join(format!("{}-{:04}.{}", name, n, extension));
+            if !path.exists() {
+                return path;
+            }
+            n += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Rgb;
+    use std::io::BufReader;
+
+    const RED: Rgb = Rgb(255, 0, 0);
+    const BLUE: Rgb = Rgb(0, 0, 255);
+
+    fn picture(left: Rgb) -> Image {
+        return Image::from_fn(4, 2, |x, _| if x == 0 { left } else { Rgb(0, 0, 0) });
+    }
+
+    // red until frame 3, blue from 3 to 10, red again until 16
+    fn recorder() -> Recorder {
+        let mut recorder = Recorder::new(Filter::Nearest, 2);
+        recorder.add(0, &picture(RED));
+        recorder.add(3, &picture(RED));
+        // green is drawn over in the same frame, so never seen
+        recorder.add(3, &picture(Rgb(0, 255, 0)));
+        recorder.add(3, &picture(BLUE));
+        recorder.add(5, &picture(BLUE));
+        recorder.add(10, &picture(RED));
+        return recorder;
+    }
+
+    #[test]
+    fn only_pictures_that_were_seen_are_kept() {
+        let frames = recorder().frames(16);
+        let lengths: Vec<(u64, u64)> = frames.iter().map(|(frame, length, _)| (*frame, *length)).collect();
+        assert_eq!(lengths, [(0, 3), (3, 7), (10, 6)]);
+        assert_eq!((frames[1].2.width, frames[1].2.height), (8, 4));
+        assert_eq!(frames[1].2.pixels[..2], [BLUE, BLUE]);
+        // the last picture is shown for at least a frame
+        assert_eq!(recorder().frames(0)[2].1, 1);
+    }
+
+    #[test]
+    fn gif_frames_keep_60fps_in_hundredths() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.gif");
+        recorder().save(&path, RecordingFormat::Gif, 16).unwrap();
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(File::open(&path).unwrap()).unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (8, 4));
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((frame.delay, frame.buffer[..3].to_vec()));
+        }
+        // 3, 7 and 6 frames are 5, 11.7 and 10 hundredths of a second, rounded where each one starts
+        assert_eq!(frames, [(5, vec![255, 0, 0]), (12, vec![0, 0, 255]), (10, vec![255, 0, 0])]);
+    }
+
+    #[test]
+    fn apng_frames_are_timed_in_sixtieths() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.png");
+        recorder().save(&path, RecordingFormat::Apng, 16).unwrap();
+        let mut reader = png::Decoder::new(BufReader::new(File::open(&path).unwrap())).read_info().unwrap();
+        assert_eq!(reader.info().size(), (8, 4));
+        assert_eq!(reader.info().animation_control.unwrap().num_frames, 3);
+        let mut buffer = vec![0; reader.output_buffer_size().unwrap()];
+        let mut frames = Vec::new();
+        for _ in 0..3 {
+            reader.next_frame(&mut buffer).unwrap();
+            let control = reader.info().frame_control.unwrap();
+            frames.push((control.delay_num, control.delay_den, buffer[..3].to_vec()));
+        }
+        assert_eq!(frames, [(3, 60, vec![255, 0, 0]), (7, 60, vec![0, 0, 255]), (6, 60, vec![255, 0, 0])]);
+    }
+
+    #[test]
+    fn an_empty_recording_is_not_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("empty.gif");
+        assert!(Recorder::new(Filter::Nearest, 1).save(&path, RecordingFormat::Gif, 10).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn formats_from_paths_and_names() {
+        assert_eq!(RecordingFormat::for_path(Path::new("a.PNG")), RecordingFormat::Apng);
+        assert_eq!(RecordingFormat::for_path(Path::new("a.apng")), RecordingFormat::Apng);
+        assert_eq!(RecordingFormat::for_path(Path::new("a")), RecordingFormat::Gif);
+        assert_eq!("APNG".parse(), Ok(RecordingFormat::Apng));
+        assert!("mp4".parse::<RecordingFormat>().is_err());
+    }
+
+    #[test]
+    fn capture_paths_skip_files_that_exist() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let options = CaptureOptions {
+            dir: dir.to_path_buf(),
+            ..CaptureOptions::default()
+        };
+        let first = options.next_path("game", "png");
+        assert_eq!(first, dir.join("game-0001.png"));
+        File::create(&first).unwrap();
+        assert_eq!(options.next_path("game", "png"), dir.join("game-0002.png"));
+        assert_eq!(options.next_path("game", "gif"), dir.join("game-0001.gif"));
+    }
+}
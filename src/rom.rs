@@ -1,16 +1,103 @@
+use crate::assembler;
+use crate::cartridge::{self, Cartridge};
+use sha1::{Digest, Sha1};
 use std::fs::File;
 use std::io::{prelude::*};
-use std::io::{self};
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
 
-pub(crate) struct Rom {
+pub struct Rom {
     pub bytes: Vec<u8>,
+    // the source and options when the ROM was loaded from an Octo cartridge
+    pub cartridge: Option<Cartridge>,
 }
 
-pub(crate) fn read(file_name: &str) -> io::Result<Rom> {
-    let mut file = File::open(&file_name)?;
+impl Rom {
+    // a ROM image or an Octo cartridge GIF already in memory
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Rom> {
+        if cartridge::is_cartridge(bytes) {
+            return from_cartridge(bytes);
+        }
+        return Ok(Rom { bytes: bytes.to_vec(), cartridge: None });
+    }
+
+    // lowercase hex SHA-1 of the ROM, the key used by the CHIP-8 database
+    pub fn sha1(&self) -> String {
+        let digest = Sha1::digest(&self.bytes);
+        return digest.iter().map(|b| format!("{:02x}", b)).collect();
+    }
+}
+
+// Octo cartridges are assembled from the source they carry
+fn from_cartridge(bytes: &[u8]) -> io::Result<Rom> {
+    let cartridge = cartridge::decode(bytes).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    let assembly = assembler::assemble(&cartridge.program)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("cartridge source:{}", e)))?;
+    return Ok(Rom {
+        bytes: assembly.bytes,
+        cartridge: Some(cartridge),
+    });
+}
+
+pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Rom> {
+    let mut file = File::open(path)?;
     
     let mut program_buffer = Vec::new();
     file.read_to_end(&mut program_buffer)?;
-    
-    return Ok(Rom { bytes: program_buffer });
+    return Rom::from_bytes(&program_buffer);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::OctoOptions;
+    use crate::cpu::load_rom;
+    use crate::headless_platform::HeadlessPlatform;
+    use crate::machine::Framebuffer;
+    use crate::screenshot;
+    use crate::settings::{Palette, Settings};
+    use std::fs;
+
+    #[test]
+    fn bytes_are_a_rom_as_they_are() {
+        let rom = Rom::from_bytes(&[0x00, 0xe0]).unwrap();
+        assert_eq!(rom.bytes, [0x00, 0xe0]);
+        assert!(rom.cartridge.is_none());
+        assert_eq!(Rom::from_bytes(&[]).unwrap().sha1(), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+    }
+
+    #[test]
+    fn a_cartridge_in_memory_is_assembled() {
+        let cartridge = Cartridge {
+            program: String::from(": main\n\tclear\n\tloop again\n"),
+            options: OctoOptions::default(),
+        };
+        let gif = cartridge::encode(&cartridge, &Palette::default(), &Framebuffer::default()).unwrap();
+        let rom = Rom::from_bytes(&gif).unwrap();
+        assert_eq!(rom.bytes, [0x00, 0xe0, 0x12, 0x02]);
+        assert_eq!(rom.cartridge.unwrap().program, cartridge.program);
+    }
+
+    #[test]
+    fn a_file_reads_the_same_as_its_bytes() {
+        let bytes = fs::read("test/2-ibm-logo.ch8").unwrap();
+        let rom = read("test/2-ibm-logo.ch8").unwrap();
+        assert_eq!(rom.bytes, bytes);
+        assert_eq!(rom.sha1(), Rom::from_bytes(&bytes).unwrap().sha1());
+        assert_eq!(read("test/missing.ch8").err().unwrap().kind(), ErrorKind::NotFound);
+    }
+
+    // the way the library is meant to be used, without a window
+    #[test]
+    fn a_rom_from_bytes_runs_headlessly() {
+        let bytes = fs::read("test/2-ibm-logo.ch8").unwrap();
+        let mut program = load_rom(Rom::from_bytes(&bytes).unwrap()).unwrap();
+        program.realtime = false;
+        program.frame_limit = Some(30);
+        let mut platform = HeadlessPlatform::new(&Settings::default());
+        platform.run(&mut program);
+        assert_eq!(program.frames, 30);
+        let golden = fs::read_to_string("tests/golden/2-ibm-logo.modern.txt").unwrap();
+        assert_eq!(screenshot::ascii(platform.screen()), golden);
+    }
 }
@@ -0,0 +1,93 @@
+use crate::machine::Framebuffer;
+use crate::settings::{Palette, Rotation};
+use crate::upscale::{Filter, Image};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+// the screen the way the window shows it: in the colours of the palette, turned and upscaled so every
+// CHIP-8 pixel is about scale x scale
+pub fn screen_image(
+    screen: &Framebuffer,
+    palette: &Palette,
+    rotation: Rotation,
+    filter: Filter,
+    scale: u32,
+) -> Image {
+    return Image::from_screen(screen, palette).rotated(rotation).upscaled(filter, scale);
+}
+
+pub fn save_png(path: &Path, image: &Image) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), image.width as u32, image.height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder
+        .write_header()
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    writer
+        .write_image_data(&image.to_rgb())
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    return Ok(());
+}
+
+// one line per screen row, '#' for a lit pixel and '.' for an unlit one
+pub fn ascii(screen: &Framebuffer) -> String {
+    let mut out = String::new();
+    for y in 0..screen.height() {
+        for x in 0..screen.width() {
+            out.push(if screen.pixel(x, y) > 0 { '#' } else { '.' });
+        }
+        out.push('\n');
+    }
+    return out;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+
+    fn screen() -> Framebuffer {
+        let mut screen = Framebuffer::default();
+        screen.set_pixel(0, 0, 1);
+        screen.set_pixel(63, 31, 1);
+        return screen;
+    }
+
+    #[test]
+    fn the_image_is_turned_and_upscaled() {
+        let palette = Palette::default();
+        let image = screen_image(&screen(), &palette, Rotation::None, Filter::Nearest, 3);
+        assert_eq!((image.width, image.height), (192, 96));
+        assert_eq!(image.pixels[2 * 192 + 2], palette.foreground);
+        assert_eq!(image.pixels[3], palette.background);
+
+        let turned = screen_image(&screen(), &palette, Rotation::Clockwise, Filter::Scale2x, 2);
+        assert_eq!((turned.width, turned.height), (64, 128));
+    }
+
+    #[test]
+    fn png_has_the_pixels_of_the_image() {
+        let image = screen_image(&screen(), &Palette::default(), Rotation::None, Filter::Nearest, 2);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("screen.png");
+        save_png(&path, &image).unwrap();
+        let mut reader = png::Decoder::new(BufReader::new(File::open(&path).unwrap())).read_info().unwrap();
+        let mut rgb = vec![0; reader.output_buffer_size().unwrap()];
+        reader.next_frame(&mut rgb).unwrap();
+        assert_eq!(reader.info().size(), (128, 64));
+        assert_eq!(rgb, image.to_rgb());
+    }
+
+    #[test]
+    fn ascii_has_a_line_per_row() {
+        let text = ascii(&screen());
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 32);
+        assert_eq!(&lines[0][..3], "#..");
+        assert!(lines[31].ends_with(".#"));
+        assert_eq!(text.matches('#').count(), 2);
+    }
+}
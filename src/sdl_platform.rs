@@ -1,86 +1,304 @@
-use crate::keyboard::char_to_index;
-use crate::platform::Platform;
-use crate::platform::PlatformContext;
+use crate::audio::Buzzer;
+use crate::keymap::Keymap;
+use crate::machine::Framebuffer;
+use crate::persistence::PersistenceFilter;
+use crate::machine::State;
+use crate::platform::{Command, Event as EmulatorEvent, KeyInput, Platform, PlatformContext};
+use crate::recording::{CaptureOptions, Recorder, RecordingFormat};
+use crate::screenshot;
+use crate::settings::{Palette, Rgb, Rotation, Settings};
+use crate::upscale::{Filter, Image};
 
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Scancode};
+use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::Point;
 use sdl2::rect::Rect;
 use sdl2::render::Canvas;
-use sdl2::video::Window;
-use sdl2::EventPump;
-use std::collections::HashMap;
-use std::time::Duration;
-
-lazy_static! {
-    pub(crate) static ref VALID_KEYS_TO_CHAR: HashMap<Keycode, char> = HashMap::from([
-        (Keycode::Num0, '0'),
-        (Keycode::Num1, '1'),
-        (Keycode::Num2, '2'),
-        (Keycode::Num3, '3'),
-        (Keycode::Num4, '4'),
-        (Keycode::Num5, '5'),
-        (Keycode::Num6, '6'),
-        (Keycode::Num7, '7'),
-        (Keycode::Num8, '8'),
-        (Keycode::Num9, '9'),
-        (Keycode::A, 'A'),
-        (Keycode::B, 'B'),
-        (Keycode::C, 'C'),
-        (Keycode::D, 'D'),
-        (Keycode::E, 'E'),
-        (Keycode::F, 'F'),
-    ]);
+use sdl2::video::{FullscreenType, Window};
+use sdl2::{EventPump, TimerSubsystem};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const SAMPLE_RATE: i32 = 44100;
+
+// window pixels per CHIP-8 pixel when the settings don't say
+const DEFAULT_SCALE: u32 = 10;
+
+fn color(rgb: Rgb) -> Color {
+    return Color::RGB(rgb.0, rgb.1, rgb.2);
+}
+
+// when SDL saw an event, from its timestamp in milliseconds since SDL started and the time and ticks now
+fn event_time(now: (Instant, u32), timestamp: u32) -> Instant {
+    let ago = Duration::from_millis(u64::from(now.1.wrapping_sub(timestamp)));
+    return now.0.checked_sub(ago).unwrap_or(now.0);
+}
+
+struct BuzzerCallback {
+    buzzer: Buzzer,
 }
 
-pub(crate) struct SdlPlatform {
+impl AudioCallback for BuzzerCallback {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.buzzer.fill(out);
+    }
+}
+
+// a recording started from the keyboard or the command line, frames are counted at 60Hz from when it started
+struct Recording {
+    recorder: Recorder,
+    path: PathBuf,
+    format: RecordingFormat,
+    started: Instant,
+}
+
+impl Recording {
+    fn frame(&self) -> u64 {
+        return (self.started.elapsed().as_nanos() * 60 / 1_000_000_000) as u64;
+    }
+}
+
+pub struct SdlPlatform {
     running: bool,
 
     canvas: Canvas<Window>,
     event_pump: EventPump,
+    // stamps the key events with when they happened
+    timer: TimerSubsystem,
 
+    keys: HashMap<Scancode, u8>,
+    // physical keys held down, a CHIP-8 key is released once none of the keys bound to it are
+    held: HashSet<Scancode>,
+    palette: Palette,
+    // None when there is no audio device, the emulator still runs but silently
+    audio: Option<AudioDevice<BuzzerCallback>>,
+    sounding: bool,
+    playing: bool,
+    muted: bool,
     keyboard_state: [u8; 16],
-    width: u32,
-    height: u32,
-    pixels: Vec<Rect>,
+    paused: bool,
+    // the state saved with F5, F7 loads it
+    saved: Option<Box<State>>,
+    screen: PersistenceFilter,
+    grid: bool,
+    rotation: Rotation,
+    fullscreen: bool,
+    filter: Filter,
+    // window pixels per CHIP-8 pixel in screenshots and recordings
+    scale: u32,
+    picture: Image,
+    capture: CaptureOptions,
+    recording: Option<Recording>,
+    title: String,
+    // the window is only redrawn when something on it changed
+    dirty: bool,
 }
 
-impl Default for SdlPlatform {
-    fn default() -> Self {
-        let sdl = sdl2::init().unwrap();
-        let video_subsystem = sdl.video().unwrap();
+impl SdlPlatform {
+    pub fn new(settings: &Settings) -> Result<Self, String> {
+        let sdl = sdl2::init()?;
+        let video_subsystem = sdl.video()?;
 
-        let w = 800;
-        let h = 600;
+        // vertical games turn the window on its side too
+        let scale = settings.scale.unwrap_or(DEFAULT_SCALE).max(1);
+        // a whole number of filtered pixels so the first frame fills the window
+        let factor = settings.filter.factor() as u32;
+        let scale = (scale / factor).max(1) * factor;
+        let (columns, rows) = settings.rotation.size(64, 32);
+        let (w, h) = (columns as u32 * scale, rows as u32 * scale);
 
-        let window = video_subsystem
-            .window("chip8", w, h)
+        let title = match &settings.title {
+            Some(title) => format!("chip8 - {}", title),
+            None => String::from("chip8"),
+        };
+        let mut window = video_subsystem
+            .window(&title, w, h)
             .position_centered()
+            .resizable()
             .build()
-            .expect("Could not initialize SDL Video Subsystem");
+            .map_err(|e| format!("Could not initialize SDL Video Subsystem: {}", e))?;
+        match window.set_minimum_size(columns as u32, rows as u32) {
+            Ok(_) => (),
+            Err(_) => (),
+        }
+        if settings.fullscreen {
+            window.set_fullscreen(FullscreenType::Desktop)?;
+        }
 
         let canvas = window
             .into_canvas()
             .build()
-            .expect("Could not make a canvas.");
+            .map_err(|e| format!("Could not make a canvas: {}", e))?;
+
+        let event_pump = sdl.event_pump()?;
+        let timer = sdl.timer()?;
 
-        let event_pump = sdl.event_pump().unwrap();
+        let desired = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: Some(512),
+        };
+        let tone = settings.tone;
+        let audio = sdl.audio().and_then(|audio| {
+            audio.open_playback(None, &desired, |spec| BuzzerCallback {
+                buzzer: Buzzer::new(tone, spec.freq as u32),
+            })
+        });
+        let audio = match audio {
+            Ok(device) => {
+                device.resume();
+                Some(device)
+            }
+            Err(e) => {
+                eprintln!("warning: no sound: {}", e);
+                None
+            }
+        };
 
-        let mut pixels = Vec::<Rect>::new();
-        for _ in 0..2048 {
-            pixels.push(Rect::new(0, 0, 0, 0));
+        // keys are bound by where they are on the keyboard rather than what they are labelled
+        let mut keys = HashMap::new();
+        for (name, key) in Keymap::for_settings(settings).keys.iter() {
+            let scancode = Scancode::from_name(name).ok_or_else(|| format!("unknown key '{}'", name))?;
+            keys.insert(scancode, *key);
         }
 
-        Self {
+        return Ok(Self {
             running: false,
-            canvas: canvas,
-            event_pump: event_pump,
+            canvas,
+            event_pump,
+            timer,
+            keys,
+            held: HashSet::new(),
+            palette: settings.palette,
+            audio,
+            sounding: false,
+            playing: false,
+            muted: settings.muted,
             keyboard_state: [0u8; 16],
-            width: w,
-            height: h,
-            pixels: pixels,
+            paused: false,
+            saved: None,
+            screen: PersistenceFilter::new(settings.persistence),
+            grid: settings.grid,
+            rotation: settings.rotation,
+            fullscreen: settings.fullscreen,
+            filter: settings.filter,
+            scale,
+            picture: Image::from_screen(&Framebuffer::default(), &settings.palette).rotated(settings.rotation),
+            capture: CaptureOptions::default(),
+            recording: None,
+            title,
+            dirty: true,
+        });
+    }
+
+    // what the screen shows before it is upscaled, with fading pixels and turned the right way up
+    fn picture(&self) -> Image {
+        let screen = &self.screen;
+        let palette = &self.palette;
+        return Image::from_fn(screen.width(), screen.height(), |x, y| {
+            let (pixel, brightness) = screen.pixel(x, y);
+            palette.shade(pixel, brightness)
+        })
+        .rotated(self.rotation);
+    }
+
+    pub fn set_capture(&mut self, capture: CaptureOptions) {
+        if let Some(path) = &capture.record {
+            self.start_recording(path.clone(), RecordingFormat::for_path(path));
+        }
+        self.capture = capture;
+    }
+
+    fn save_screenshot(&self, path: &Path) {
+        let image = self.picture.upscaled(self.filter, self.scale);
+        match screenshot::save_png(path, &image) {
+            Ok(_) => println!("saved {}", path.display()),
+            Err(e) => eprintln!("warning: no screenshot: {}", e),
+        }
+    }
+
+    fn start_recording(&mut self, path: PathBuf, format: RecordingFormat) {
+        let mut recorder = Recorder::new(self.filter, self.scale);
+        recorder.add(0, &self.picture);
+        self.recording = Some(Recording {
+            recorder,
+            path,
+            format,
+            started: Instant::now(),
+        });
+    }
+
+    fn stop_recording(&mut self) {
+        if let Some(recording) = self.recording.take() {
+            match recording.recorder.save(&recording.path, recording.format, recording.frame()) {
+                Ok(_) => println!("saved {}", recording.path.display()),
+                Err(e) => eprintln!("warning: no recording: {}", e),
+            }
+        }
+    }
+
+    // the filtered screen at the largest whole number scale that fits the window, centred with the background
+    // colour around it
+    fn draw(&mut self) {
+        let (width, height) = match self.canvas.output_size() {
+            Ok(size) => size,
+            Err(_) => return,
+        };
+        let image = self.picture.filtered(self.filter);
+        let (columns, rows) = (image.width as u32, image.height as u32);
+        let scale = (width / columns).min(height / rows).max(1);
+        let left = (width as i32 - (columns * scale) as i32) / 2;
+        let top = (height as i32 - (rows * scale) as i32) / 2;
+
+        self.canvas.set_draw_color(color(self.palette.background));
+        self.canvas.clear();
+
+        let texture_creator = self.canvas.texture_creator();
+        if let Ok(mut texture) = texture_creator.create_texture_static(PixelFormatEnum::RGB24, columns, rows) {
+            match texture.update(None, &image.to_rgb(), image.width * 3) {
+                Ok(_) => (),
+                Err(_) => (),
+            }
+            match self.canvas.copy(&texture, None, Rect::new(left, top, columns * scale, rows * scale)) {
+                Ok(_) => (),
+                Err(_) => (),
+            }
+        }
+
+        // draw a grid in the background colour between CHIP-8 pixels, there is no room for one at a scale of 1
+        let cell = self.filter.factor() as u32 * scale;
+        if self.grid && cell > 1 {
+            self.canvas.set_draw_color(color(self.palette.background));
+            let right = left + (columns * scale) as i32;
+            let bottom = top + (rows * scale) as i32;
+            for column in 1..columns * scale / cell {
+                let x = left + (column * cell) as i32;
+                match self.canvas.draw_line(Point::new(x, top), Point::new(x, bottom)) {
+                    Ok(_) => (),
+                    Err(_) => (),
+                }
+            }
+            for row in 1..rows * scale / cell {
+                let y = top + (row * cell) as i32;
+                match self.canvas.draw_line(Point::new(left, y), Point::new(right, y)) {
+                    Ok(_) => (),
+                    Err(_) => (),
+                }
+            }
         }
+
+        self.canvas.present();
+    }
+}
+
+impl Default for SdlPlatform {
+    fn default() -> Self {
+        return SdlPlatform::new(&Settings::default()).unwrap();
     }
 }
 
@@ -88,7 +306,7 @@ impl Platform for SdlPlatform {
     fn start(&mut self, context: &PlatformContext) {
         self.running = true;
 
-        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.canvas.set_draw_color(color(self.palette.background));
         self.canvas.clear();
         self.canvas.present();
 
@@ -98,112 +316,228 @@ impl Platform for SdlPlatform {
             Platform::render(self, context);
             ::std::thread::sleep(Duration::new(0, 1_000_000u32 / 30));
         }
+
+        self.stop_recording();
+        if let Some(path) = self.capture.screenshot.clone() {
+            self.save_screenshot(&path);
+        }
     }
 
     fn update(&mut self, context: &PlatformContext) {
+        let mut screenshot = false;
+        let mut record = false;
+        let now = (Instant::now(), self.timer.ticks());
         for event in self.event_pump.poll_iter() {
             match event {
-                Event::KeyDown { keycode, .. } => {
-                    let key = keycode.unwrap();
-                    if VALID_KEYS_TO_CHAR.contains_key(&key) {
-                        let key_char = VALID_KEYS_TO_CHAR.get(&key).unwrap();
-
+                Event::KeyDown {
+                    timestamp,
+                    keycode,
+                    scancode: Some(scancode),
+                    repeat,
+                    ..
+                } => {
+                    // keys that aren't bound to the keypad control the window
+                    if !repeat && !self.keys.contains_key(&scancode) {
+                        match keycode {
+                            Some(Keycode::M) => self.muted = !self.muted,
+                            Some(Keycode::G) => self.grid = !self.grid,
+                            Some(Keycode::F9) => {
+                                self.filter = self.filter.next();
+                                let title = format!("{} [{}]", self.title, self.filter);
+                                match self.canvas.window_mut().set_title(&title) {
+                                    Ok(_) => (),
+                                    Err(_) => (),
+                                }
+                            }
+                            Some(Keycode::P) => {
+                                self.paused = !self.paused;
+                                context.send(if self.paused { Command::Pause } else { Command::Resume });
+                                let title = match self.paused {
+                                    true => format!("{} [paused]", self.title),
+                                    false => self.title.clone(),
+                                };
+                                match self.canvas.window_mut().set_title(&title) {
+                                    Ok(_) => (),
+                                    Err(_) => (),
+                                }
+                            }
+                            Some(Keycode::F2) => context.send(Command::Reset),
+                            Some(Keycode::F5) => context.send(Command::SaveState),
+                            Some(Keycode::F7) => match &self.saved {
+                                Some(state) => context.send(Command::LoadState(state.clone())),
+                                None => eprintln!("warning: no state saved yet, F5 saves one"),
+                            },
+                            Some(Keycode::F10) => record = true,
+                            Some(Keycode::F12) => screenshot = true,
+                            Some(Keycode::F11) => {
+                                let mode = if self.fullscreen { FullscreenType::Off } else { FullscreenType::Desktop };
+                                match self.canvas.window_mut().set_fullscreen(mode) {
+                                    Ok(_) => self.fullscreen = !self.fullscreen,
+                                    Err(e) => eprintln!("warning: could not change fullscreen: {}", e),
+                                }
+                            }
+                            _ => (),
+                        }
+                        self.dirty = true;
+                    }
+                    if let Some(&k) = self.keys.get(&scancode) {
                         // update the platform keyboard state
-                        self.keyboard_state[char_to_index(*key_char)] = 1;
+                        let k = usize::from(k);
+                        if self.keyboard_state[k] == 0 {
+                            let mut key = KeyInput::press(k as u8);
+                            key.at = event_time(now, timestamp);
+                            context.send(Command::Key(key));
+                        }
+                        self.keyboard_state[k] = 1;
+                        self.held.insert(scancode);
                     }
                 }
-                Event::KeyUp { keycode, .. } => {
-                    let key = keycode.unwrap();
-                    if VALID_KEYS_TO_CHAR.contains_key(&key) {
-                        let keychar = VALID_KEYS_TO_CHAR.get(&key).unwrap();
-                        let k = char_to_index(*keychar);
+                Event::KeyUp {
+                    timestamp,
+                    scancode: Some(scancode),
+                    ..
+                } => {
+                    self.held.remove(&scancode);
+                    if let Some(&k) = self.keys.get(&scancode) {
+                        let still_held = self.held.iter().any(|held| self.keys.get(held) == Some(&k));
+                        let k = usize::from(k);
+                        if still_held {
+                            continue;
+                        }
                         if self.keyboard_state[k] == 1 {
                             // previous state was 1 and now it's going to be zero
-                            match context.single_key.try_send(*keychar) {
-                                Ok(_) => (),
-                                Err(_) => (),
-                            }
+                            let mut key = KeyInput::release(k as u8);
+                            key.at = event_time(now, timestamp);
+                            context.send(Command::Key(key));
                         }
                         self.keyboard_state[k] = 0;
                     }
                 }
+                Event::Window { win_event, .. } => {
+                    if matches!(win_event, WindowEvent::Exposed | WindowEvent::SizeChanged(..)) {
+                        self.dirty = true;
+                    }
+                }
                 Event::Quit { .. } => {
+                    context.send(Command::Quit);
                     self.running = false;
                 }
                 _ => {}
             }
         }
 
-        // send the platform keyboard state to the emulator
-        match context.keyboard.try_send(self.keyboard_state) {
-            Ok(_) => (),
-            Err(_) => (),
+        if screenshot {
+            let path = self.capture.next_path("screenshot", "png");
+            self.save_screenshot(&path);
+        }
+        if record && self.recording.is_some() {
+            self.stop_recording();
+        } else if record {
+            let format = self.capture.format;
+            let path = self.capture.next_path("recording", format.extension());
+            self.start_recording(path, format);
         }
     }
 
     fn render(&mut self, context: &PlatformContext) {
-        let sound_option = context.sound.try_recv();
-        if sound_option.is_ok() {
-            let sound = sound_option.unwrap();
-            if sound {
-                //let _audio = self.sdl_context.audio().unwrap();
-                // play sound
+        while let Ok(event) = context.events.try_recv() {
+            match event {
+                EmulatorEvent::Screen(update) => {
+                    self.screen.push(&update);
+                    self.dirty = true;
+                }
+                EmulatorEvent::Sound(on) => self.sounding = on,
+                EmulatorEvent::State(state) => {
+                    self.saved = Some(state);
+                    println!("saved the state, F7 loads it");
+                }
+                // the window stays open with the last screen until it is closed
+                EmulatorEvent::Status(_) | EmulatorEvent::Stopped => (),
             }
         }
 
-        let pixels_option = context.display.try_recv();
-        if pixels_option.is_ok() {
-            let pixels = pixels_option.unwrap();
-            for y in 0..32 {
-                for x in 0..64 {
-                    let idx = y * 64 + x;
-                    if pixels[x][y] > 0 {
-                        self.pixels[idx] = Rect::new(
-                            (x as f32 * (self.width as f32 / 64.0)).round() as i32,
-                            (y as f32 * (self.height as f32 / 32.0)).round() as i32,
-                            (self.width as f32 / 64.0).round() as u32,
-                            (self.height as f32 / 32.0).round() as u32,
-                        );
-                    } else {
-                        self.pixels[idx] = Rect::new(0, 0, 0, 0);
-                    }
-                }
+        let on = !self.muted && self.sounding;
+        if on != self.playing {
+            self.playing = on;
+            // the buzzer fades in and out by itself, so switching it is all that's needed
+            if let Some(audio) = &mut self.audio {
+                audio.lock().buzzer.on = on;
             }
         }
 
-        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
-        self.canvas.clear();
-        self.canvas.set_draw_color(Color::RGB(255, 255, 255));
-
-        match self.canvas.fill_rects(&self.pixels) {
-            Ok(_) => (),
-            Err(_) => (),
+        if self.screen.advance() {
+            self.dirty = true;
         }
-
-        // draw black line grid
-        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
-        for x in 0..64 {
-            let xx = x as f32 * ((self.width as f32) / 64.0);
-            match self.canvas.draw_line(
-                Point::new(xx.round() as i32, 0),
-                Point::new(xx.round() as i32, self.height as i32),
-            ) {
-                Ok(_) => (),
-                Err(_) => (),
+        if self.dirty {
+            self.picture = self.picture();
+            if let Some(recording) = &mut self.recording {
+                let frame = recording.frame();
+                recording.recorder.add(frame, &self.picture);
             }
+            self.draw();
+            self.dirty = false;
         }
+    }
+}
 
-        for y in 0..32 {
-            let yy = y as f32 * ((self.height as f32) / 32.0);
-            match self.canvas.draw_line(
-                Point::new(0, yy.round() as i32),
-                Point::new(self.width as i32, yy.round() as i32),
-            ) {
-                Ok(_) => (),
-                Err(_) => (),
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::create_contexts;
 
-        self.canvas.present();
+    // the most the loudness moves from one sample to the next while the buzzer fades, a click would jump by the
+    // whole volume
+    const MAX_STEP: f32 = 0.01;
+
+    // SDL's dummy drivers stand in for a screen and a sound card
+    fn platform(settings: &Settings) -> SdlPlatform {
+        std::env::set_var("SDL_AUDIODRIVER", "dummy");
+        std::env::set_var("SDL_VIDEODRIVER", "dummy");
+        let platform = SdlPlatform::new(settings).unwrap();
+        // the test plays the samples itself rather than the audio thread
+        platform.audio.as_ref().expect("no audio device").pause();
+        return platform;
+    }
+
+    // what the audio thread would play next with the sound timer running or not
+    fn play(platform: &mut SdlPlatform, on: bool) -> Vec<f32> {
+        let (platform_context, cpu_context) = create_contexts();
+        cpu_context.send(EmulatorEvent::Sound(on));
+        platform.render(&platform_context);
+        let mut samples = vec![0.0; 1024];
+        platform.audio.as_mut().unwrap().lock().buzzer.fill(&mut samples);
+        return samples;
+    }
+
+    // how much louder or quieter one sample is than the one before, a square wave is as loud as its gain
+    fn largest_step(samples: &[f32]) -> f32 {
+        return samples.windows(2).map(|pair| (pair[1].abs() - pair[0].abs()).abs()).fold(0.0, f32::max);
+    }
+
+    // the default tone is a square wave
+    #[test]
+    fn the_buzzer_ramps_in_and_out() {
+        let settings = Settings::default();
+        let mut platform = platform(&settings);
+        let volume = settings.tone.volume;
+
+        let on = play(&mut platform, true);
+        assert!(on[0].abs() <= MAX_STEP, "starts from silence, {}", on[0]);
+        assert!(largest_step(&on) <= MAX_STEP);
+        assert!((on[on.len() - 1].abs() - volume).abs() <= f32::EPSILON);
+
+        let off = play(&mut platform, false);
+        assert!((off[0].abs() - volume).abs() <= MAX_STEP, "fades from where it was, {}", off[0]);
+        assert!(largest_step(&off) <= MAX_STEP);
+        assert!(off.windows(2).all(|pair| pair[1].abs() <= pair[0].abs()));
+        assert_eq!(off[off.len() - 1], 0.0);
+
+        drop(platform);
+        let muted = Settings {
+            muted: true,
+            ..Settings::default()
+        };
+        let mut platform = self::platform(&muted);
+        assert!(play(&mut platform, true).iter().all(|sample| *sample == 0.0));
     }
 }
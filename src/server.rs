@@ -0,0 +1,529 @@
+use crate::cpu::{Program, Runnable};
+use crate::machine::State;
+use crate::platform::{create_contexts, Command, CpuContext, Event, KeyInput, Platform, PlatformContext};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::{
+    fs::FileTypeExt,
+    net::{UnixListener, UnixStream},
+};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// lets other processes drive the emulator: JSON-RPC 2.0 over a loopback TCP port or a Unix socket, one
+// request or response per line. Only one client is served at a time, another one that connects waits
+// without an answer until the first disconnects, and the program carries over from one to the next.
+//
+//   > {"jsonrpc": "2.0", "id": 1, "method": "load", "params": {"path": "roms/pong.ch8"}}
+//   < {"jsonrpc": "2.0", "id": 1, "result": {"sha1": "...", "size": 246}}
+//   > {"jsonrpc": "2.0", "id": 2, "method": "press", "params": {"key": 1}}
+//   > {"jsonrpc": "2.0", "id": 3, "method": "step", "params": {"frames": 2}}
+//   < {"jsonrpc": "2.0", "method": "drawn", "params": {"frame": 1, "rows": [0, 1, 2]}}
+//   < {"jsonrpc": "2.0", "id": 3, "result": {"frames": 2, "frame": 2, "pc": 522, "halted": false, ...}}
+//
+// The program only runs when it is stepped. Notifications for what happened during a step (drawn, sound
+// and halted) come before its response. Keys pressed and released go to the program at the start of the
+// next frame, a release a frame after its press at the earliest.
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+// a request that was understood but couldn't be carried out
+const FAILED: i64 = -32000;
+
+// the most frames one step runs, a minute of the program, so a step can't keep the server from the client
+// for long
+const MAX_STEP_FRAMES: u64 = 3600;
+
+// the slot save_state and load_state use when the request doesn't name one
+const DEFAULT_SLOT: &str = "default";
+
+// why a request failed, sent back as its error
+#[derive(Debug)]
+struct Failure {
+    code: i64,
+    message: String,
+}
+
+impl Failure {
+    fn invalid_params(message: &str) -> Self {
+        return Failure {
+            code: INVALID_PARAMS,
+            message: message.to_string(),
+        };
+    }
+}
+
+impl From<String> for Failure {
+    fn from(message: String) -> Self {
+        return Failure { code: FAILED, message };
+    }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    // the socket file is removed again when the server goes away
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    // a connection split into lines coming in and a stream going out
+    fn accept(&self) -> io::Result<(Box<dyn BufRead>, Box<dyn Write>)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nodelay(true)?;
+                let reader = stream.try_clone()?;
+                return Ok((Box::new(BufReader::new(reader)), Box::new(stream)));
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => {
+                let (stream, _) = listener.accept()?;
+                let reader = stream.try_clone()?;
+                return Ok((Box::new(BufReader::new(reader)), Box::new(stream)));
+            }
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Listener::Unix(_, path) = self {
+            match fs::remove_file(path) {
+                Ok(_) => (),
+                Err(_) => (),
+            }
+        }
+    }
+}
+
+// the Platform end of a client: key presses wait here for the next frame and what the emulator sends back
+// goes out to the client as notifications
+struct RpcPlatform {
+    out: Box<dyn Write>,
+    // the client went away, nothing more is written
+    closed: bool,
+    keys: Vec<KeyInput>,
+    // the frame the events being rendered came from
+    frame: u64,
+    saved: Option<Box<State>>,
+    stopped: bool,
+}
+
+impl RpcPlatform {
+    fn new(out: Box<dyn Write>) -> Self {
+        return RpcPlatform {
+            out,
+            closed: false,
+            keys: Vec::new(),
+            frame: 0,
+            saved: None,
+            stopped: false,
+        };
+    }
+
+    fn write(&mut self, message: &Value) {
+        if self.closed {
+            return;
+        }
+        // in one piece, a message written a bit at a time waits on the TCP acknowledgements in between
+        let line = format!("{}\n", message);
+        let result = self.out.write_all(line.as_bytes()).and_then(|_| self.out.flush());
+        if result.is_err() {
+            self.closed = true;
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.write(&json!({"jsonrpc": "2.0", "method": method, "params": params}));
+    }
+}
+
+impl Platform for RpcPlatform {
+    // for a program running on another thread, events are passed on at 60Hz until it stops
+    fn start(&mut self, context: &PlatformContext) {
+        while !self.stopped && !self.closed {
+            Platform::update(self, context);
+            ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+            Platform::render(self, context);
+        }
+    }
+
+    fn update(&mut self, context: &PlatformContext) {
+        for key in self.keys.drain(..) {
+            context.send(Command::Key(key));
+        }
+    }
+
+    fn render(&mut self, context: &PlatformContext) {
+        while let Ok(event) = context.events.try_recv() {
+            match event {
+                Event::Screen(update) => {
+                    let rows: Vec<usize> = update.rows.iter().map(|(y, _)| *y).collect();
+                    let frame = self.frame;
+                    self.notify("drawn", json!({"frame": frame, "rows": rows}));
+                }
+                Event::Sound(on) => {
+                    let frame = self.frame;
+                    self.notify("sound", json!({"frame": frame, "on": on}));
+                }
+                Event::State(state) => self.saved = Some(state),
+                Event::Status(_) => (),
+                Event::Stopped => self.stopped = true,
+            }
+        }
+    }
+}
+
+// turns the path in a load request into a program
+type Loader<'a> = dyn Fn(&Path) -> Result<Program, String> + 'a;
+
+// a loaded program and the channels to it
+struct Session {
+    program: Program,
+    platform_context: PlatformContext,
+    cpu_context: CpuContext,
+    // the halted notification went out
+    halted: bool,
+}
+
+pub struct Server<'a> {
+    listener: Listener,
+    address: String,
+    // with whatever settings the ROM should run with
+    loader: Box<Loader<'a>>,
+    session: Option<Session>,
+    slots: HashMap<String, Box<State>>,
+}
+
+impl<'a> Server<'a> {
+    // listens on host:port, which has to be a loopback address as anyone who can connect controls the
+    // emulator, or on a Unix socket at a path with a / in it or after unix:
+    pub fn bind(address: &str, loader: impl Fn(&Path) -> Result<Program, String> + 'a) -> Result<Self, String> {
+        let listener = match address.strip_prefix("unix:") {
+            Some(path) => unix_listener(path)?,
+            None if address.contains('/') => unix_listener(address)?,
+            None => Listener::Tcp(tcp_listener(address)?),
+        };
+        let address = match &listener {
+            Listener::Tcp(listener) => listener.local_addr().map_err(|e| e.to_string())?.to_string(),
+            #[cfg(unix)]
+            Listener::Unix(_, path) => path.display().to_string(),
+        };
+        return Ok(Server {
+            listener,
+            address,
+            loader: Box::new(loader),
+            session: None,
+            slots: HashMap::new(),
+        });
+    }
+
+    // where clients connect, with the port filled in when port 0 was asked for
+    pub fn address(&self) -> &str {
+        return &self.address;
+    }
+
+    // starts a program the way a load request does
+    pub fn load(&mut self, path: &Path) -> Result<(), String> {
+        let mut program = (self.loader)(path)?;
+        program.realtime = false;
+        let (platform_context, cpu_context) = create_contexts();
+        self.session = Some(Session {
+            program,
+            platform_context,
+            cpu_context,
+            halted: false,
+        });
+        return Ok(());
+    }
+
+    // serves clients until accepting one fails
+    pub fn serve(&mut self) -> Result<(), String> {
+        loop {
+            let (input, output) = self.listener.accept().map_err(|e| format!("{}: {}", self.address, e))?;
+            self.serve_client(input, output);
+        }
+    }
+
+    fn serve_client(&mut self, input: Box<dyn BufRead>, output: Box<dyn Write>) {
+        let mut platform = RpcPlatform::new(output);
+        for line in input.lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(response) = self.handle(&mut platform, &line) {
+                platform.write(&response);
+            }
+            if platform.closed {
+                break;
+            }
+        }
+    }
+
+    // the response to a line from a client, None for a notification
+    fn handle(&mut self, platform: &mut RpcPlatform, line: &str) -> Option<Value> {
+        let request: Value = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(e) => return Some(error(Value::Null, PARSE_ERROR, &e.to_string())),
+        };
+        let id = request.get("id").cloned();
+        let method = match (request.get("jsonrpc").and_then(Value::as_str), request.get("method")) {
+            (Some("2.0"), Some(Value::String(method))) => method.as_str(),
+            _ => return Some(error(id.unwrap_or(Value::Null), INVALID_REQUEST, "not a JSON-RPC 2.0 request")),
+        };
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+        let result = self.call(platform, method, &params);
+        let id = id?;
+        return Some(match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err(failure) => error(id, failure.code, &failure.message),
+        });
+    }
+
+    fn call(&mut self, platform: &mut RpcPlatform, method: &str, params: &Value) -> Result<Value, Failure> {
+        if method == "load" {
+            let path = params.get("path").and_then(Value::as_str).ok_or(Failure::invalid_params("expected a path"))?;
+            self.load(Path::new(path))?;
+            let program = &self.session.as_ref().unwrap().program;
+            return Ok(json!({"sha1": program.rom.sha1(), "size": program.rom.bytes.len()}));
+        }
+
+        let session = match &mut self.session {
+            Some(session) => session,
+            None if is_method(method) => return Err(Failure::from(String::from("no ROM loaded yet"))),
+            None => return Err(not_found(method)),
+        };
+        let program = &mut session.program;
+        match method {
+            "reset" => {
+                session.platform_context.send(Command::Reset);
+                program.commands(&session.cpu_context);
+                session.halted = false;
+                return Ok(Value::Null);
+            }
+            "step" => {
+                let frames = match params.get("frames") {
+                    None => 1,
+                    Some(frames) => frames
+                        .as_u64()
+                        .filter(|frames| *frames <= MAX_STEP_FRAMES)
+                        .ok_or(Failure::invalid_params("frames is a count up to 3600"))?,
+                };
+                let mut ran = 0;
+                // a client that went away mid-step isn't waiting for the rest of it
+                while ran < frames && !platform.closed {
+                    platform.update(&session.platform_context);
+                    let running = program.run_frame(&session.cpu_context);
+                    platform.frame = program.frames;
+                    platform.render(&session.platform_context);
+                    if !running {
+                        break;
+                    }
+                    ran += 1;
+                }
+                let halted = program.cpu.halted();
+                if halted && !session.halted {
+                    let pc = program.cpu.pc();
+                    platform.notify("halted", json!({"frame": program.frames, "pc": pc}));
+                }
+                session.halted = halted;
+                return Ok(json!({
+                    "frames": ran,
+                    "frame": program.frames,
+                    "pc": program.cpu.pc(),
+                    "halted": halted,
+                    "waiting_for_key": program.cpu.waiting_for_key(),
+                }));
+            }
+            "press" | "release" => {
+                let key = key(params)?;
+                let key = if method == "press" { KeyInput::press(key) } else { KeyInput::release(key) };
+                platform.keys.push(key);
+                return Ok(Value::Null);
+            }
+            "registers" => {
+                let cpu = &program.cpu;
+                let state = cpu.state();
+                return Ok(json!({
+                    "v": cpu.registers().to_vec(),
+                    "i": cpu.i(),
+                    "pc": cpu.pc(),
+                    "sp": state.sp,
+                    "delay": cpu.delay(),
+                    "sound": cpu.sound(),
+                    "keys": cpu.keys().to_vec(),
+                    "waiting_for_key": cpu.waiting_for_key(),
+                    "halted": cpu.halted(),
+                    "frame": program.frames,
+                    "instructions": program.instructions,
+                }));
+            }
+            "read_memory" => {
+                let address = address(params)?;
+                let length = match params.get("length") {
+                    None => 1,
+                    Some(length) => length.as_u64().ok_or(Failure::invalid_params("length is a count"))?,
+                };
+                if length > 4096 {
+                    return Err(Failure::invalid_params("at most 4096 bytes can be read"));
+                }
+                let memory = program.cpu.memory();
+                let data: String = (0..length as usize)
+                    .map(|n| format!("{:02x}", memory[(usize::from(address) + n) & 0xfff]))
+                    .collect();
+                return Ok(json!({"address": address, "data": data}));
+            }
+            "write_memory" => {
+                let address = address(params)?;
+                let data = params.get("data").and_then(Value::as_str).ok_or(Failure::invalid_params("expected data"))?;
+                let bytes = hex(data).ok_or(Failure::invalid_params("data is pairs of hex digits"))?;
+                program.cpu.write_memory(address, &bytes);
+                return Ok(json!({"written": bytes.len()}));
+            }
+            "framebuffer" => {
+                // a digit per pixel, the planes it is lit on
+                let screen = program.cpu.screen();
+                let rows: Vec<String> = (0..screen.height())
+                    .map(|y| (0..screen.width()).map(|x| char::from(b'0' + screen.pixel(x, y))).collect())
+                    .collect();
+                return Ok(json!({"width": screen.width(), "height": screen.height(), "rows": rows}));
+            }
+            "save_state" => {
+                let slot = slot(params)?;
+                session.platform_context.send(Command::SaveState);
+                program.commands(&session.cpu_context);
+                platform.render(&session.platform_context);
+                let state = platform.saved.take().ok_or(Failure::from(String::from("the emulator sent no state")))?;
+                self.slots.insert(slot.clone(), state);
+                return Ok(json!({"slot": slot}));
+            }
+            "load_state" => {
+                let slot = slot(params)?;
+                let state = match self.slots.get(&slot) {
+                    Some(state) => state.clone(),
+                    None => return Err(Failure::from(format!("nothing saved in the slot '{}'", slot))),
+                };
+                session.platform_context.send(Command::LoadState(state));
+                program.commands(&session.cpu_context);
+                session.halted = program.cpu.halted();
+                return Ok(json!({"slot": slot}));
+            }
+            _ => return Err(not_found(method)),
+        }
+    }
+}
+
+// every address the host resolves to has to be a loopback one, localhost may well be more than one
+fn tcp_listener(address: &str) -> Result<TcpListener, String> {
+    let sockets: Vec<SocketAddr> = match address.to_socket_addrs() {
+        Ok(sockets) => sockets.collect(),
+        Err(e) => return Err(format!("{}: {}, a Unix socket needs a path with a / or unix: in front", address, e)),
+    };
+    if let Some(socket) = sockets.iter().find(|socket| !socket.ip().is_loopback()) {
+        return Err(format!(
+            "{}: {} isn't a loopback address, there is no authentication",
+            address,
+            socket.ip()
+        ));
+    }
+    return TcpListener::bind(&sockets[..]).map_err(|e| format!("{}: {}", address, e));
+}
+
+#[cfg(unix)]
+fn unix_listener(address: &str) -> Result<Listener, String> {
+    let path = PathBuf::from(address);
+    // a socket left behind by a server that didn't shut down cleanly is in the way, one that still answers
+    // belongs to a server that is running
+    if fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        match UnixStream::connect(&path) {
+            Ok(_) => return Err(format!("{}: another server is listening there", address)),
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => match fs::remove_file(&path) {
+                Ok(_) => (),
+                Err(_) => (),
+            },
+            Err(e) => return Err(format!("{}: {}", address, e)),
+        }
+    }
+    let listener = UnixListener::bind(&path).map_err(|e| format!("{}: {}", address, e))?;
+    return Ok(Listener::Unix(listener, path));
+}
+
+#[cfg(not(unix))]
+fn unix_listener(address: &str) -> Result<Listener, String> {
+    return Err(format!("{}: expected a loopback host:port, Unix sockets need a Unix", address));
+}
+
+const METHODS: [&str; 10] = [
+    "reset",
+    "step",
+    "press",
+    "release",
+    "registers",
+    "read_memory",
+    "write_memory",
+    "framebuffer",
+    "save_state",
+    "load_state",
+];
+
+fn is_method(method: &str) -> bool {
+    return METHODS.contains(&method);
+}
+
+fn not_found(method: &str) -> Failure {
+    return Failure {
+        code: METHOD_NOT_FOUND,
+        message: format!("no method '{}'", method),
+    };
+}
+
+fn error(id: Value, code: i64, message: &str) -> Value {
+    return json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}});
+}
+
+// a key as a number or a hex digit
+fn key(params: &Value) -> Result<u8, Failure> {
+    let key = match params.get("key") {
+        Some(Value::Number(key)) => key.as_u64().filter(|key| *key < 16),
+        Some(Value::String(key)) if key.len() == 1 => key.chars().next().and_then(|c| c.to_digit(16)).map(u64::from),
+        _ => None,
+    };
+    return key.map(|key| key as u8).ok_or(Failure::invalid_params("key is 0 to 15 or a hex digit"));
+}
+
+fn address(params: &Value) -> Result<u16, Failure> {
+    return params
+        .get("address")
+        .and_then(Value::as_u64)
+        .filter(|address| *address < 4096)
+        .map(|address| address as u16)
+        .ok_or(Failure::invalid_params("address is 0 to 4095"));
+}
+
+fn slot(params: &Value) -> Result<String, Failure> {
+    return match params.get("slot") {
+        None => Ok(String::from(DEFAULT_SLOT)),
+        Some(Value::String(slot)) => Ok(slot.clone()),
+        Some(_) => Err(Failure::invalid_params("slot is a name")),
+    };
+}
+
+fn hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    return (0..text.len()).step_by(2).map(|n| u8::from_str_radix(text.get(n..n + 2)?, 16).ok()).collect();
+}
@@ -0,0 +1,285 @@
+use crate::audio::Tone;
+use crate::persistence::Persistence;
+use crate::quirks::Quirks;
+use crate::upscale::Filter;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl FromStr for Rgb {
+    type Err = String;
+
+    // accepts RRGGBB with an optional leading '#'
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.trim_start_matches('#');
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("invalid colour '{}' (expected RRGGBB)", s));
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        return Ok(Rgb(channel(0), channel(2), channel(4)));
+    }
+}
+
+impl fmt::Display for Rgb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "#{:02x}{:02x}{:02x}", self.0, self.1, self.2);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub background: Rgb,
+    pub foreground: Rgb,
+    // XO-CHIP pixels lit on the second plane only and on both planes
+    pub plane2: Rgb,
+    pub blend: Rgb,
+}
+
+pub const PALETTE_PRESETS: [&str; 6] = ["mono", "inverse", "octo", "amber", "green", "lcd"];
+
+impl Palette {
+    pub fn preset(name: &str) -> Option<Palette> {
+        let (background, foreground, plane2, blend) = match name.to_ascii_lowercase().as_str() {
+            "mono" => (0x000000, 0xffffff, 0xaaaaaa, 0x555555),
+            "inverse" => (0xffffff, 0x000000, 0x555555, 0xaaaaaa),
+            // the colours Octo starts with
+            "octo" => (0x996600, 0xffcc00, 0xff6600, 0x662200),
+            "amber" => (0x1a0f00, 0xffb000, 0xb37b00, 0xffd966),
+            "green" => (0x001a00, 0x33ff33, 0x1f991f, 0x99ff99),
+            // the 4 greens of the original Game Boy
+            "lcd" => (0x9bbc0f, 0x0f380f, 0x8bac0f, 0x306230),
+            _ => return None,
+        };
+        let rgb = |color: u32| Rgb((color >> 16) as u8, (color >> 8) as u8, color as u8);
+        return Some(Palette {
+            background: rgb(background),
+            foreground: rgb(foreground),
+            plane2: rgb(plane2),
+            blend: rgb(blend),
+        });
+    }
+
+    // the colour of a screen pixel, bit 0 is the first plane and bit 1 the second
+    pub fn color(&self, pixel: u8) -> Rgb {
+        match pixel & 3 {
+            0 => self.background,
+            1 => self.foreground,
+            2 => self.plane2,
+            _ => self.blend,
+        }
+    }
+
+    // the colour of a pixel part way between dark and fully lit
+    pub fn shade(&self, pixel: u8, brightness: f32) -> Rgb {
+        let (from, to) = (self.background, self.color(pixel));
+        let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * brightness.clamp(0.0, 1.0)).round() as u8;
+        return Rgb(mix(from.0, to.0), mix(from.1, to.1), mix(from.2, to.2));
+    }
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return Palette::preset(s)
+            .ok_or_else(|| format!("unknown palette '{}' (expected {})", s, PALETTE_PRESETS.join(", ")));
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        return Palette::preset("mono").unwrap();
+    }
+}
+
+// clockwise, for games meant to be played with the screen on its side
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Rotation {
+    #[default]
+    None,
+    Clockwise,
+    UpsideDown,
+    Anticlockwise,
+}
+
+impl Rotation {
+    pub fn from_degrees(degrees: u32) -> Result<Rotation, String> {
+        match degrees {
+            0 => Ok(Rotation::None),
+            90 => Ok(Rotation::Clockwise),
+            180 => Ok(Rotation::UpsideDown),
+            270 => Ok(Rotation::Anticlockwise),
+            _ => Err(format!("invalid rotation {} (expected 0, 90, 180 or 270)", degrees)),
+        }
+    }
+
+    // the size of a width x height screen once rotated
+    pub fn size(&self, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Rotation::None | Rotation::UpsideDown => (width, height),
+            Rotation::Clockwise | Rotation::Anticlockwise => (height, width),
+        }
+    }
+
+    // where the pixel at x, y of a width x height screen ends up once rotated
+    pub fn apply(&self, x: usize, y: usize, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Rotation::None => (x, y),
+            Rotation::Clockwise => (height - 1 - y, x),
+            Rotation::UpsideDown => (width - 1 - x, height - 1 - y),
+            Rotation::Anticlockwise => (y, width - 1 - x),
+        }
+    }
+}
+
+impl FromStr for Rotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let degrees = s
+            .parse::<u32>()
+            .map_err(|_| format!("invalid rotation '{}' (expected 0, 90, 180 or 270)", s))?;
+        return Rotation::from_degrees(degrees);
+    }
+}
+
+// which physical keys drive the 16 key hex keypad, see keymap.rs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyLayout {
+    // the keys labelled 0-9 and A-F on a US keyboard
+    Hex,
+    // the 4x4 block under 1 2 3 4 laid out like the COSMAC VIP keypad, the three only differ in the
+    // characters those keys type which matters to the terminal
+    Qwerty,
+    Azerty,
+    Qwertz,
+}
+
+impl FromStr for KeyLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "hex" => Ok(KeyLayout::Hex),
+            "cosmac" | "qwerty" => Ok(KeyLayout::Qwerty),
+            "azerty" => Ok(KeyLayout::Azerty),
+            "qwertz" => Ok(KeyLayout::Qwertz),
+            _ => Err(format!("unknown key layout '{}' (expected qwerty, azerty, qwertz or hex)", s)),
+        }
+    }
+}
+
+// everything about a run that can be configured from outside of the ROM
+#[derive(Clone, Debug)]
+pub struct Settings {
+    // shown in the window title when the ROM was found in the database
+    pub title: Option<String>,
+    // None leaves the quirks suggested by analysing the ROM
+    pub quirks: Option<Quirks>,
+    // instructions executed per second
+    pub frequency: f32,
+    // size of a CHIP-8 pixel in window pixels, None lets each platform pick
+    pub scale: Option<u32>,
+    pub palette: Palette,
+    // lines between the CHIP-8 pixels
+    pub grid: bool,
+    pub rotation: Rotation,
+    pub fullscreen: bool,
+    // keeps pixels lit for a while to hide flicker
+    pub persistence: Persistence,
+    // how the screen is upscaled before it is stretched to fit
+    pub filter: Filter,
+    pub key_layout: KeyLayout,
+    // extra controls bound to keypad keys, named up, down, left, right, a and b
+    pub buttons: BTreeMap<String, u8>,
+    // CHIP-8 keys bound by hand to physical keys, replacing what the layout binds them to
+    pub bindings: BTreeMap<u8, Vec<String>>,
+    pub seed: Option<u64>,
+    // the buzzer played while the sound timer runs
+    pub tone: Tone,
+    pub muted: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        return Settings {
+            title: None,
+            quirks: None,
+            frequency: 2000.0, // 2kHz
+            scale: None,
+            palette: Palette::default(),
+            grid: false,
+            rotation: Rotation::None,
+            fullscreen: false,
+            persistence: Persistence::Off,
+            filter: Filter::Nearest,
+            key_layout: KeyLayout::Qwerty,
+            buttons: BTreeMap::new(),
+            bindings: BTreeMap::new(),
+            seed: None,
+            tone: Tone::default(),
+            muted: false,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colours_parse_and_print() {
+        assert_eq!("#ffb000".parse(), Ok(Rgb(0xff, 0xb0, 0x00)));
+        assert_eq!("0F380f".parse(), Ok(Rgb(0x0f, 0x38, 0x0f)));
+        assert_eq!(Rgb(1, 2, 255).to_string(), "#0102ff");
+        for bad in ["fff", "#12345g", "1234567"] {
+            assert!(bad.parse::<Rgb>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn every_preset_is_a_palette() {
+        for name in PALETTE_PRESETS {
+            let palette: Palette = name.to_uppercase().parse().unwrap();
+            assert_ne!(palette.background, palette.foreground, "{}", name);
+        }
+        assert!("sepia".parse::<Palette>().unwrap_err().contains("mono, inverse"));
+    }
+
+    #[test]
+    fn pixels_take_the_colour_of_their_planes() {
+        let palette = Palette::preset("octo").unwrap();
+        let colors: Vec<Rgb> = (0..4).map(|pixel| palette.color(pixel)).collect();
+        assert_eq!(colors, [palette.background, palette.foreground, palette.plane2, palette.blend]);
+        // a half lit pixel is half way from the background
+        let palette = Palette::default();
+        assert_eq!(palette.shade(1, 0.5), Rgb(128, 128, 128));
+        assert_eq!(palette.shade(1, 0.0), palette.background);
+        assert_eq!(palette.shade(1, 2.0), palette.foreground);
+    }
+
+    #[test]
+    fn rotations_turn_the_corners() {
+        // the top left corner of a 64x32 screen
+        let corners: Vec<(usize, usize)> = [0, 90, 180, 270]
+            .iter()
+            .map(|degrees| Rotation::from_degrees(*degrees).unwrap().apply(0, 0, 64, 32))
+            .collect();
+        assert_eq!(corners, [(0, 0), (31, 0), (63, 31), (0, 63)]);
+        assert_eq!(Rotation::Clockwise.size(64, 32), (32, 64));
+        assert_eq!(Rotation::UpsideDown.size(64, 32), (64, 32));
+        assert_eq!("270".parse(), Ok(Rotation::Anticlockwise));
+        assert!("45".parse::<Rotation>().is_err());
+        assert!("left".parse::<Rotation>().is_err());
+    }
+
+    #[test]
+    fn layouts_parse() {
+        assert_eq!("COSMAC".parse(), Ok(KeyLayout::Qwerty));
+        assert_eq!("qwertz".parse(), Ok(KeyLayout::Qwertz));
+        assert!("dvorak".parse::<KeyLayout>().is_err());
+    }
+}
@@ -0,0 +1,387 @@
+use crate::keymap::{self, Keymap};
+use crate::persistence::{Persistence, PersistenceFilter};
+use crate::platform::{Command, Event as EmulatorEvent, KeyInput, Platform, PlatformContext, Status};
+use crate::settings::{KeyLayout, Palette, Rgb, Settings};
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::{cursor, queue, terminal};
+use std::collections::HashMap;
+use std::io::{self, Stdout, Write};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// how the framebuffer is drawn with text
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Glyphs {
+    // one character per 1x2 pixels using ▀ ▄ █
+    HalfBlock,
+    // one character per 2x4 pixels using braille dots, small but fits hires in an 80 column terminal
+    Braille,
+}
+
+impl FromStr for Glyphs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "half" | "halfblock" | "half-block" => Ok(Glyphs::HalfBlock),
+            "braille" => Ok(Glyphs::Braille),
+            _ => Err(format!("unknown glyphs '{}' (expected half or braille)", s)),
+        }
+    }
+}
+
+// terminals report characters rather than where a key is, so the key map's physical keys become the
+// characters they type on the layout
+fn key_code(name: &str, layout: KeyLayout) -> Option<KeyCode> {
+    match name {
+        "Up" => Some(KeyCode::Up),
+        "Down" => Some(KeyCode::Down),
+        "Left" => Some(KeyCode::Left),
+        "Right" => Some(KeyCode::Right),
+        "Space" => Some(KeyCode::Char(' ')),
+        "Return" => Some(KeyCode::Enter),
+        name => keymap::key_char(name, layout).map(KeyCode::Char),
+    }
+}
+
+fn color(rgb: Rgb) -> Color {
+    return Color::Rgb {
+        r: rgb.0,
+        g: rgb.1,
+        b: rgb.2,
+    };
+}
+
+// lines of text drawing a width x height framebuffer where lit(x, y) says if a pixel is on
+fn draw_lines(glyphs: Glyphs, width: usize, height: usize, lit: impl Fn(usize, usize) -> bool) -> Vec<String> {
+    let mut lines = Vec::new();
+    match glyphs {
+        Glyphs::HalfBlock => {
+            for y in (0..height).step_by(2) {
+                let line: String = (0..width)
+                    .map(|x| match (lit(x, y), y + 1 < height && lit(x, y + 1)) {
+                        (true, true) => '█',
+                        (true, false) => '▀',
+                        (false, true) => '▄',
+                        (false, false) => ' ',
+                    })
+                    .collect();
+                lines.push(line);
+            }
+        }
+        Glyphs::Braille => {
+            // bit of each dot in a braille cell, indexed by [y][x]
+            const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+            for y in (0..height).step_by(4) {
+                let line: String = (0..width)
+                    .step_by(2)
+                    .map(|x| {
+                        let mut bits = 0;
+                        for (dy, dots) in DOTS.iter().enumerate() {
+                            for (dx, dot) in dots.iter().enumerate() {
+                                if x + dx < width && y + dy < height && lit(x + dx, y + dy) {
+                                    bits |= dot;
+                                }
+                            }
+                        }
+                        char::from_u32(0x2800 + bits).unwrap()
+                    })
+                    .collect();
+                lines.push(line);
+            }
+        }
+    }
+    return lines;
+}
+
+pub struct TerminalPlatform {
+    running: bool,
+    stdout: Stdout,
+
+    keys: HashMap<KeyCode, u8>,
+    keyboard_state: [u8; 16],
+    // terminals that can't report key releases get a release this long after the last press or repeat
+    key_timeout: Duration,
+    key_release_events: bool,
+    last_pressed: [Option<Instant>; 16],
+
+    glyphs: Glyphs,
+    palette: Palette,
+    persistence: Persistence,
+    screen: PersistenceFilter,
+    // the framebuffer size last drawn
+    size: (usize, usize),
+    dirty: bool,
+
+    status: Option<Status>,
+    sound: bool,
+    // the emulator frame at `fps_since`, used to show the actual speed
+    fps_frame: u64,
+    fps_since: Instant,
+    fps: f32,
+}
+
+impl TerminalPlatform {
+    pub fn new(settings: &Settings, glyphs: Glyphs, key_timeout: Duration) -> Result<Self, String> {
+        let mut stdout = io::stdout();
+        terminal::enable_raw_mode().map_err(|e| format!("Could not set up the terminal: {}", e))?;
+        // the kitty keyboard protocol reports key releases, everything else needs the timeout
+        let key_release_events = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if key_release_events {
+            let flags = KeyboardEnhancementFlags::REPORT_EVENT_TYPES;
+            queue!(stdout, PushKeyboardEnhancementFlags(flags)).map_err(|e| e.to_string())?;
+        }
+        queue!(stdout, terminal::EnterAlternateScreen, cursor::Hide, terminal::Clear(terminal::ClearType::All))
+            .map_err(|e| e.to_string())?;
+        stdout.flush().map_err(|e| e.to_string())?;
+
+        let mut keys = HashMap::new();
+        for (name, key) in Keymap::for_settings(settings).keys.iter() {
+            if let Some(code) = key_code(name, settings.key_layout) {
+                keys.insert(code, *key);
+            }
+        }
+
+        return Ok(Self {
+            running: false,
+            stdout,
+            keys,
+            keyboard_state: [0u8; 16],
+            key_timeout,
+            key_release_events,
+            last_pressed: [None; 16],
+            glyphs,
+            palette: settings.palette,
+            persistence: settings.persistence,
+            screen: PersistenceFilter::new(settings.persistence),
+            size: (0, 0),
+            dirty: true,
+            status: None,
+            sound: false,
+            fps_frame: 0,
+            fps_since: Instant::now(),
+            fps: 0.0,
+        });
+    }
+
+    fn press(&mut self, key: u8, context: &PlatformContext) {
+        let k = usize::from(key);
+        if self.keyboard_state[k] == 0 {
+            context.send(Command::Key(KeyInput::press(key)));
+        }
+        self.keyboard_state[k] = 1;
+        self.last_pressed[k] = Some(Instant::now());
+    }
+
+    fn release(&mut self, key: u8, context: &PlatformContext) {
+        let k = usize::from(key);
+        if self.keyboard_state[k] == 1 {
+            // previous state was 1 and now it's going to be zero
+            context.send(Command::Key(KeyInput::release(key)));
+        }
+        self.keyboard_state[k] = 0;
+        self.last_pressed[k] = None;
+    }
+
+    fn key_event(&mut self, event: KeyEvent, context: &PlatformContext) {
+        let quit = event.code == KeyCode::Esc
+            || (event.code == KeyCode::Char('c') && event.modifiers.contains(KeyModifiers::CONTROL));
+        if quit {
+            context.send(Command::Quit);
+            self.running = false;
+            return;
+        }
+
+        let code = match event.code {
+            KeyCode::Char(c) => KeyCode::Char(c.to_ascii_lowercase()),
+            code => code,
+        };
+        let key = match self.keys.get(&code) {
+            Some(key) => *key,
+            None => return,
+        };
+        match event.kind {
+            KeyEventKind::Press | KeyEventKind::Repeat => self.press(key, context),
+            KeyEventKind::Release => self.release(key, context),
+        }
+    }
+
+    fn footer(&self) -> String {
+        let mut footer = match &self.status {
+            Some(status) => format!(
+                "{:.0} ips  {:.1} fps  frame {}  pc {:03x}  i {:03x}",
+                status.frequency, self.fps, status.frame, status.pc, status.i
+            ),
+            None => String::from("stopped"),
+        };
+        if self.status.is_some_and(|status| status.waiting_for_key) {
+            footer.push_str("  waiting for a key");
+        }
+        if self.sound {
+            footer.push_str("  ♪");
+        }
+        footer.push_str("  [esc] quit");
+        return footer;
+    }
+
+    // the footer goes on the line under the framebuffer, which is taller in hires
+    fn footer_row(&self) -> u16 {
+        let height = self.screen.height();
+        return match self.glyphs {
+            Glyphs::HalfBlock => height.div_ceil(2) as u16,
+            Glyphs::Braille => height.div_ceil(4) as u16,
+        };
+    }
+
+    // every character is ▀ with the top pixel as its foreground and the bottom one as its background so
+    // fading pixels can be drawn in between colours
+    fn draw_shaded(&mut self) -> io::Result<()> {
+        let mut colors = None;
+        let (width, height) = (self.screen.width(), self.screen.height());
+        for row in 0..height / 2 {
+            queue!(self.stdout, cursor::MoveTo(0, row as u16))?;
+            for x in 0..width {
+                let (top, top_brightness) = self.screen.pixel(x, row * 2);
+                let (bottom, bottom_brightness) = self.screen.pixel(x, row * 2 + 1);
+                let cell = (
+                    self.palette.shade(top, top_brightness),
+                    self.palette.shade(bottom, bottom_brightness),
+                );
+                if colors != Some(cell) {
+                    queue!(self.stdout, SetForegroundColor(color(cell.0)), SetBackgroundColor(color(cell.1)))?;
+                    colors = Some(cell);
+                }
+                queue!(self.stdout, Print('▀'))?;
+            }
+        }
+        return Ok(());
+    }
+
+    // the screen is only drawn when it changed, the footer every frame
+    fn draw(&mut self, screen_changed: bool) -> io::Result<()> {
+        let shaded = self.glyphs == Glyphs::HalfBlock && self.persistence != Persistence::Off;
+        // switching between lores and hires changes how much of the terminal the screen covers
+        let size = (self.screen.width(), self.screen.height());
+        if size != self.size {
+            queue!(self.stdout, ResetColor, terminal::Clear(terminal::ClearType::All))?;
+            self.size = size;
+        }
+        if screen_changed && shaded {
+            self.draw_shaded()?;
+        } else if screen_changed {
+            // braille dots can't be dimmed, a fading pixel stays until it is half as bright
+            let screen = &self.screen;
+            let lines = draw_lines(self.glyphs, screen.width(), screen.height(), |x, y| screen.pixel(x, y).1 >= 0.5);
+            queue!(
+                self.stdout,
+                SetForegroundColor(color(self.palette.foreground)),
+                SetBackgroundColor(color(self.palette.background))
+            )?;
+            for (row, line) in lines.iter().enumerate() {
+                queue!(self.stdout, cursor::MoveTo(0, row as u16), Print(line))?;
+            }
+        }
+        let (row, footer) = (self.footer_row(), self.footer());
+        queue!(
+            self.stdout,
+            ResetColor,
+            cursor::MoveTo(0, row),
+            terminal::Clear(terminal::ClearType::CurrentLine),
+            Print(footer)
+        )?;
+        return self.stdout.flush();
+    }
+}
+
+impl Drop for TerminalPlatform {
+    fn drop(&mut self) {
+        if self.key_release_events {
+            match queue!(self.stdout, PopKeyboardEnhancementFlags) {
+                Ok(_) => (),
+                Err(_) => (),
+            }
+        }
+        match queue!(self.stdout, ResetColor, cursor::Show, terminal::LeaveAlternateScreen) {
+            Ok(_) => (),
+            Err(_) => (),
+        }
+        match self.stdout.flush() {
+            Ok(_) => (),
+            Err(_) => (),
+        }
+        match terminal::disable_raw_mode() {
+            Ok(_) => (),
+            Err(_) => (),
+        }
+    }
+}
+
+impl Platform for TerminalPlatform {
+    fn start(&mut self, context: &PlatformContext) {
+        self.running = true;
+
+        // platform loop
+        while self.running {
+            Platform::update(self, context);
+            Platform::render(self, context);
+            ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+        }
+    }
+
+    fn update(&mut self, context: &PlatformContext) {
+        while event::poll(Duration::ZERO).unwrap_or(false) {
+            match event::read() {
+                Ok(Event::Key(key)) => self.key_event(key, context),
+                Ok(Event::Resize(_, _)) => {
+                    match queue!(self.stdout, terminal::Clear(terminal::ClearType::All)) {
+                        Ok(_) => (),
+                        Err(_) => (),
+                    }
+                    self.dirty = true;
+                }
+                _ => {}
+            }
+        }
+
+        // without key up events a key counts as released once it stops repeating
+        if !self.key_release_events {
+            for k in 0..16 {
+                if self.last_pressed[k].is_some_and(|at| at.elapsed() >= self.key_timeout) {
+                    self.release(k as u8, context);
+                }
+            }
+        }
+    }
+
+    fn render(&mut self, context: &PlatformContext) {
+        while let Ok(event) = context.events.try_recv() {
+            match event {
+                EmulatorEvent::Screen(update) => {
+                    self.screen.push(&update);
+                    self.dirty = true;
+                }
+                EmulatorEvent::Sound(on) => self.sound = on,
+                EmulatorEvent::Status(status) => self.status = Some(status),
+                EmulatorEvent::State(_) | EmulatorEvent::Stopped => (),
+            }
+        }
+        if self.screen.advance() {
+            self.dirty = true;
+        }
+        if self.fps_since.elapsed() >= Duration::from_secs(1) {
+            let frame = self.status.map_or(0, |status| status.frame);
+            self.fps = (frame - self.fps_frame) as f32 / self.fps_since.elapsed().as_secs_f32();
+            self.fps_frame = frame;
+            self.fps_since = Instant::now();
+        }
+
+        match self.draw(self.dirty) {
+            Ok(_) => self.dirty = false,
+            Err(_) => self.running = false,
+        }
+    }
+}
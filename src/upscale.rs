@@ -0,0 +1,328 @@
+use crate::machine::Framebuffer;
+use crate::settings::{Palette, Rgb, Rotation};
+use std::fmt;
+use std::str::FromStr;
+
+// turns the low resolution screen into a bigger picture before it is stretched to fit, the same for
+// the window, screenshots and headless frame dumps
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Filter {
+    // plain square pixels
+    #[default]
+    Nearest,
+    // smooths diagonal edges, also known as EPX
+    Scale2x,
+    Scale3x,
+    // a dark line under every row of pixels
+    Scanlines,
+    // scanlines over red, green and blue stripes like the mask of a CRT
+    Crt,
+}
+
+pub const FILTERS: [Filter; 5] = [Filter::Nearest, Filter::Scale2x, Filter::Scale3x, Filter::Scanlines, Filter::Crt];
+
+impl Filter {
+    // how many pixels wide and high every pixel becomes
+    pub fn factor(&self) -> usize {
+        match self {
+            Filter::Nearest => 1,
+            Filter::Scale2x => 2,
+            Filter::Scale3x | Filter::Scanlines | Filter::Crt => 3,
+        }
+    }
+
+    // the one after this, for cycling through them with a key
+    pub fn next(&self) -> Filter {
+        let i = FILTERS.iter().position(|filter| filter == self).unwrap();
+        return FILTERS[(i + 1) % FILTERS.len()];
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Filter::Nearest => "nearest",
+            Filter::Scale2x => "scale2x",
+            Filter::Scale3x => "scale3x",
+            Filter::Scanlines => "scanlines",
+            Filter::Crt => "crt",
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        if s == "epx" {
+            return Ok(Filter::Scale2x);
+        }
+        return FILTERS.iter().copied().find(|filter| filter.name() == s).ok_or_else(|| {
+            let names: Vec<&str> = FILTERS.iter().map(|filter| filter.name()).collect();
+            format!("unknown filter '{}' (expected {})", s, names.join(", "))
+        });
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}", self.name());
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    // row by row
+    pub pixels: Vec<Rgb>,
+}
+
+impl Image {
+    pub fn from_fn(width: usize, height: usize, pixel: impl Fn(usize, usize) -> Rgb) -> Image {
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                pixels.push(pixel(x, y));
+            }
+        }
+        return Image { width, height, pixels };
+    }
+
+    // the screen in the colours of the palette
+    pub fn from_screen(screen: &Framebuffer, palette: &Palette) -> Image {
+        return Image::from_fn(screen.width(), screen.height(), |x, y| palette.color(screen.pixel(x, y)));
+    }
+
+    // the pixel at x, y where anything outside of the image repeats the nearest edge
+    fn at(&self, x: isize, y: isize) -> Rgb {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        return self.pixels[y * self.width + x];
+    }
+
+    pub fn rotated(&self, rotation: Rotation) -> Image {
+        let (width, height) = rotation.size(self.width, self.height);
+        let mut pixels = vec![Rgb(0, 0, 0); width * height];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let (u, v) = rotation.apply(x, y, self.width, self.height);
+                pixels[v * width + u] = self.pixels[y * self.width + x];
+            }
+        }
+        return Image { width, height, pixels };
+    }
+
+    pub fn filtered(&self, filter: Filter) -> Image {
+        let factor = filter.factor();
+        let mut pixels = vec![Rgb(0, 0, 0); self.width * factor * self.height * factor];
+        let width = self.width * factor;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let block = match filter {
+                    Filter::Nearest => vec![self.pixels[y * self.width + x]],
+                    Filter::Scale2x => self.scale2x(x as isize, y as isize).to_vec(),
+                    Filter::Scale3x => self.scale3x(x as isize, y as isize).to_vec(),
+                    Filter::Scanlines => scanlines(self.pixels[y * self.width + x], false).to_vec(),
+                    Filter::Crt => scanlines(self.pixels[y * self.width + x], true).to_vec(),
+                };
+                for (i, color) in block.iter().enumerate() {
+                    let (dx, dy) = (i % factor, i / factor);
+                    pixels[(y * factor + dy) * width + x * factor + dx] = *color;
+                }
+            }
+        }
+        return Image {
+            width,
+            height: self.height * factor,
+            pixels,
+        };
+    }
+
+    // filtered and then scaled so every pixel is about scale x scale, rounded down to a whole number of
+    // filtered pixels
+    pub fn upscaled(&self, filter: Filter, scale: u32) -> Image {
+        return self.filtered(filter).scaled(scale as usize / filter.factor());
+    }
+
+    // every pixel becomes a scale x scale square
+    pub fn scaled(&self, scale: usize) -> Image {
+        let scale = scale.max(1);
+        return Image::from_fn(self.width * scale, self.height * scale, |x, y| {
+            self.pixels[(y / scale) * self.width + x / scale]
+        });
+    }
+
+    // RGB bytes row by row
+    pub fn to_rgb(&self) -> Vec<u8> {
+        let mut rgb = Vec::with_capacity(self.pixels.len() * 3);
+        for color in self.pixels.iter() {
+            rgb.extend_from_slice(&[color.0, color.1, color.2]);
+        }
+        return rgb;
+    }
+
+    // the neighbours of E are named
+    //   A B C
+    //   D E F
+    //   G H I
+    // and a 2x2 or 3x3 block replaces E, with corners taking the colour of edges that meet there
+
+    fn scale2x(&self, x: isize, y: isize) -> [Rgb; 4] {
+        let (b, d, e, f, h) =
+            (self.at(x, y - 1), self.at(x - 1, y), self.at(x, y), self.at(x + 1, y), self.at(x, y + 1));
+        if b == h || d == f {
+            return [e; 4];
+        }
+        return [
+            if d == b { d } else { e },
+            if b == f { f } else { e },
+            if d == h { d } else { e },
+            if h == f { f } else { e },
+        ];
+    }
+
+    fn scale3x(&self, x: isize, y: isize) -> [Rgb; 9] {
+        let (a, b, c) = (self.at(x - 1, y - 1), self.at(x, y - 1), self.at(x + 1, y - 1));
+        let (d, e, f) = (self.at(x - 1, y), self.at(x, y), self.at(x + 1, y));
+        let (g, h, i) = (self.at(x - 1, y + 1), self.at(x, y + 1), self.at(x + 1, y + 1));
+        if b == h || d == f {
+            return [e; 9];
+        }
+        return [
+            if d == b { d } else { e },
+            if (d == b && e != c) || (b == f && e != a) { b } else { e },
+            if b == f { f } else { e },
+            if (d == b && e != g) || (d == h && e != a) { d } else { e },
+            e,
+            if (b == f && e != i) || (h == f && e != c) { f } else { e },
+            if d == h { d } else { e },
+            if (d == h && e != i) || (h == f && e != g) { h } else { e },
+            if h == f { f } else { e },
+        ];
+    }
+}
+
+// a 3x3 block with the bottom row dimmed, and with crt every column favouring red, green or blue
+fn scanlines(color: Rgb, crt: bool) -> [Rgb; 9] {
+    let dim = |color: Rgb, r: f32, g: f32, b: f32| {
+        Rgb((color.0 as f32 * r) as u8, (color.1 as f32 * g) as u8, (color.2 as f32 * b) as u8)
+    };
+    let mut block = [color; 9];
+    for (i, pixel) in block.iter_mut().enumerate() {
+        let (column, row) = (i % 3, i / 3);
+        if crt {
+            *pixel = match column {
+                0 => dim(*pixel, 1.0, 0.7, 0.7),
+                1 => dim(*pixel, 0.7, 1.0, 0.7),
+                _ => dim(*pixel, 0.7, 0.7, 1.0),
+            };
+        }
+        if row == 2 {
+            *pixel = dim(*pixel, 0.5, 0.5, 0.5);
+        }
+    }
+    return block;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ON: Rgb = Rgb(255, 255, 255);
+    const OFF: Rgb = Rgb(0, 0, 0);
+
+    // an image drawn with # for lit pixels and . for dark ones
+    fn image(rows: &[&str]) -> Image {
+        return Image::from_fn(rows[0].len(), rows.len(), |x, y| match rows[y].as_bytes()[x] {
+            b'#' => ON,
+            _ => OFF,
+        });
+    }
+
+    fn picture(image: &Image) -> Vec<String> {
+        return image
+            .pixels
+            .chunks(image.width)
+            .map(|row| row.iter().map(|color| if *color == ON { '#' } else { '.' }).collect())
+            .collect();
+    }
+
+    // away from the edges, which repeat outwards
+    const DIAGONAL: [&str; 4] = ["....", ".#..", "..#.", "...."];
+
+    #[test]
+    fn nearest_makes_squares() {
+        let big = image(&["#.", ".."]).scaled(2);
+        assert_eq!(picture(&big), ["##..", "##..", "....", "...."]);
+    }
+
+    #[test]
+    fn scale2x_smooths_a_diagonal() {
+        let big = image(&DIAGONAL).filtered(Filter::Scale2x);
+        assert_eq!(
+            picture(&big),
+            ["........", "........", "..##....", "..###...", "...###..", "....##..", "........", "........"]
+        );
+    }
+
+    #[test]
+    fn scale3x_smooths_a_diagonal() {
+        let big = image(&DIAGONAL).filtered(Filter::Scale3x);
+        assert_eq!(
+            picture(&big)[2..10],
+            [
+                "............",
+                "...###......",
+                "...###......",
+                "...####.....",
+                ".....####...",
+                "......###...",
+                "......###...",
+                "............",
+            ]
+        );
+    }
+
+    #[test]
+    fn scaling_leaves_lone_pixels_and_straight_edges_square() {
+        let dot = image(&["...", ".#.", "..."]);
+        assert_eq!(picture(&dot.filtered(Filter::Scale2x)), picture(&dot.scaled(2)));
+        assert_eq!(picture(&dot.filtered(Filter::Scale3x)), picture(&dot.scaled(3)));
+        let edge = image(&["##..", "##..", "##.."]);
+        assert_eq!(picture(&edge.filtered(Filter::Scale2x)), picture(&edge.scaled(2)));
+        assert_eq!(picture(&edge.filtered(Filter::Scale3x)), picture(&edge.scaled(3)));
+    }
+
+    #[test]
+    fn scanlines_dim_the_bottom_row() {
+        let big = image(&["#"]).filtered(Filter::Scanlines);
+        assert_eq!((big.width, big.height), (3, 3));
+        assert_eq!(big.pixels[..6], [ON; 6]);
+        assert_eq!(big.pixels[6..], [Rgb(127, 127, 127); 3]);
+        let crt = image(&["#"]).filtered(Filter::Crt);
+        assert_eq!(crt.pixels[..3], [Rgb(255, 178, 178), Rgb(178, 255, 178), Rgb(178, 178, 255)]);
+    }
+
+    #[test]
+    fn upscaling_rounds_to_whole_filtered_pixels() {
+        let big = image(&DIAGONAL).upscaled(Filter::Scale2x, 5);
+        assert_eq!((big.width, big.height), (16, 16));
+        let big = image(&DIAGONAL).upscaled(Filter::Scale3x, 2);
+        assert_eq!((big.width, big.height), (12, 12));
+    }
+
+    #[test]
+    fn filters_parse_and_cycle() {
+        assert_eq!("EPX".parse(), Ok(Filter::Scale2x));
+        assert_eq!("scale3x".parse(), Ok(Filter::Scale3x));
+        assert!("blur".parse::<Filter>().is_err());
+        let mut filter = Filter::Nearest;
+        for expected in &FILTERS[1..] {
+            filter = filter.next();
+            assert_eq!(filter, *expected);
+            assert_eq!(filter.to_string().parse(), Ok(filter));
+        }
+        assert_eq!(filter.next(), Filter::Nearest);
+    }
+}
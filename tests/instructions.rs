@@ -0,0 +1,151 @@
+// random programs on random machines, run through the interpreter and the reference model in
+// tests/reference under every quirk profile. proptest shrinks a failing case down to the fewest and
+// simplest instructions that still disagree and keeps it in tests/instructions.proptest-regressions, to
+// be run first from then on. PROPTEST_CASES=100000 cargo test --test instructions searches harder.
+#![allow(clippy::needless_return)]
+
+mod reference;
+
+use proptest::prelude::*;
+use reference::{Case, PROFILES, TABLE};
+
+// every instruction in the table with random operands, and now and then anything at all
+fn opcode() -> impl Strategy<Value = u16> {
+    let instruction = (0..TABLE.len(), any::<u16>())
+        .prop_map(|(n, operands)| TABLE[n].pattern | (operands & !TABLE[n].mask));
+    return prop_oneof![9 => instruction, 1 => any::<u16>()];
+}
+
+// I mostly points at the program, the font or the stack so loads and stores hit something
+fn address() -> impl Strategy<Value = u16> {
+    return prop_oneof![0x200u16..0x240, 0x000u16..0x0a0, 0u16..0x1000];
+}
+
+fn case() -> impl Strategy<Value = Case> {
+    return (
+        (
+            0..PROFILES.len(),
+            prop::collection::vec(opcode(), 1..24),
+            any::<[u8; 16]>(),
+            address(),
+            any::<u8>(),
+            any::<u8>(),
+        ),
+        (
+            any::<u16>(),
+            prop::option::of(0u8..16),
+            prop::collection::vec(any::<u8>(), 1..4),
+            prop::collection::vec((0u8..64, 0u8..32), 0..16),
+            prop::collection::vec(0x200u16..0x240, 0..4),
+            1usize..12,
+        ),
+    )
+        .prop_map(
+            |((profile, program, registers, i, delay, sound), (keys, release, random, pixels, stack, speed))| Case {
+                profile,
+                program,
+                registers,
+                i,
+                delay,
+                sound,
+                keys,
+                release,
+                random,
+                pixels,
+                stack,
+                speed,
+            },
+        );
+}
+
+// 2000 cases unless PROPTEST_CASES says otherwise
+fn cases() -> u32 {
+    return std::env::var("PROPTEST_CASES").ok().and_then(|cases| cases.parse().ok()).unwrap_or(2000);
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(cases()))]
+
+    #[test]
+    fn interpreter_matches_reference(case in case()) {
+        if let Err(mismatch) = reference::run(&case) {
+            prop_assert!(false, "\n{}", mismatch);
+        }
+    }
+}
+
+// a single program on an otherwise blank machine
+fn program(profile: usize, program: &[u16], registers: &[(usize, u8)]) -> Case {
+    let mut case = Case::from_bytes(&[]);
+    case.profile = profile;
+    case.program = program.to_vec();
+    for (r, value) in registers {
+        case.registers[*r] = *value;
+    }
+    return case;
+}
+
+fn check(case: Case) {
+    if let Err(mismatch) = reference::run(&case) {
+        panic!("\n{}", mismatch);
+    }
+}
+
+// the differences the fuzzing found first, kept as plain tests
+#[test]
+fn sub_sets_vf_when_nothing_is_borrowed() {
+    check(program(0, &[0x8015, 0x8237], &[(0, 7), (1, 7), (2, 9), (3, 9)]));
+}
+
+#[test]
+fn font_address_comes_from_the_register() {
+    check(program(0, &[0xf329], &[(3, 0x1b)]));
+}
+
+#[test]
+fn key_skips_use_the_low_nibble() {
+    check(program(0, &[0xe49e, 0xe4a1], &[(4, 0xf3)]));
+}
+
+#[test]
+fn memory_wraps_around_at_4k() {
+    let mut case = program(1, &[0xff55, 0xff65, 0xf233, 0xf01e], &[(0, 0xff), (2, 0x80)]);
+    case.i = 0xffe;
+    check(case);
+}
+
+#[test]
+fn return_with_an_empty_stack() {
+    check(program(0, &[0x00ee], &[]));
+}
+
+#[test]
+fn only_5xy0_and_9xy0_skip() {
+    check(program(3, &[0x5012, 0x9013], &[]));
+}
+
+#[test]
+fn waiting_for_a_key_leaves_the_timers_running() {
+    let mut case = program(0, &[0xf00a], &[]);
+    case.delay = 10;
+    case.sound = 10;
+    case.release = Some(0xa);
+    check(case);
+}
+
+#[test]
+fn skips_step_over_all_of_long_i() {
+    check(program(0, &[0x3000, 0xf000, 0x0300, 0x6101], &[]));
+}
+
+#[test]
+fn big_sprites_on_both_planes_in_hires() {
+    let mut case = program(3, &[0x00ff, 0xf301, 0xd010, 0x00c3, 0x00fb, 0xf201, 0x00e0], &[(0, 120), (1, 60)]);
+    case.i = 0x200;
+    check(case);
+}
+
+#[test]
+fn ranges_flags_and_exit() {
+    check(program(3, &[0xa300, 0x5242, 0x5423, 0xf575, 0x6000, 0xf385, 0x00fd, 0x6107], &[(2, 9), (4, 7)]));
+}
@@ -0,0 +1,119 @@
+// key presses and releases sent to a program the way a platform sends them, checked against what the
+// program saw. A tap shorter than a frame has to show up in EX9E, EXA1 and FX0A all the same.
+#![allow(clippy::needless_return)]
+
+use chip8_rust::keyboard::KeyQueue;
+use chip8_rust::{create_contexts, load_rom, Command, CpuContext, KeyInput, PlatformContext, Program, Rom, Runnable};
+use std::time::{Duration, Instant};
+
+// 10 instructions a frame
+const FREQUENCY: f32 = 600.0;
+
+fn program(opcodes: &[u16]) -> (Program, PlatformContext, CpuContext) {
+    let bytes: Vec<u8> = opcodes.iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+    let mut program = load_rom(Rom::from_bytes(&bytes).unwrap()).unwrap();
+    program.frequency = FREQUENCY;
+    program.realtime = false;
+    let (platform, cpu) = create_contexts();
+    return (program, platform, cpu);
+}
+
+fn tap(platform: &PlatformContext, key: u8) {
+    platform.send(Command::Key(KeyInput::press(key)));
+    platform.send(Command::Key(KeyInput::release(key)));
+}
+
+// counts in V1 until key 5 is down, then stops at 0x208
+const WAIT_FOR_5: [u16; 5] = [0x6505, 0x7101, 0xe59e, 0x1202, 0x1208];
+
+#[test]
+fn a_tap_is_held_for_a_frame() {
+    let (mut program, platform, cpu) = program(&WAIT_FOR_5);
+    assert!(program.run_frame(&cpu));
+    tap(&platform, 5);
+    assert!(program.run_frame(&cpu));
+    assert_eq!(program.cpu.pc(), 0x208);
+    assert_eq!(program.cpu.keys()[5], 1);
+    assert!(program.run_frame(&cpu));
+    assert_eq!(program.cpu.keys()[5], 0);
+}
+
+#[test]
+fn a_tap_goes_down_and_up_again() {
+    // waits for key 5 to go down, then for it to come up and stops at 0x20a
+    let (mut program, platform, cpu) = program(&[0x6505, 0xe59e, 0x1202, 0xe5a1, 0x1206, 0x120a]);
+    assert!(program.run_frame(&cpu));
+    tap(&platform, 5);
+    assert!(program.run_frame(&cpu));
+    assert!((0x206..=0x208).contains(&program.cpu.pc()));
+    assert!(program.run_frame(&cpu));
+    assert_eq!(program.cpu.pc(), 0x20a);
+}
+
+#[test]
+fn fx0a_gets_a_tap() {
+    let (mut program, platform, cpu) = program(&[0xf00a, 0x1202]);
+    assert!(program.run_frame(&cpu));
+    assert!(program.cpu.waiting_for_key());
+    tap(&platform, 7);
+    assert!(program.run_frame(&cpu));
+    assert!(program.run_frame(&cpu));
+    assert!(!program.cpu.waiting_for_key());
+    assert_eq!(program.cpu.registers()[0], 7);
+}
+
+#[test]
+fn a_key_held_for_frames_comes_up_at_once() {
+    let (mut program, platform, cpu) = program(&WAIT_FOR_5);
+    platform.send(Command::Key(KeyInput::press(5)));
+    assert!(program.run_frame(&cpu));
+    assert!(program.run_frame(&cpu));
+    platform.send(Command::Key(KeyInput::release(5)));
+    assert!(program.run_frame(&cpu));
+    // held long enough already, so the release isn't put off
+    assert_eq!(program.cpu.keys()[5], 0);
+}
+
+#[test]
+fn a_press_lands_as_far_into_the_frame_as_it_happened() {
+    let start = Instant::now();
+    let mut keys = KeyQueue::default();
+    keys.begin_frame(0, 10, Some(start));
+    // the frame before took 30ms and the key went down a third of the way through it
+    keys.begin_frame(1, 10, Some(start + Duration::from_millis(30)));
+    let mut press = KeyInput::press(5);
+    press.at = start + Duration::from_millis(10);
+    keys.push(press);
+    assert_eq!(keys.apply(2), None);
+    assert_eq!(keys.apply(3).map(|held| held[5]), Some(1));
+}
+
+#[test]
+fn untimed_frames_take_keys_at_their_start() {
+    let start = Instant::now();
+    let mut keys = KeyQueue::default();
+    keys.begin_frame(0, 10, None);
+    keys.begin_frame(1, 10, None);
+    let mut press = KeyInput::press(5);
+    press.at = start + Duration::from_millis(10);
+    keys.push(press);
+    assert_eq!(keys.apply(0).map(|held| held[5]), Some(1));
+}
+
+#[test]
+fn a_timed_release_waits_for_the_frame_after_its_press() {
+    let start = Instant::now();
+    let mut keys = KeyQueue::default();
+    keys.begin_frame(0, 10, Some(start));
+    keys.begin_frame(1, 10, Some(start + Duration::from_millis(20)));
+    let (mut press, mut release) = (KeyInput::press(7), KeyInput::release(7));
+    press.at = start + Duration::from_millis(10);
+    release.at = start + Duration::from_millis(15);
+    keys.push(press);
+    keys.push(release);
+    assert_eq!(keys.apply(9).map(|held| held[7]), Some(1));
+    keys.begin_frame(2, 10, Some(start + Duration::from_millis(40)));
+    assert_eq!(keys.apply(4), None);
+    assert_eq!(keys.apply(5).map(|held| held[7]), Some(0));
+    assert_eq!(keys.released(), Some(7));
+}
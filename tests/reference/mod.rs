@@ -0,0 +1,689 @@
+// a second CHIP-8, written from the specification instead of from the interpreter, to run random
+// programs through both and compare the machines after every instruction. Every instruction is an entry
+// in a table of patterns and the quirk profiles are a table of their own, so a difference points at one
+// instruction under one profile. The proptest suite in tests/instructions.rs and the fuzz target in
+// fuzz/ share it, each uses part of it.
+#![allow(dead_code)]
+
+use chip8_rust::machine::{Cpu, Framebuffer, Keypad, Random, Screen, State, Step, PROGRAM_START};
+use chip8_rust::{disassemble_instruction, OpCode, QuirkProfile};
+use std::collections::BTreeMap;
+use std::fmt;
+
+// the font sits here, 5 bytes for each of 0-F, and the big font right after it with 10 bytes a digit
+const FONT: u16 = 0x50;
+const BIG_FONT: u16 = 0xa0;
+
+// frames every case runs for
+pub const FRAMES: usize = 4;
+
+// the profiles as documented in the README: VF reset, I increments, display wait, clipping, shift VX, BXNN
+pub const PROFILES: [(&str, [bool; 6]); 4] = [
+    ("modern", [false, false, false, true, true, false]),
+    ("vip", [true, true, true, true, false, false]),
+    ("schip", [false, false, false, true, true, true]),
+    ("xochip", [false, true, false, false, false, false]),
+];
+
+#[derive(Clone, Copy, Debug)]
+pub struct Profile {
+    pub name: &'static str,
+    pub vf_reset: bool,
+    pub increment_i: bool,
+    pub display_wait: bool,
+    pub clipping: bool,
+    pub shift_vx: bool,
+    pub jump_vx: bool,
+}
+
+impl Profile {
+    pub fn get(index: usize) -> Profile {
+        let (name, [vf_reset, increment_i, display_wait, clipping, shift_vx, jump_vx]) =
+            PROFILES[index % PROFILES.len()];
+        return Profile {
+            name,
+            vf_reset,
+            increment_i,
+            display_wait,
+            clipping,
+            shift_vx,
+            jump_vx,
+        };
+    }
+}
+
+// the operands of an instruction, every field is decoded whether the instruction uses it or not
+#[derive(Clone, Copy)]
+pub struct Args {
+    x: usize,
+    y: usize,
+    n: u16,
+    nn: u8,
+    nnn: u16,
+}
+
+pub struct Instruction {
+    pub pattern: u16,
+    pub mask: u16,
+    pub name: &'static str,
+    run: fn(&mut Model, Args),
+}
+
+const fn op(pattern: u16, mask: u16, name: &'static str, run: fn(&mut Model, Args)) -> Instruction {
+    return Instruction {
+        pattern,
+        mask,
+        name,
+        run,
+    };
+}
+
+// everything else does nothing
+pub const TABLE: [Instruction; 48] = [
+    op(0x00c0, 0xfff0, "SCD N", |m, a| m.scroll(0, a.n as isize)),
+    op(0x00d0, 0xfff0, "SCU N", |m, a| m.scroll(0, -(a.n as isize))),
+    op(0x00e0, 0xffff, "CLS", |m, _| {
+        // only the selected planes
+        let (width, height) = (m.state.screen.width(), m.state.screen.height());
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = m.state.screen.pixel(x, y);
+                m.state.screen.set_pixel(x, y, pixel & !m.state.planes);
+            }
+        }
+    }),
+    op(0x00ee, 0xffff, "RET", |m, _| {
+        m.state.pc = u16::from_be_bytes([m.read(m.state.sp), m.read(m.state.sp.wrapping_add(1))]);
+        m.state.sp = m.state.sp.wrapping_sub(2);
+    }),
+    op(0x00fb, 0xffff, "SCR", |m, _| m.scroll(4, 0)),
+    op(0x00fc, 0xffff, "SCL", |m, _| m.scroll(-4, 0)),
+    op(0x00fd, 0xffff, "EXIT", |m, _| m.state.exited = true),
+    op(0x00fe, 0xffff, "LOW", |m, _| m.state.screen = Framebuffer::new(64, 32)),
+    op(0x00ff, 0xffff, "HIGH", |m, _| m.state.screen = Framebuffer::new(128, 64)),
+    op(0x1000, 0xf000, "JP NNN", |m, a| m.state.pc = a.nnn),
+    op(0x2000, 0xf000, "CALL NNN", |m, a| {
+        // the stack lives in RAM, growing up from 0x002
+        m.state.sp = m.state.sp.wrapping_add(2);
+        let [high, low] = m.state.pc.to_be_bytes();
+        m.write(m.state.sp, high);
+        m.write(m.state.sp.wrapping_add(1), low);
+        m.state.pc = a.nnn;
+    }),
+    op(0x3000, 0xf000, "SE VX, NN", |m, a| m.skip_if(m.v(a.x) == a.nn)),
+    op(0x4000, 0xf000, "SNE VX, NN", |m, a| m.skip_if(m.v(a.x) != a.nn)),
+    op(0x5000, 0xf00f, "SE VX, VY", |m, a| m.skip_if(m.v(a.x) == m.v(a.y))),
+    op(0x5002, 0xf00f, "SAVE VX - VY", |m, a| {
+        for (n, r) in m.range(a).into_iter().enumerate() {
+            m.write(m.state.i.wrapping_add(n as u16), m.v(r));
+        }
+    }),
+    op(0x5003, 0xf00f, "LOAD VX - VY", |m, a| {
+        for (n, r) in m.range(a).into_iter().enumerate() {
+            m.state.registers[r] = m.read(m.state.i.wrapping_add(n as u16));
+        }
+    }),
+    op(0x6000, 0xf000, "LD VX, NN", |m, a| m.state.registers[a.x] = a.nn),
+    op(0x7000, 0xf000, "ADD VX, NN", |m, a| m.state.registers[a.x] = m.v(a.x).wrapping_add(a.nn)),
+    op(0x8000, 0xf00f, "LD VX, VY", |m, a| m.state.registers[a.x] = m.v(a.y)),
+    op(0x8001, 0xf00f, "OR VX, VY", |m, a| m.logic(a, m.v(a.x) | m.v(a.y))),
+    op(0x8002, 0xf00f, "AND VX, VY", |m, a| m.logic(a, m.v(a.x) & m.v(a.y))),
+    op(0x8003, 0xf00f, "XOR VX, VY", |m, a| m.logic(a, m.v(a.x) ^ m.v(a.y))),
+    op(0x8004, 0xf00f, "ADD VX, VY", |m, a| {
+        let (sum, carry) = m.v(a.x).overflowing_add(m.v(a.y));
+        m.set_with_flag(a.x, sum, carry);
+    }),
+    op(0x8005, 0xf00f, "SUB VX, VY", |m, a| {
+        let (difference, borrow) = m.v(a.x).overflowing_sub(m.v(a.y));
+        m.set_with_flag(a.x, difference, !borrow);
+    }),
+    op(0x8006, 0xf00f, "SHR VX, VY", |m, a| {
+        let value = if m.profile.shift_vx { m.v(a.x) } else { m.v(a.y) };
+        m.set_with_flag(a.x, value >> 1, value & 1 == 1);
+    }),
+    op(0x8007, 0xf00f, "SUBN VX, VY", |m, a| {
+        let (difference, borrow) = m.v(a.y).overflowing_sub(m.v(a.x));
+        m.set_with_flag(a.x, difference, !borrow);
+    }),
+    op(0x800e, 0xf00f, "SHL VX, VY", |m, a| {
+        let value = if m.profile.shift_vx { m.v(a.x) } else { m.v(a.y) };
+        m.set_with_flag(a.x, value << 1, value & 0x80 != 0);
+    }),
+    op(0x9000, 0xf00f, "SNE VX, VY", |m, a| m.skip_if(m.v(a.x) != m.v(a.y))),
+    op(0xa000, 0xf000, "LD I, NNN", |m, a| m.state.i = a.nnn),
+    op(0xb000, 0xf000, "JP V0, NNN", |m, a| {
+        let offset = if m.profile.jump_vx { m.v(a.x) } else { m.v(0) };
+        m.state.pc = a.nnn + u16::from(offset);
+    }),
+    op(0xc000, 0xf000, "RND VX, NN", |m, a| {
+        let random = m.random[m.next_random % m.random.len()];
+        m.next_random += 1;
+        m.state.registers[a.x] = random & a.nn;
+    }),
+    op(0xd000, 0xf000, "DRW VX, VY, N", |m, a| m.draw(a)),
+    op(0xe09e, 0xf0ff, "SKP VX", |m, a| m.skip_if(m.state.keys[usize::from(m.v(a.x) & 0xf)] != 0)),
+    op(0xe0a1, 0xf0ff, "SKNP VX", |m, a| m.skip_if(m.state.keys[usize::from(m.v(a.x) & 0xf)] == 0)),
+    op(0xf000, 0xffff, "LD I, NNNN", |m, _| {
+        m.state.i = u16::from_be_bytes([m.read(m.state.pc), m.read(m.state.pc.wrapping_add(1))]);
+        m.state.pc += 2;
+    }),
+    op(0xf001, 0xf0ff, "PLANE X", |m, a| m.state.planes = a.x as u8 & 3),
+    op(0xf007, 0xf0ff, "LD VX, DT", |m, a| m.state.registers[a.x] = m.state.delay),
+    op(0xf00a, 0xf0ff, "LD VX, K", |m, a| match m.release {
+        Some(key) => {
+            m.state.registers[a.x] = key & 0xf;
+            m.state.waiting_for_key = false;
+        }
+        None => {
+            m.state.pc -= 2;
+            m.state.waiting_for_key = true;
+        }
+    }),
+    op(0xf015, 0xf0ff, "LD DT, VX", |m, a| m.state.delay = m.v(a.x)),
+    op(0xf018, 0xf0ff, "LD ST, VX", |m, a| m.state.sound = m.v(a.x)),
+    op(0xf01e, 0xf0ff, "ADD I, VX", |m, a| m.state.i = m.state.i.wrapping_add(u16::from(m.v(a.x)))),
+    op(0xf029, 0xf0ff, "LD F, VX", |m, a| m.state.i = FONT + u16::from(m.v(a.x) & 0xf) * 5),
+    op(0xf030, 0xf0ff, "LD HF, VX", |m, a| m.state.i = BIG_FONT + u16::from(m.v(a.x) & 0xf) * 10),
+    op(0xf033, 0xf0ff, "LD B, VX", |m, a| {
+        let value = m.v(a.x);
+        let i = m.state.i;
+        m.write(i, value / 100);
+        m.write(i.wrapping_add(1), value / 10 % 10);
+        m.write(i.wrapping_add(2), value % 10);
+    }),
+    op(0xf055, 0xf0ff, "LD [I], VX", |m, a| {
+        for r in 0..=a.x {
+            m.write(m.state.i.wrapping_add(r as u16), m.v(r));
+        }
+        m.increment_i(a);
+    }),
+    op(0xf065, 0xf0ff, "LD VX, [I]", |m, a| {
+        for r in 0..=a.x {
+            m.state.registers[r] = m.read(m.state.i.wrapping_add(r as u16));
+        }
+        m.increment_i(a);
+    }),
+    op(0xf075, 0xf0ff, "LD R, VX", |m, a| {
+        for r in 0..=a.x {
+            m.state.flags[r] = m.v(r);
+        }
+    }),
+    op(0xf085, 0xf0ff, "LD VX, R", |m, a| {
+        for r in 0..=a.x {
+            m.state.registers[r] = m.state.flags[r];
+        }
+    }),
+];
+
+pub fn decode(opcode: u16) -> Option<&'static Instruction> {
+    return TABLE.iter().find(|instruction| opcode & instruction.mask == instruction.pattern);
+}
+
+pub struct Model {
+    pub state: State,
+    pub profile: Profile,
+    // the program stops once the program counter gets here
+    pub end: u16,
+    random: Vec<u8>,
+    next_random: usize,
+    release: Option<u8>,
+    // set by DXYN with the display wait quirk
+    frame_over: bool,
+}
+
+impl Model {
+    fn read(&self, address: u16) -> u8 {
+        return self.state.ram[usize::from(address & 0xfff)];
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.state.ram[usize::from(address & 0xfff)] = value;
+    }
+
+    fn v(&self, x: usize) -> u8 {
+        return self.state.registers[x];
+    }
+
+    // F000 NNNN is skipped as a whole
+    fn skip_if(&mut self, condition: bool) {
+        if condition {
+            let next = u16::from_be_bytes([self.read(self.state.pc), self.read(self.state.pc.wrapping_add(1))]);
+            self.state.pc += if next == 0xf000 { 4 } else { 2 };
+        }
+    }
+
+    // the registers from X to Y, counting down when X is past Y
+    fn range(&self, a: Args) -> Vec<usize> {
+        if a.x <= a.y {
+            return (a.x..=a.y).collect();
+        }
+        return (a.y..=a.x).rev().collect();
+    }
+
+    // moves the selected planes right by dx and down by dy pixels, blank pixels come in at the edges
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let old = self.state.screen.clone();
+        let (width, height) = (old.width() as isize, old.height() as isize);
+        let planes = self.state.planes;
+        for y in 0..height {
+            for x in 0..width {
+                let (from_x, from_y) = (x - dx, y - dy);
+                let from = if (0..width).contains(&from_x) && (0..height).contains(&from_y) {
+                    old.pixel(from_x as usize, from_y as usize)
+                } else {
+                    0
+                };
+                let kept = old.pixel(x as usize, y as usize) & !planes;
+                self.state.screen.set_pixel(x as usize, y as usize, kept | (from & planes));
+            }
+        }
+    }
+
+    // the flag is written last, so it wins when VX is VF
+    fn set_with_flag(&mut self, x: usize, value: u8, flag: bool) {
+        self.state.registers[x] = value;
+        self.state.registers[0xf] = flag as u8;
+    }
+
+    fn logic(&mut self, a: Args, value: u8) {
+        self.state.registers[a.x] = value;
+        if self.profile.vf_reset {
+            self.state.registers[0xf] = 0;
+        }
+    }
+
+    fn increment_i(&mut self, a: Args) {
+        if self.profile.increment_i {
+            self.state.i = self.state.i.wrapping_add(a.x as u16 + 1);
+        }
+    }
+
+    // DXYN draws N rows of 8 pixels and DXY0 16 rows of 16 on each selected plane, the sprite for the
+    // second plane follows the one for the first
+    fn draw(&mut self, a: Args) {
+        let (width, height) = (self.state.screen.width(), self.state.screen.height());
+        let left = usize::from(self.v(a.x)) % width;
+        let top = usize::from(self.v(a.y)) % height;
+        let (columns, rows) = if a.n == 0 { (16, 16) } else { (8, usize::from(a.n)) };
+        self.state.registers[0xf] = 0;
+        let mut address = self.state.i;
+        for plane in 0..2 {
+            let bit = 1 << plane;
+            if self.state.planes & bit == 0 {
+                continue;
+            }
+            for row in 0..rows {
+                let y = top + row;
+                if y >= height && self.profile.clipping {
+                    break;
+                }
+                for column in 0..columns {
+                    let byte = self.read(address.wrapping_add((row * columns / 8 + column / 8) as u16));
+                    let x = left + column;
+                    if x >= width && self.profile.clipping {
+                        break;
+                    }
+                    if byte & (0x80 >> (column % 8)) == 0 {
+                        continue;
+                    }
+                    let pixel = self.state.screen.pixel(x % width, y % height);
+                    if pixel & bit != 0 {
+                        self.state.registers[0xf] = 1;
+                    }
+                    self.state.screen.set_pixel(x % width, y % height, pixel ^ bit);
+                }
+            }
+            address = address.wrapping_add((rows * columns / 8) as u16);
+        }
+        if self.profile.display_wait {
+            self.frame_over = true;
+        }
+    }
+
+    // runs one instruction, false once the program has stopped
+    pub fn step(&mut self) -> bool {
+        if self.stopped() {
+            return false;
+        }
+        let opcode = u16::from_be_bytes([self.read(self.state.pc), self.read(self.state.pc + 1)]);
+        self.state.pc += 2;
+        if let Some(instruction) = decode(opcode) {
+            let args = Args {
+                x: usize::from(opcode >> 8 & 0xf),
+                y: usize::from(opcode >> 4 & 0xf),
+                n: opcode & 0xf,
+                nn: (opcode & 0xff) as u8,
+                nnn: opcode & 0xfff,
+            };
+            (instruction.run)(self, args);
+        }
+        return true;
+    }
+
+    pub fn stopped(&self) -> bool {
+        return self.state.exited || self.state.pc >= self.end;
+    }
+
+    pub fn end_frame(&mut self) {
+        self.state.delay = self.state.delay.saturating_sub(1);
+        self.state.sound = self.state.sound.saturating_sub(1);
+        self.frame_over = false;
+    }
+}
+
+// random numbers for the interpreter, the same ones the model gets
+struct Bytes {
+    bytes: Vec<u8>,
+    next: usize,
+}
+
+impl Random for Bytes {
+    fn random(&mut self) -> u8 {
+        let byte = self.bytes[self.next % self.bytes.len()];
+        self.next += 1;
+        return byte;
+    }
+}
+
+// the keys are part of the state, FX0A always gets the same key or none
+struct Release(Option<u8>);
+
+impl Keypad for Release {
+    fn keys(&mut self) -> Option<[u8; 16]> {
+        return None;
+    }
+
+    fn released(&mut self) -> Option<u8> {
+        return self.0;
+    }
+}
+
+struct NoScreen;
+
+impl Screen for NoScreen {
+    fn draw(&mut self, _screen: &Framebuffer, _rows: u64) {}
+}
+
+// a program and the machine it starts on
+#[derive(Clone, Debug, PartialEq)]
+pub struct Case {
+    // an index into PROFILES
+    pub profile: usize,
+    // loaded at 0x200
+    pub program: Vec<u16>,
+    pub registers: [u8; 16],
+    pub i: u16,
+    pub delay: u8,
+    pub sound: u8,
+    // a bit per key
+    pub keys: u16,
+    // the key FX0A gets, None to keep it waiting
+    pub release: Option<u8>,
+    // CXNN takes these in turn
+    pub random: Vec<u8>,
+    // lit pixels
+    pub pixels: Vec<(u8, u8)>,
+    // return addresses already on the stack
+    pub stack: Vec<u16>,
+    // instructions per frame
+    pub speed: usize,
+}
+
+impl Case {
+    // decodes a case from fuzzer input, whatever is left over after the machine becomes the program
+    pub fn from_bytes(data: &[u8]) -> Case {
+        let byte = |n: usize| data.get(n).copied().unwrap_or(0);
+        let mut registers = [0u8; 16];
+        for (r, register) in registers.iter_mut().enumerate() {
+            *register = byte(4 + r);
+        }
+        let program = data
+            .get(30..)
+            .unwrap_or(&[])
+            .chunks(2)
+            .take(64)
+            .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]))
+            .collect();
+        return Case {
+            profile: usize::from(byte(0)),
+            program,
+            registers,
+            i: u16::from_be_bytes([byte(20), byte(21)]) & 0xfff,
+            delay: byte(22),
+            sound: byte(23),
+            keys: u16::from_be_bytes([byte(24), byte(25)]),
+            release: if byte(1) < 16 { Some(byte(1)) } else { None },
+            random: vec![byte(26), byte(27), byte(28), byte(29)],
+            pixels: Vec::new(),
+            stack: Vec::new(),
+            speed: usize::from(byte(2) % 16) + 1,
+        };
+    }
+
+    fn setup(&self) -> (Cpu, Model) {
+        let profile = Profile::get(self.profile);
+        let mut cpu = Cpu::new(profile.name.parse::<QuirkProfile>().unwrap().quirks());
+        let bytes: Vec<u8> = self.program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+        cpu.load(&bytes).unwrap();
+
+        let mut state = cpu.state();
+        state.registers = self.registers;
+        state.i = self.i;
+        state.delay = self.delay;
+        state.sound = self.sound;
+        for key in 0..16 {
+            state.keys[key] = (self.keys >> key & 1) as u8;
+        }
+        for (x, y) in self.pixels.iter() {
+            state.screen.set_pixel(usize::from(*x) % 64, usize::from(*y) % 32, 1);
+        }
+        for address in self.stack.iter() {
+            state.sp += 2;
+            let [high, low] = address.to_be_bytes();
+            state.ram[usize::from(state.sp)] = high;
+            state.ram[usize::from(state.sp) + 1] = low;
+        }
+        cpu.set_state(&state);
+
+        let model = Model {
+            state,
+            profile,
+            end: (PROGRAM_START + bytes.len()) as u16,
+            random: self.random.clone(),
+            next_random: 0,
+            release: self.release,
+            frame_over: false,
+        };
+        return (cpu, model);
+    }
+}
+
+// where the interpreter and the model first disagreed
+pub struct Mismatch {
+    pub case: Case,
+    pub frame: usize,
+    // the instruction that was run last, None when the frame ended
+    pub instruction: Option<(u16, u16)>,
+    pub interpreter: State,
+    pub reference: State,
+    // whether the interpreter and the reference stopped the program, and ended the frame early
+    pub stopped: (bool, bool),
+    pub frame_over: (bool, bool),
+}
+
+// runs a case on the interpreter and the model side by side
+pub fn run(case: &Case) -> Result<(), Box<Mismatch>> {
+    let (mut cpu, mut model) = case.setup();
+    let mut random = Bytes {
+        bytes: case.random.clone(),
+        next: 0,
+    };
+    let mut keypad = Release(case.release);
+    let mismatch = |frame: usize, instruction: Option<(u16, u16)>, cpu: &Cpu, model: &Model, step: Option<Step>| {
+        return Box::new(Mismatch {
+            case: case.clone(),
+            frame,
+            instruction,
+            interpreter: cpu.state(),
+            reference: model.state.clone(),
+            stopped: (cpu.halted(), model.stopped()),
+            frame_over: (step.is_some_and(|step| step.end_frame), model.frame_over),
+        });
+    };
+
+    for frame in 0..FRAMES {
+        for _ in 0..case.speed {
+            let address = model.state.pc;
+            let opcode = u16::from_be_bytes([model.read(address), model.read(address.wrapping_add(1))]);
+            let step = cpu.step(&mut random, &mut keypad, &mut NoScreen);
+            let running = model.step();
+            let frame_over = step.is_some_and(|step| step.end_frame);
+            if cpu.state() != model.state || step.is_some() != running || frame_over != model.frame_over {
+                return Err(mismatch(frame, Some((address, opcode)), &cpu, &model, step));
+            }
+            if !running || frame_over {
+                break;
+            }
+        }
+        cpu.end_frame(&mut NoScreen);
+        model.end_frame();
+        if cpu.state() != model.state {
+            return Err(mismatch(frame, None, &cpu, &model, None));
+        }
+    }
+    return Ok(());
+}
+
+// the smallest case that still fails: drops instructions, pixels and stack entries and zeroes
+// registers and the rest of the machine for as long as the mismatch stays
+pub fn minimise(mut case: Case) -> Case {
+    let fails = |case: &Case| run(case).is_err();
+    let simplifications: [fn(&mut Case); 7] = [
+        |case| case.i = 0,
+        |case| case.delay = 0,
+        |case| case.sound = 0,
+        |case| case.keys = 0,
+        |case| case.release = None,
+        |case| case.random = vec![0],
+        |case| case.speed = 1,
+    ];
+    loop {
+        let mut smaller = false;
+        for simplify in simplifications.iter() {
+            let mut candidate = case.clone();
+            simplify(&mut candidate);
+            if candidate != case && fails(&candidate) {
+                case = candidate;
+                smaller = true;
+            }
+        }
+        for n in (0..case.program.len()).rev() {
+            let mut candidate = case.clone();
+            candidate.program.remove(n);
+            if fails(&candidate) {
+                case = candidate;
+                smaller = true;
+            }
+        }
+        for n in (0..case.pixels.len()).rev() {
+            let mut candidate = case.clone();
+            candidate.pixels.remove(n);
+            if fails(&candidate) {
+                case = candidate;
+                smaller = true;
+            }
+        }
+        if !case.stack.is_empty() {
+            let mut candidate = case.clone();
+            candidate.stack.pop();
+            if fails(&candidate) {
+                case = candidate;
+                smaller = true;
+            }
+        }
+        for r in 0..16 {
+            let mut candidate = case.clone();
+            candidate.registers[r] = 0;
+            if case.registers[r] != 0 && fails(&candidate) {
+                case = candidate;
+                smaller = true;
+            }
+        }
+        if !smaller {
+            return case;
+        }
+    }
+}
+
+fn describe(opcode: u16) -> String {
+    let code = OpCode {
+        high: (opcode >> 8) as u8,
+        low: opcode as u8,
+    };
+    let name = decode(opcode).map(|instruction| instruction.name).unwrap_or("(nothing)");
+    let instruction = disassemble_instruction(&code, &BTreeMap::new()).unwrap_or_default();
+    return format!("{:04x}  {:<14} {}", opcode, name, instruction);
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let case = &self.case;
+        writeln!(f, "the interpreter and the reference differ with the {} quirks", Profile::get(case.profile).name)?;
+        writeln!(f, "program:")?;
+        for (n, opcode) in case.program.iter().enumerate() {
+            writeln!(f, "  {:03x}  {}", PROGRAM_START + 2 * n, describe(*opcode))?;
+        }
+        write!(
+            f,
+            "starting with v={:02x?} i={:03x} dt={} st={} keys={:04x} release={:?} ",
+            case.registers, case.i, case.delay, case.sound, case.keys, case.release
+        )?;
+        writeln!(
+            f,
+            "random={:02x?} pixels={:?} stack={:03x?}, {} instructions a frame",
+            case.random, case.pixels, case.stack, case.speed
+        )?;
+        match self.instruction {
+            Some((address, opcode)) => {
+                writeln!(f, "in frame {} after {:03x}  {}", self.frame, address, describe(opcode))?
+            }
+            None => writeln!(f, "at the end of frame {}", self.frame)?,
+        }
+
+        let (a, b) = (&self.interpreter, &self.reference);
+        writeln!(f, "{:<14} {:<24} reference", "", "interpreter")?;
+        let mut field = |name: String, interpreter: String, reference: String| {
+            if interpreter != reference {
+                return writeln!(f, "{:<14} {:<24} {}", name, interpreter, reference);
+            }
+            return Ok(());
+        };
+        for r in 0..16 {
+            field(format!("v{:x}", r), format!("{:02x}", a.registers[r]), format!("{:02x}", b.registers[r]))?;
+        }
+        field(String::from("i"), format!("{:03x}", a.i), format!("{:03x}", b.i))?;
+        field(String::from("pc"), format!("{:03x}", a.pc), format!("{:03x}", b.pc))?;
+        field(String::from("sp"), format!("{:03x}", a.sp), format!("{:03x}", b.sp))?;
+        field(String::from("delay"), a.delay.to_string(), b.delay.to_string())?;
+        field(String::from("sound"), a.sound.to_string(), b.sound.to_string())?;
+        field(String::from("waiting"), a.waiting_for_key.to_string(), b.waiting_for_key.to_string())?;
+        field(String::from("planes"), a.planes.to_string(), b.planes.to_string())?;
+        field(String::from("flags"), format!("{:02x?}", a.flags), format!("{:02x?}", b.flags))?;
+        field(String::from("exited"), a.exited.to_string(), b.exited.to_string())?;
+        field(String::from("stopped"), self.stopped.0.to_string(), self.stopped.1.to_string())?;
+        field(String::from("frame over"), self.frame_over.0.to_string(), self.frame_over.1.to_string())?;
+        for address in (0..4096).filter(|address| a.ram[*address] != b.ram[*address]).take(16) {
+            let (interpreter, reference) = (format!("{:02x}", a.ram[address]), format!("{:02x}", b.ram[address]));
+            field(format!("ram[{:03x}]", address), interpreter, reference)?;
+        }
+        let size = |screen: &Framebuffer| format!("{}x{}", screen.width(), screen.height());
+        field(String::from("screen"), size(&a.screen), size(&b.screen))?;
+        for x in 0..a.screen.width().min(b.screen.width()) {
+            for y in 0..a.screen.height().min(b.screen.height()) {
+                let (interpreter, reference) = (a.screen.pixel(x, y), b.screen.pixel(x, y));
+                field(format!("pixel {},{}", x, y), interpreter.to_string(), reference.to_string())?;
+            }
+        }
+        return Ok(());
+    }
+}
@@ -0,0 +1,115 @@
+// runs the test ROMs in test/ headlessly under every quirk profile and compares their final screens with
+// the ASCII art in tests/golden/<case>.<profile>.txt. After a change that is meant to alter a screen,
+// CHIP8_UPDATE_GOLDEN=1 cargo test writes the golden frames from the current emulator instead, and git
+// diff shows what changed.
+#![allow(clippy::needless_return)]
+
+use chip8_rust::headless_platform::{HeadlessPlatform, KeyScript};
+use chip8_rust::{load_rom, rom, screenshot, QuirkProfile, Settings};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+// long enough for every test to finish with the display wait quirk slowing the drawing down
+const FRAMES: u64 = 300;
+
+fn root() -> PathBuf {
+    return PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+}
+
+// the final screen of a ROM run with a profile's quirks, keys come from a KeyScript
+fn run(rom_name: &str, profile: QuirkProfile, script: &str) -> String {
+    let rom = rom::read(root().join("test").join(rom_name)).unwrap();
+    let mut program = load_rom(rom).unwrap();
+    program.cpu.quirks = profile.quirks();
+    program.reseed(0);
+    program.realtime = false;
+    program.frame_limit = Some(FRAMES);
+
+    let mut platform = HeadlessPlatform::new(&Settings::default());
+    platform.set_script(KeyScript::parse(script).unwrap());
+    platform.run(&mut program);
+    assert!(platform.errors.is_empty(), "{}: {:?}", rom_name, platform.errors);
+    return screenshot::ascii(platform.screen());
+}
+
+// runs a case under every profile and fails with every screen that differs from its golden frame
+fn check(case: &str, rom_name: &str, script: &str) {
+    check_profiles(case, rom_name, script, &QuirkProfile::ALL);
+}
+
+fn check_profiles(case: &str, rom_name: &str, script: &str, profiles: &[QuirkProfile]) {
+    let update = env::var_os("CHIP8_UPDATE_GOLDEN").is_some();
+    let mut failures = Vec::new();
+    for &profile in profiles {
+        let screen = run(rom_name, profile, script);
+        let path = root().join("tests").join("golden").join(format!("{}.{}.txt", case, profile));
+        if update {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, &screen).unwrap();
+            continue;
+        }
+        match fs::read_to_string(&path) {
+            Ok(expected) if expected == screen => (),
+            Ok(expected) => failures.push(format!(
+                "{} with {} quirks differs from {}\nexpected:\n{}found:\n{}",
+                rom_name,
+                profile,
+                path.display(),
+                expected,
+                screen
+            )),
+            Err(e) => failures.push(format!("{}: {}", path.display(), e)),
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn chip8_logo() {
+    check("1-chip8-logo", "1-chip8-logo.ch8", "");
+}
+
+#[test]
+fn ibm_logo() {
+    check("2-ibm-logo", "2-ibm-logo.ch8", "");
+}
+
+#[test]
+fn corax_opcodes() {
+    check("3-corax+", "3-corax+.ch8", "");
+}
+
+#[test]
+fn flags() {
+    check("4-flags", "4-flags.ch8", "");
+}
+
+// the keypad test starts with a menu: 1 tests EX9E, 2 EXA1 and 3 FX0A. Each case holds or taps a key
+// once the test is running.
+#[test]
+fn keypad_ex9e() {
+    check("6-keypad-ex9e", "6-keypad.ch8", "60 tap 1\n120 down 5\n");
+}
+
+#[test]
+fn keypad_exa1() {
+    check("6-keypad-exa1", "6-keypad.ch8", "60 tap 2\n120 down 5\n");
+}
+
+#[test]
+fn keypad_fx0a() {
+    check("6-keypad-fx0a", "6-keypad.ch8", "60 tap 3\n120 tap a\n");
+}
+
+#[test]
+fn test_opcode() {
+    check("test_opcode", "test_opcode.ch8", "");
+}
+
+// written for the modern behaviour and shows OK once every test passed, under the other profiles' quirks it
+// stops with error 14 instead
+#[test]
+fn c8_test() {
+    check_profiles("c8_test", "c8_test.ch8", "", &[QuirkProfile::Modern]);
+}
@@ -0,0 +1,185 @@
+// drives the JSON-RPC server from a client over TCP the way a test bot would
+#![allow(clippy::needless_return)]
+
+use chip8_rust::init_program;
+use chip8_rust::server::Server;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+use tempfile::NamedTempFile;
+
+// counts in V1 until key 5 is down, then runs off the end
+const WAIT_FOR_5: [u8; 10] = [0x65, 0x05, 0x71, 0x01, 0xe5, 0x9e, 0x12, 0x02, 0x00, 0xe0];
+
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    id: u64,
+    // notifications that came before the last response
+    notifications: Vec<Value>,
+}
+
+impl Client {
+    // a server of its own on a free port
+    fn start() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut server = Server::bind("127.0.0.1:0", |path| init_program(path).map_err(|e| e.to_string())).unwrap();
+            sender.send(server.address().to_string()).unwrap();
+            let _ = server.serve();
+        });
+        let stream = TcpStream::connect(receiver.recv().unwrap()).unwrap();
+        return Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+            id: 0,
+            notifications: Vec::new(),
+        };
+    }
+
+    fn send(&mut self, line: &str) -> Value {
+        self.writer.write_all(format!("{}\n", line).as_bytes()).unwrap();
+        self.notifications.clear();
+        loop {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+            let message: Value = serde_json::from_str(&line).unwrap();
+            if message.get("id").is_some() {
+                return message;
+            }
+            self.notifications.push(message);
+        }
+    }
+
+    fn call(&mut self, method: &str, params: Value) -> Value {
+        self.id += 1;
+        let request = json!({"jsonrpc": "2.0", "id": self.id, "method": method, "params": params});
+        let response = self.send(&request.to_string());
+        assert_eq!(response["id"], self.id);
+        return response;
+    }
+
+    // the result of a call that has to succeed
+    fn result(&mut self, method: &str, params: Value) -> Value {
+        let response = self.call(method, params);
+        assert!(response.get("error").is_none(), "{}: {}", method, response);
+        return response["result"].clone();
+    }
+
+    fn notified(&self, method: &str) -> Vec<&Value> {
+        return self.notifications.iter().filter(|n| n["method"] == method).map(|n| &n["params"]).collect();
+    }
+}
+
+// a ROM file that is deleted when it is dropped
+fn rom(bytes: &[u8]) -> NamedTempFile {
+    let mut file = NamedTempFile::new().unwrap();
+    file.write_all(bytes).unwrap();
+    return file;
+}
+
+fn error_code(response: &Value) -> i64 {
+    return response["error"]["code"].as_i64().unwrap();
+}
+
+#[test]
+fn steps_a_program_and_streams_what_happened() {
+    let mut client = Client::start();
+    assert_eq!(error_code(&client.call("step", json!({}))), -32000);
+
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test").join("2-ibm-logo.ch8");
+    let loaded = client.result("load", json!({"path": path}));
+    assert_eq!(loaded["size"], 132);
+
+    let step = client.result("step", json!({"frames": 10}));
+    assert_eq!(step["frames"], 10);
+    assert_eq!(step["frame"], 10);
+    assert!(!client.notified("drawn").is_empty());
+
+    let screen = client.result("framebuffer", json!({}));
+    assert_eq!(screen["width"], 64);
+    assert_eq!(screen["rows"].as_array().unwrap().len(), 32);
+    assert!(screen["rows"][8].as_str().unwrap().contains('1'));
+
+    let registers = client.result("registers", json!({}));
+    assert_eq!(registers["pc"], step["pc"]);
+    assert_eq!(registers["v"].as_array().unwrap().len(), 16);
+}
+
+#[test]
+fn keys_memory_and_states() {
+    let mut client = Client::start();
+    let rom = rom(&WAIT_FOR_5);
+    client.result("load", json!({"path": rom.path()}));
+    client.result("step", json!({"frames": 2}));
+    client.result("save_state", json!({"slot": "waiting"}));
+
+    // a tap between two steps is seen, and the program runs off its end
+    client.result("press", json!({"key": 5}));
+    client.result("release", json!({"key": "5"}));
+    let step = client.result("step", json!({"frames": 5}));
+    assert_eq!(step["halted"], true);
+    assert_eq!(client.notified("halted").len(), 1);
+
+    client.result("load_state", json!({"slot": "waiting"}));
+    assert_eq!(client.result("registers", json!({}))["halted"], false);
+
+    client.result("write_memory", json!({"address": 0x300, "data": "c0ffee"}));
+    let memory = client.result("read_memory", json!({"address": 0x2ff, "length": 5}));
+    assert_eq!(memory["data"], "00c0ffee00");
+    let wrapped = client.result("read_memory", json!({"address": 0xfff, "length": 2}));
+    assert_eq!(wrapped["data"].as_str().unwrap().len(), 4);
+
+    client.result("reset", json!({}));
+    assert_eq!(client.result("registers", json!({}))["pc"], 0x200);
+}
+
+#[test]
+fn bad_requests_get_errors() {
+    let mut client = Client::start();
+    assert_eq!(error_code(&client.send("not json")), -32700);
+    assert_eq!(error_code(&client.send(r#"{"id": 1, "method": "step"}"#)), -32600);
+    assert_eq!(error_code(&client.call("fly", json!({}))), -32601);
+    let rom = rom(&WAIT_FOR_5);
+    client.result("load", json!({"path": rom.path()}));
+    assert_eq!(error_code(&client.call("press", json!({"key": 16}))), -32602);
+    assert_eq!(error_code(&client.call("write_memory", json!({"address": 0, "data": "abc"}))), -32602);
+    // a step is at most a minute long
+    assert_eq!(error_code(&client.call("step", json!({"frames": 3601}))), -32602);
+    assert_eq!(client.result("step", json!({"frames": 3600}))["frames"], 3600);
+    assert_eq!(error_code(&client.call("load_state", json!({"slot": "nothing"}))), -32000);
+    assert_eq!(error_code(&client.call("load", json!({"path": "/nonexistent.ch8"}))), -32000);
+}
+
+fn bind(address: &str) -> Result<Server<'static>, String> {
+    return Server::bind(address, |path| init_program(path).map_err(|e| e.to_string()));
+}
+
+#[test]
+fn only_listens_on_loopback() {
+    assert!(bind("0.0.0.0:0").err().unwrap().contains("isn't a loopback address"));
+    // a host name is looked up rather than taken for the name of a socket
+    let server = bind("localhost:0").unwrap();
+    assert!(server.address().starts_with("127.0.0.1:") || server.address().starts_with("[::1]:"));
+    assert!(bind("chip8.sock").is_err());
+    assert!(!PathBuf::from("chip8.sock").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn a_socket_only_replaces_one_nobody_listens_on() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("chip8.sock");
+    let address = format!("unix:{}", path.display());
+    let server = bind(&address).unwrap();
+    assert!(bind(&address).err().unwrap().contains("another server is listening"));
+    drop(server);
+
+    // left behind by a server that didn't shut down cleanly
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+    assert_eq!(bind(&address).unwrap().address(), path.display().to_string());
+}